@@ -0,0 +1,126 @@
+/*
+    Chain source trait used by the TxBuilder to look up previous
+    transactions, broadcast transactions and estimate fees.
+
+    api::Electrum is the default implementation. Other backends such as
+    a local indexer can be used by implementing this trait.
+*/
+use crate::tx::{
+    Tx,
+    Output,
+    FeeRate
+};
+use super::ElectrumErr;
+
+#[derive(Debug)]
+pub enum ChainErr {
+    TxNotFound(String),
+    MissingVout(u32),
+    BadTxData(String),
+    Rejected(RejectReason),
+    FeeUnavailable,
+    Electrum(ElectrumErr)
+}
+
+/**
+    Reasons a node gives for not accepting a broadcast transaction
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    MissingInputs,          //Inputs are unknown or already spent
+    InsufficientFee,        //Fee is below the relay or mempool minimum
+    NonStandard(String),    //Valid but not relayed by policy, holds the reason given
+    AlreadyInChain,
+    AlreadyInMempool,
+    Other(String)           //Any other message from the node
+}
+
+impl RejectReason {
+    /**
+        Match the reject reason in a message from Bitcoin Core or a server relaying it
+    */
+    pub fn from_message(message: &str) -> Self {
+        let lowercase: String = message.to_lowercase();
+        let contains = |patterns: &[&str]| patterns.iter().any(|x| lowercase.contains(x));
+
+        if contains(&["already in block chain", "txn-already-confirmed", "transaction outputs already in utxo set"]) {
+            return RejectReason::AlreadyInChain
+        }
+        if contains(&["txn-already-in-mempool", "txn-already-known"]) {
+            return RejectReason::AlreadyInMempool
+        }
+        if contains(&["missingorspent", "missing-inputs", "missing inputs"]) {
+            return RejectReason::MissingInputs
+        }
+        if contains(&["min relay fee not met", "mempool min fee not met", "insufficient fee", "min-fee-not-met", "fee-not-met"]) {
+            return RejectReason::InsufficientFee
+        }
+
+        //Policy reasons from IsStandardTx and the non-mandatory script checks
+        let policy: [&str; 11] = [
+            "non-mandatory-script-verify-flag", "non-standard", "scriptsig-size", "scriptsig-not-pushonly",
+            "scriptpubkey", "bare-multisig", "dust", "multi-op-return", "tx-size", "non-final", "version"
+        ];
+        for reason in policy.iter() {
+            if lowercase.contains(reason) {
+                return RejectReason::NonStandard(reason.to_string())
+            }
+        }
+
+        RejectReason::Other(message.to_string())
+    }
+}
+
+pub trait ChainSource {
+    /**
+        Gets a transaction given a txid string
+    */
+    fn get_transaction(&self, txid: &str) -> Result<Tx, ChainErr>;
+
+    /**
+        Broadcasts a transaction and returns its txid
+    */
+    fn broadcast(&self, tx: &Tx) -> Result<String, ChainErr>;
+
+    /**
+        Estimate the feerate needed to confirm within the given amount of blocks
+    */
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, ChainErr>;
+
+    /**
+        Gets the output (value and script pubkey) being spent given a txid and output index
+    */
+    fn get_prevout(&self, txid: &str, vout: u32) -> Result<Output, ChainErr> {
+        let tx = self.get_transaction(txid)?;
+
+        match tx.outputs.get(vout as usize) {
+            Some(x) => Ok(x.clone()),
+            None => Err(ChainErr::MissingVout(vout))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_reasons() {
+        //Messages as relayed by ElectrumX and Fulcrum
+        let cases = [
+            ("the transaction was rejected by network rules.\n\nbad-txns-inputs-missingorspent\n[0200...]", RejectReason::MissingInputs),
+            ("missing-inputs", RejectReason::MissingInputs),
+            ("min relay fee not met, 110 < 141", RejectReason::InsufficientFee),
+            ("mempool min fee not met, 1000 < 2000 (code 66)", RejectReason::InsufficientFee),
+            ("insufficient fee, rejecting replacement 3b...; new feerate 0.00001 BTC/kvB <= old feerate 0.00002 BTC/kvB", RejectReason::InsufficientFee),
+            ("dust (code 64)", RejectReason::NonStandard("dust".to_string())),
+            ("non-mandatory-script-verify-flag (Signature must be zero for failed CHECK(MULTI)SIG operation)", RejectReason::NonStandard("non-mandatory-script-verify-flag".to_string())),
+            ("Transaction already in block chain", RejectReason::AlreadyInChain),
+            ("txn-already-in-mempool", RejectReason::AlreadyInMempool),
+            ("bad-txns-vout-negative", RejectReason::Other("bad-txns-vout-negative".to_string()))
+        ];
+        for (message, reason) in cases.iter() {
+            assert_eq!(&RejectReason::from_message(message), reason);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/*
+    Fee estimation through an Electrum server.
+
+    Electrum servers relay bitcoind's estimatesmartfee in BTC/kvB and return -1 when
+    bitcoind does not have enough data. In that case the feerate is estimated from the
+    mempool fee histogram, and if the mempool would be cleared within the target the
+    server's minimum relay fee is used instead. Estimates are never below the relay fee.
+*/
+use std::cmp::max;
+use crate::{
+    ElectrumApi,
+    tx::FeeRate
+};
+use super::{
+    Electrum,
+    ElectrumErr
+};
+
+//Maximum virtual size of a block
+const BLOCK_VSIZE: u64 = 1_000_000;
+
+/**
+    Estimate the feerate needed to confirm within the target amount of blocks from a
+    fee histogram sorted from the highest feerate, assuming each block is filled with
+    the highest paying transactions. Returns None if the whole mempool fits in the target.
+*/
+pub fn feerate_from_histogram(histogram: &Vec<(FeeRate, u64)>, target: usize) -> Option<FeeRate> {
+    let space: u64 = BLOCK_VSIZE * max(target, 1) as u64;
+    let mut vsize: u64 = 0;
+    for (feerate, size) in histogram {
+        vsize += size;
+        if vsize >= space {
+            //Just outbid the transactions that would be left out
+            return Some(FeeRate::from_sat_per_kvb(feerate.sat_per_kvb() + 1))
+        }
+    }
+
+    None
+}
+
+impl Electrum {
+    /**
+        Estimate the feerate needed to confirm within the target amount of blocks.
+        Falls back on the mempool fee histogram and then the minimum relay fee when the
+        server cannot give an estimate.
+    */
+    pub fn estimate_fee(&self, target: usize) -> Result<FeeRate, ElectrumErr> {
+        let relay_fee: Option<FeeRate> = self.relay_fee().ok();
+        let estimate: Option<FeeRate> = match self.client.estimate_fee(target) {
+            Ok(x) if x > 0.0 => Some(FeeRate::from_btc_per_kvb(x)),
+            _ => match self.fee_histogram() {
+                Ok(x) => feerate_from_histogram(&x, target),
+                Err(_) => None
+            }
+        };
+
+        match (estimate, relay_fee) {
+            (Some(x), Some(y)) => Ok(max(x, y)),
+            (Some(x), None) | (None, Some(x)) => Ok(x),
+            (None, None) => Err(ElectrumErr::FeeUnavailable)
+        }
+    }
+
+    /**
+        Minimum feerate for transactions to be relayed by the server's node
+    */
+    pub fn relay_fee(&self) -> Result<FeeRate, ElectrumErr> {
+        match self.client.relay_fee() {
+            Ok(x) if x > 0.0 => Ok(FeeRate::from_btc_per_kvb(x)),
+            _ => Err(ElectrumErr::FeeUnavailable)
+        }
+    }
+
+    /**
+        Gets the mempool fee histogram, the virtual size of transactions paying each feerate
+        or more up to the previous entry, sorted from the highest feerate
+    */
+    pub fn fee_histogram(&self) -> Result<Vec<(FeeRate, u64)>, ElectrumErr> {
+        let response = match self.client.raw_call("mempool.get_fee_histogram", Vec::<electrum_client::Param>::new()) {
+            Ok(x) => x,
+            Err(_) => return Err(ElectrumErr::FailedToGet)
+        };
+
+        //Entries are [feerate in sat/vB, vsize] and the feerate can be fractional
+        let entries = match response.as_array() {
+            Some(x) => x,
+            None => return Err(ElectrumErr::FailedToGet)
+        };
+        let mut histogram: Vec<(FeeRate, u64)> = vec![];
+        for entry in entries {
+            match (entry.get(0).and_then(|x| x.as_f64()), entry.get(1).and_then(|x| x.as_u64())) {
+                (Some(feerate), Some(vsize)) => histogram.push((FeeRate::from_sat_per_kvb((feerate * 1000.0).round() as u64), vsize)),
+                _ => return Err(ElectrumErr::FailedToGet)
+            }
+        }
+        histogram.sort_by_key(|x| std::cmp::Reverse(x.0));
+
+        Ok(histogram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_estimates() {
+        let histogram = vec![
+            (FeeRate::from_sat_per_vb(50), 400_000),
+            (FeeRate::from_sat_per_vb(20), 700_000),
+            (FeeRate::from_sat_per_kvb(5500), 1_200_000),
+            (FeeRate::from_sat_per_vb(1), 300_000)
+        ];
+
+        //The next block fills up in the 20 sat/vB bucket, two blocks in the 5.5 sat/vB bucket
+        assert_eq!(feerate_from_histogram(&histogram, 1), Some(FeeRate::from_sat_per_kvb(20001)));
+        assert_eq!(feerate_from_histogram(&histogram, 0), Some(FeeRate::from_sat_per_kvb(20001)));
+        assert_eq!(feerate_from_histogram(&histogram, 2), Some(FeeRate::from_sat_per_kvb(5501)));
+        assert_eq!(feerate_from_histogram(&histogram, 3), None);
+        assert_eq!(feerate_from_histogram(&vec![], 1), None);
+    }
+}
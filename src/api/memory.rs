@@ -0,0 +1,99 @@
+/*
+    In memory chain source.
+
+    Holds transactions and prevouts in memory so that transactions can be
+    built and signed without a network connection, mainly for testing.
+*/
+use std::{
+    cell::RefCell,
+    collections::HashMap
+};
+use crate::tx::{
+    Tx,
+    Output,
+    FeeRate
+};
+use super::{
+    ChainSource,
+    ChainErr
+};
+
+#[derive(Debug)]
+pub struct MemoryChain {
+    txs: HashMap<String, Tx>,
+    prevouts: HashMap<(String, u32), Output>,
+    broadcasted: RefCell<Vec<Tx>>,
+    feerate: Option<FeeRate>
+}
+
+impl MemoryChain {
+    pub fn new() -> Self {
+        Self {
+            txs: HashMap::new(),
+            prevouts: HashMap::new(),
+            broadcasted: RefCell::new(vec![]),
+            feerate: None
+        }
+    }
+
+    /**
+        Store a transaction so its outputs can be looked up by txid
+    */
+    pub fn insert_tx(&mut self, tx: Tx) {
+        self.txs.insert(tx.get_txid(), tx);
+    }
+
+    /**
+        Store a single output without the rest of the transaction creating it
+    */
+    pub fn insert_prevout(&mut self, txid: &str, vout: u32, output: Output) {
+        self.prevouts.insert((txid.to_string(), vout), output);
+    }
+
+    /**
+        Set the feerate returned for all fee estimates
+    */
+    pub fn set_feerate(&mut self, feerate: FeeRate) {
+        self.feerate = Some(feerate);
+    }
+
+    /**
+        Returns the transactions that have been broadcast through this source
+    */
+    pub fn broadcasted(&self) -> Vec<Tx> {
+        self.broadcasted.borrow().clone()
+    }
+}
+
+impl ChainSource for MemoryChain {
+    fn get_transaction(&self, txid: &str) -> Result<Tx, ChainErr> {
+        match self.txs.get(txid) {
+            Some(x) => Ok(x.clone()),
+            None => Err(ChainErr::TxNotFound(txid.to_string()))
+        }
+    }
+
+    fn get_prevout(&self, txid: &str, vout: u32) -> Result<Output, ChainErr> {
+        if let Some(x) = self.prevouts.get(&(txid.to_string(), vout)) {
+            return Ok(x.clone())
+        }
+
+        let tx = self.get_transaction(txid)?;
+        match tx.outputs.get(vout as usize) {
+            Some(x) => Ok(x.clone()),
+            None => Err(ChainErr::MissingVout(vout))
+        }
+    }
+
+    fn broadcast(&self, tx: &Tx) -> Result<String, ChainErr> {
+        self.broadcasted.borrow_mut().push(tx.clone());
+        Ok(tx.get_txid())
+    }
+
+    fn estimate_fee(&self, _target: usize) -> Result<FeeRate, ChainErr> {
+        match self.feerate {
+            Some(x) => Ok(x),
+            None => Err(ChainErr::FeeUnavailable)
+        }
+    }
+}
@@ -9,6 +9,29 @@
             > Figure out how to install and run a TOR socks proxy locally wihout the browser.
 */
 
+mod chain;
+mod memory;
+mod scripthash;
+mod subscribe;
+mod fees;
+
+pub use chain::{
+    ChainSource,
+    ChainErr,
+    RejectReason
+};
+pub use memory::MemoryChain;
+pub use scripthash::{
+    script_hash,
+    Unspent,
+    HistoryEntry,
+    Balance
+};
+pub use subscribe::Notification;
+pub use fees::feerate_from_histogram;
+use subscribe::Subscriptions;
+
+use std::fmt;
 use bitcoin_hashes::hex::FromHex;
 use crate::{
     Client, 
@@ -17,11 +40,30 @@ use crate::{
     Socks5Config,
     util::bytes::decode_02x,
     util::bytes::encode_02x,
-    util::Network
+    util::Network,
+    util::serialize::{
+        Serialize,
+        Deserialize
+    },
+    tx::{
+        Output,
+        Script,
+        Tx,
+        FeeRate
+    }
 };
 
 pub struct Electrum {
-    client: Client
+    client: Client,
+    url: Option<String>,                //Kept to reconnect to the same server
+    network: Network,
+    subscriptions: Subscriptions        //Scripts and headers to subscribe to again after reconnecting
+}
+
+impl fmt::Debug for Electrum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Electrum").finish()
+    }
 }
 
 #[derive(Debug)]
@@ -31,6 +73,12 @@ pub enum ElectrumErr {
     FailedToGet,
     MissingVout(u32),
     NetworkMismatch,
+    FailedToSerialize,
+    FailedToBroadcast,
+    FailedToSubscribe,
+    FeeUnavailable,
+    Rejected(RejectReason),
+    TxidMismatch(String, String)    //Txid of the transaction and txid returned by the server
 }
 
 /**
@@ -97,7 +145,12 @@ impl Electrum {
         };
 
         //Check that the client is connected to the right network.
-        let client = Self { client };
+        let client = Self {
+            client,
+            url: url.clone(),
+            network: network.clone(),
+            subscriptions: Subscriptions::default()
+        };
         let detected_network = client.server_network()?;
         if network == &detected_network { return Ok(client) }
         else { return Err(ElectrumErr::NetworkMismatch) }
@@ -160,6 +213,97 @@ impl Electrum {
 
         Ok(tx.output[vout].value.clone())
     }
+
+    /**
+        Gets the output (value and script pubkey) being spent given a txid and output index
+    */
+    pub fn get_prevout(&self, txid: &str, vout: usize) -> Result<Output, ElectrumErr> {
+        let tx = self.get_tx(txid)?;
+
+        if vout >= tx.output.len() {
+            return Err(ElectrumErr::MissingVout(vout as u32))
+        }
+
+        Ok(Output {
+            value: tx.output[vout].value,
+            script_pub_key: Script::new(tx.output[vout].script_pubkey.clone().into_bytes())
+        })
+    }
+
+    /**
+        Broadcasts a transaction and returns its txid.
+        Errors with the reason given by the server if the transaction is rejected, or if the
+        server returns a txid that is not the txid of the transaction.
+    */
+    pub fn broadcast(&self, tx: &Tx) -> Result<String, ElectrumErr> {
+        let raw = match tx.serialize() {
+            Ok(x) => x,
+            Err(_) => return Err(ElectrumErr::FailedToSerialize)
+        };
+
+        let txid: String = match self.client.transaction_broadcast_raw(&raw) {
+            Ok(x) => x.to_string(),
+            Err(electrum_client::Error::Protocol(x)) => {
+                //JSON-RPC errors carry the node's reject message in the message field
+                let message: String = match x.get("message").and_then(|m| m.as_str()) {
+                    Some(m) => m.to_string(),
+                    None => x.to_string()
+                };
+                return Err(ElectrumErr::Rejected(RejectReason::from_message(&message)))
+            },
+            Err(electrum_client::Error::Message(x)) => return Err(ElectrumErr::Rejected(RejectReason::from_message(&x))),
+            Err(_) => return Err(ElectrumErr::FailedToBroadcast)
+        };
+
+        let expected: String = tx.get_txid();
+        if txid != expected {
+            return Err(ElectrumErr::TxidMismatch(expected, txid))
+        }
+
+        Ok(txid)
+    }
+}
+
+impl ChainSource for Electrum {
+    fn get_transaction(&self, txid: &str) -> Result<Tx, ChainErr> {
+        let txid_hash = match electrum_client::bitcoin::hashes::sha256d::Hash::from_hex(txid) {
+            Ok(x) => electrum_client::bitcoin::Txid::from_hash(x),
+            Err(_) => return Err(ChainErr::TxNotFound(txid.to_string()))
+        };
+        let raw = match self.client.transaction_get_raw(&txid_hash) {
+            Ok(x) => x,
+            Err(_) => return Err(ChainErr::Electrum(ElectrumErr::FailedToGet))
+        };
+
+        match Tx::from_bytes(&raw) {
+            Ok(x) => Ok(x),
+            Err(_) => Err(ChainErr::BadTxData(txid.to_string()))
+        }
+    }
+
+    fn get_prevout(&self, txid: &str, vout: u32) -> Result<Output, ChainErr> {
+        match Electrum::get_prevout(self, txid, vout as usize) {
+            Ok(x) => Ok(x),
+            Err(ElectrumErr::MissingVout(x)) => Err(ChainErr::MissingVout(x)),
+            Err(x) => Err(ChainErr::Electrum(x))
+        }
+    }
+
+    fn broadcast(&self, tx: &Tx) -> Result<String, ChainErr> {
+        match Electrum::broadcast(self, tx) {
+            Ok(x) => Ok(x),
+            Err(ElectrumErr::FailedToSerialize) => Err(ChainErr::BadTxData(tx.get_txid())),
+            Err(ElectrumErr::Rejected(x)) => Err(ChainErr::Rejected(x)),
+            Err(x) => Err(ChainErr::Electrum(x))
+        }
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, ChainErr> {
+        match Electrum::estimate_fee(self, target) {
+            Ok(x) => Ok(x),
+            Err(_) => Err(ChainErr::FeeUnavailable)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,188 @@
+/*
+    Wallet lookups by scripthash through an Electrum server.
+
+    Electrum indexes outputs by the SHA256 of their scriptPubKey, shown in reverse
+    byte order like txids. Addresses are looked up through their scriptPubKey.
+*/
+use crate::{
+    ElectrumApi,
+    hash,
+    util::bytes,
+    tx::{
+        Output,
+        Script,
+        Address,
+        Utxo,
+        SigningData,
+        BuilderErr
+    }
+};
+use super::{
+    Electrum,
+    ElectrumErr
+};
+
+/**
+    Unspent output paying a script along with the output itself
+*/
+#[derive(Debug, Clone)]
+pub struct Unspent {
+    pub txid: String,
+    pub vout: u32,
+    pub height: Option<u32>,    //Block height of the transaction, None if it is in the mempool
+    pub output: Output
+}
+
+/**
+    Transaction that pays to or spends from a script
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub txid: String,
+    pub height: Option<u32>,    //Block height of the transaction, None if it is in the mempool
+    pub fee: Option<u64>        //Only given by the server for mempool transactions
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balance {
+    pub confirmed: u64,
+    pub unconfirmed: i64        //Negative when mempool transactions spend confirmed outputs
+}
+
+impl Unspent {
+    /**
+        Turn into a UTXO for coin selection, weighing the input with the signing data
+    */
+    pub fn to_utxo(&self, signing_data: &SigningData) -> Result<Utxo, BuilderErr> {
+        Utxo::new(&self.txid, self.vout, self.output.clone(), signing_data)
+    }
+}
+
+/**
+    Electrum scripthash of a scriptPubKey
+*/
+pub fn script_hash(script: &Script) -> String {
+    bytes::encode_02x(&bytes::reverse(&hash::sha256(script.code.clone()).to_vec()))
+}
+
+/**
+    Convert the crate's script into the one used by the Electrum client
+*/
+pub(super) fn client_script(script: &Script) -> electrum_client::bitcoin::Script {
+    electrum_client::bitcoin::Script::from(script.code.clone())
+}
+
+impl Electrum {
+    /**
+        Lists the unspent outputs paying to a scriptPubKey, including ones in the mempool
+    */
+    pub fn list_unspent(&self, script: &Script) -> Result<Vec<Unspent>, ElectrumErr> {
+        let unspent = match self.client.script_list_unspent(&client_script(script)) {
+            Ok(x) => x,
+            Err(_) => return Err(ElectrumErr::FailedToGet)
+        };
+
+        Ok(unspent.iter().map(|x| Unspent {
+            txid: x.tx_hash.to_string(),
+            vout: x.tx_pos as u32,
+            height: if x.height > 0 { Some(x.height as u32) } else { None },
+            output: Output::from_script(script.clone(), x.value)
+        }).collect())
+    }
+
+    /**
+        Lists the transactions paying to or spending from a scriptPubKey
+    */
+    pub fn get_history(&self, script: &Script) -> Result<Vec<HistoryEntry>, ElectrumErr> {
+        let history = match self.client.script_get_history(&client_script(script)) {
+            Ok(x) => x,
+            Err(_) => return Err(ElectrumErr::FailedToGet)
+        };
+
+        //Mempool transactions have a height of 0, or -1 if they spend unconfirmed outputs
+        Ok(history.iter().map(|x| HistoryEntry {
+            txid: x.tx_hash.to_string(),
+            height: if x.height > 0 { Some(x.height as u32) } else { None },
+            fee: x.fee
+        }).collect())
+    }
+
+    /**
+        Gets the confirmed and unconfirmed balance of a scriptPubKey
+    */
+    pub fn get_balance(&self, script: &Script) -> Result<Balance, ElectrumErr> {
+        match self.client.script_get_balance(&client_script(script)) {
+            Ok(x) => Ok(Balance {
+                confirmed: x.confirmed,
+                unconfirmed: x.unconfirmed
+            }),
+            Err(_) => Err(ElectrumErr::FailedToGet)
+        }
+    }
+
+    /**
+        Lists the unspent outputs paying to an address
+    */
+    pub fn list_unspent_address(&self, address: &Address) -> Result<Vec<Unspent>, ElectrumErr> {
+        self.list_unspent(&address.script_pub_key())
+    }
+
+    /**
+        Lists the transactions paying to or spending from an address
+    */
+    pub fn get_history_address(&self, address: &Address) -> Result<Vec<HistoryEntry>, ElectrumErr> {
+        self.get_history(&address.script_pub_key())
+    }
+
+    /**
+        Gets the confirmed and unconfirmed balance of an address
+    */
+    pub fn get_balance_address(&self, address: &Address) -> Result<Balance, ElectrumErr> {
+        self.get_balance(&address.script_pub_key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        util::Network,
+        tx::{
+            TxBuilder,
+            SigHash
+        },
+        test_utils::{ PREV_TXID, key, p2wpkh_output }
+    };
+
+    #[test]
+    fn electrum_script_hash() {
+        //Example from the Electrum protocol documentation
+        let address = Address::parse("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", &Network::Bitcoin).unwrap();
+        assert_eq!(bytes::encode_02x(&address.script_pub_key().code), "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac");
+        assert_eq!(script_hash(&address.script_pub_key()), "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161");
+    }
+
+    #[test]
+    fn unspent_into_builder() {
+        let key = key(14);
+        let signing_data = SigningData::new(vec![key], None);
+        let unspent = Unspent {
+            txid: PREV_TXID.to_string(),
+            vout: 1,
+            height: Some(2100000),
+            output: p2wpkh_output(&key, 20000)
+        };
+
+        //The prevout comes with the unspent output so signing needs no lookups
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_unspent(&unspent).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 19000).unwrap();
+        txb.sign_input(0, &signing_data, SigHash::ALL).unwrap();
+        let tx = txb.build().unwrap();
+        assert_eq!(tx.inputs[0].vout, 1);
+        assert_eq!(tx.fee(&vec![unspent.output.clone()]).unwrap(), 1000);
+
+        let utxo = unspent.to_utxo(&signing_data).unwrap();
+        assert_eq!(utxo.output.value, 20000);
+    }
+}
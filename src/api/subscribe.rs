@@ -0,0 +1,283 @@
+/*
+    Scripthash and block header subscriptions through an Electrum server.
+
+    The server pushes a new status whenever the history of a subscribed script changes
+    and a new header whenever the chain tip changes. Notifications are queued by the
+    client and collected by polling.
+
+    Subscriptions only last as long as the connection. The scripts and headers that have
+    been subscribed to are remembered so they can be subscribed to again after the
+    connection drops, and any change missed while disconnected is reported then.
+*/
+use std::{
+    thread,
+    time::Duration,
+    sync::mpsc::Sender
+};
+use crate::{
+    ElectrumApi,
+    util::bytes::encode_02x,
+    tx::Script
+};
+use super::{
+    Electrum,
+    ElectrumErr,
+    scripthash::client_script
+};
+
+#[derive(Debug, Clone)]
+pub enum Notification {
+    ScriptStatus(Script, Option<String>),   //Script and its new status, None if it has no history
+    Header(u32, Vec<u8>)                    //Height and serialized header of the new tip
+}
+
+/**
+    Subscriptions and the last status or tip seen for each
+*/
+#[derive(Debug, Clone, Default)]
+pub(super) struct Subscriptions {
+    scripts: Vec<(Script, Option<String>)>,
+    headers: bool,
+    tip: Option<(u32, Vec<u8>)>     //Height and serialized header of the last tip seen
+}
+
+impl Subscriptions {
+    /**
+        Record the status of a script, returning a notification if it changed
+    */
+    fn script_status(&mut self, script: &Script, status: Option<String>) -> Option<Notification> {
+        match self.scripts.iter_mut().find(|(x, _)| x.code == script.code) {
+            Some((_, last)) if *last == status => None,
+            Some((_, last)) => {
+                *last = status.clone();
+                Some(Notification::ScriptStatus(script.clone(), status))
+            },
+            None => {
+                self.scripts.push((script.clone(), status.clone()));
+                Some(Notification::ScriptStatus(script.clone(), status))
+            }
+        }
+    }
+
+    /**
+        Record the chain tip, returning a notification if it changed.
+        A different header at the same height is a reorg and is reported.
+    */
+    fn header(&mut self, height: u32, header: Vec<u8>) -> Option<Notification> {
+        if let Some((last_height, last_header)) = &self.tip {
+            if *last_height == height && *last_header == header {
+                return None
+            }
+        }
+        self.tip = Some((height, header.clone()));
+
+        Some(Notification::Header(height, header))
+    }
+
+    fn remove_script(&mut self, script: &Script) {
+        self.scripts.retain(|(x, _)| x.code != script.code);
+    }
+
+    fn scripts(&self) -> Vec<Script> {
+        self.scripts.iter().map(|(x, _)| x.clone()).collect()
+    }
+}
+
+/**
+    Hex encode a script status
+*/
+fn status_hex(status: Option<electrum_client::ScriptStatus>) -> Option<String> {
+    status.map(|x| encode_02x(&x[..]))
+}
+
+impl Electrum {
+    /**
+        Subscribe to changes in the history of a scriptPubKey and return its current status.
+        The status is None if the script has no history.
+    */
+    pub fn subscribe_script(&mut self, script: &Script) -> Result<Option<String>, ElectrumErr> {
+        let status: Option<String> = match self.client.script_subscribe(&client_script(script)) {
+            Ok(x) => status_hex(x),
+            Err(electrum_client::Error::AlreadySubscribed(_)) => return Ok(self.script_status(script)),
+            Err(_) => return Err(ElectrumErr::FailedToSubscribe)
+        };
+        self.subscriptions.script_status(script, status.clone());
+
+        Ok(status)
+    }
+
+    /**
+        Stop receiving changes in the history of a scriptPubKey
+    */
+    pub fn unsubscribe_script(&mut self, script: &Script) -> Result<(), ElectrumErr> {
+        self.subscriptions.remove_script(script);
+        match self.client.script_unsubscribe(&client_script(script)) {
+            Ok(_) | Err(electrum_client::Error::NotSubscribed(_)) => Ok(()),
+            Err(_) => Err(ElectrumErr::FailedToSubscribe)
+        }
+    }
+
+    /**
+        Subscribe to new block headers and return the height and serialized header of the tip
+    */
+    pub fn subscribe_headers(&mut self) -> Result<(u32, Vec<u8>), ElectrumErr> {
+        let tip = match self.client.block_headers_subscribe() {
+            Ok(x) => x,
+            Err(_) => return Err(ElectrumErr::FailedToSubscribe)
+        };
+        let header: Vec<u8> = electrum_client::bitcoin::consensus::encode::serialize(&tip.header);
+        self.subscriptions.headers = true;
+        self.subscriptions.header(tip.height as u32, header.clone());
+
+        Ok((tip.height as u32, header))
+    }
+
+    /**
+        Collect the notifications received since the last poll.
+        If the connection has dropped, reconnect and subscribe again to everything,
+        returning a notification for each change missed while disconnected.
+    */
+    pub fn poll(&mut self) -> Result<Vec<Notification>, ElectrumErr> {
+        if self.client.ping().is_err() {
+            return self.reconnect()
+        }
+
+        let mut notifications: Vec<Notification> = vec![];
+        let mut resubscribe: bool = false;
+        if self.subscriptions.headers {
+            loop {
+                match self.client.block_headers_pop() {
+                    Ok(Some(x)) => {
+                        let header: Vec<u8> = electrum_client::bitcoin::consensus::encode::serialize(&x.header);
+                        notifications.extend(self.subscriptions.header(x.height as u32, header));
+                    },
+                    Ok(None) => break,
+                    Err(_) => { resubscribe = true; break }
+                }
+            }
+        }
+        for script in self.subscriptions.scripts() {
+            loop {
+                //Errors if the client reconnected by itself and dropped the subscription
+                match self.client.script_pop(&client_script(&script)) {
+                    Ok(Some(x)) => notifications.extend(self.subscriptions.script_status(&script, status_hex(Some(x)))),
+                    Ok(None) => break,
+                    Err(_) => { resubscribe = true; break }
+                }
+            }
+        }
+
+        if resubscribe {
+            notifications.append(&mut self.resubscribe()?);
+        }
+
+        Ok(notifications)
+    }
+
+    /**
+        Poll for notifications at the given interval and send them through the channel.
+        Keeps trying to reconnect while the server cannot be reached and returns when a
+        notification cannot be sent because the receiving end of the channel was dropped.
+    */
+    pub fn listen(&mut self, sender: &Sender<Notification>, interval: Duration) -> Result<(), ElectrumErr> {
+        loop {
+            match self.poll() {
+                Ok(notifications) => for notification in notifications {
+                    if sender.send(notification).is_err() {
+                        return Ok(())
+                    }
+                },
+                Err(ElectrumErr::FailedToConnect) | Err(ElectrumErr::FailedToSubscribe) => { /* Try again next interval */ },
+                Err(x) => return Err(x)
+            }
+
+            thread::sleep(interval);
+        }
+    }
+
+    /**
+        Replace the client with a new connection to the same server and subscribe again
+    */
+    fn reconnect(&mut self) -> Result<Vec<Notification>, ElectrumErr> {
+        let fresh: Electrum = Electrum::new(&self.url, &self.network)?;
+        self.client = fresh.client;
+
+        self.resubscribe()
+    }
+
+    /**
+        Subscribe again to all scripts and headers, returning the changes since they were last seen
+    */
+    fn resubscribe(&mut self) -> Result<Vec<Notification>, ElectrumErr> {
+        let mut notifications: Vec<Notification> = vec![];
+        for script in self.subscriptions.scripts() {
+            let status: Option<String> = match self.client.script_subscribe(&client_script(&script)) {
+                Ok(x) => status_hex(x),
+                Err(electrum_client::Error::AlreadySubscribed(_)) => continue,
+                Err(_) => return Err(ElectrumErr::FailedToSubscribe)
+            };
+            notifications.extend(self.subscriptions.script_status(&script, status));
+        }
+
+        if self.subscriptions.headers {
+            let tip = match self.client.block_headers_subscribe() {
+                Ok(x) => x,
+                Err(_) => return Err(ElectrumErr::FailedToSubscribe)
+            };
+            let header: Vec<u8> = electrum_client::bitcoin::consensus::encode::serialize(&tip.header);
+            notifications.extend(self.subscriptions.header(tip.height as u32, header));
+        }
+
+        Ok(notifications)
+    }
+
+    /**
+        Last status seen for a subscribed script
+    */
+    fn script_status(&self, script: &Script) -> Option<String> {
+        match self.subscriptions.scripts.iter().find(|(x, _)| x.code == script.code) {
+            Some((_, status)) => status.clone(),
+            None => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscription_changes() {
+        let mut subscriptions = Subscriptions::default();
+        let script = Script::new(vec![0x00, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14]);
+        let status = Some("c2a4d8e5f3b1".to_string());
+
+        //Only changes in status are notified, like a status reported again after reconnecting
+        let changed = |notification: Option<Notification>, expected: &Option<String>| match notification {
+            Some(Notification::ScriptStatus(x, y)) => x.code == script.code && &y == expected,
+            _ => false
+        };
+        assert!(changed(subscriptions.script_status(&script, None), &None));
+        assert!(subscriptions.script_status(&script, None).is_none());
+        assert!(changed(subscriptions.script_status(&script, status.clone()), &status));
+        assert!(subscriptions.script_status(&script, status.clone()).is_none());
+        assert_eq!(subscriptions.scripts().len(), 1);
+
+        assert!(matches!(subscriptions.header(800000, vec![0; 80]), Some(Notification::Header(800000, _))));
+        assert!(subscriptions.header(800000, vec![0; 80]).is_none());
+        match subscriptions.header(800001, vec![1; 80]) {
+            Some(Notification::Header(800001, x)) => assert_eq!(x, vec![1; 80]),
+            x => panic!("{:?}", x)
+        }
+
+        //A new header at the same height is a reorg
+        match subscriptions.header(800001, vec![2; 80]) {
+            Some(Notification::Header(800001, x)) => assert_eq!(x, vec![2; 80]),
+            x => panic!("{:?}", x)
+        }
+        assert!(subscriptions.header(800001, vec![2; 80]).is_none());
+
+        subscriptions.remove_script(&script);
+        assert!(subscriptions.scripts().is_empty());
+    }
+}
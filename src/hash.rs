@@ -2,6 +2,7 @@ use crate::{
     Digest, Sha256,
     util::bytes
 };
+use bitcoin_hashes::Hash;
 
 /**
     Takes in a byte array and returns the sha256 hash of it as a byte array of length 32
@@ -23,4 +24,47 @@ where T: AsRef<[u8]>
 {
     let round1 = sha256(input);
     sha256(round1)
-}
\ No newline at end of file
+}
+
+/**
+    BIP-340 tagged hash.
+    SHA256(SHA256(tag) || SHA256(tag) || input)
+*/
+pub fn tagged_hash<T>(tag: &str, input: T) -> [u8; 32]
+where T: AsRef<[u8]>
+{
+    let tag_hash = sha256(tag.as_bytes());
+    let mut r = Sha256::new();
+    r.update(tag_hash);
+    r.update(tag_hash);
+    r.update(input);
+    bytes::try_into(r.finalize().to_vec())
+}
+
+
+/**
+    RIPEMD160 of the SHA256 of the input data
+*/
+pub fn hash160<T>(input: T) -> [u8; 20]
+where T: AsRef<[u8]>
+{
+    bitcoin_hashes::hash160::Hash::hash(input.as_ref()).into_inner()
+}
+
+/**
+    RIPEMD160 of the input data
+*/
+pub fn ripemd160<T>(input: T) -> [u8; 20]
+where T: AsRef<[u8]>
+{
+    bitcoin_hashes::ripemd160::Hash::hash(input.as_ref()).into_inner()
+}
+
+/**
+    SHA1 of the input data
+*/
+pub fn sha1<T>(input: T) -> [u8; 20]
+where T: AsRef<[u8]>
+{
+    bitcoin_hashes::sha1::Hash::hash(input.as_ref()).into_inner()
+}
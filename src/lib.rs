@@ -13,7 +13,6 @@
         - Transaction Builder (See bitcoinjs-lib TransactionBuilder)              
                - General cleanup and refactoring (Looking for a BIG cleanup and redesign of the library)
                - Unit tests for internal methods
-               - Signing Taproot inputs (Control blocks, annex and etc...)
         
         - Documentation
 */
@@ -24,7 +23,10 @@ pub mod tx;
 pub mod util;
 pub mod api;
 pub mod hash;
+pub mod psbt;
 pub mod prelude;
+#[cfg(test)]
+mod test_utils;
 
 //Dependencies
 use btc_keyaddress::prelude::*;
@@ -36,7 +38,8 @@ pub use secp256k1::{
     SecretKey,
     Signature,
     Message,
-    SerializedSignature
+    SerializedSignature,
+    schnorrsig
 };
 use electrum_client::{
     Client,
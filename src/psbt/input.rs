@@ -0,0 +1,374 @@
+/*
+    Per-input map of a PSBT
+*/
+use std::collections::BTreeMap;
+use crate::{
+    schnorrsig,
+    tx::{
+        Tx,
+        Output,
+        Script,
+        Witness
+    },
+    util::serialize::{
+        Serialize,
+        Deserialize
+    }
+};
+use super::{
+    PsbtErr,
+    write_pair,
+    write_unknown,
+    read_u32_value,
+    LOCKTIME_THRESHOLD
+};
+
+//Input key types
+pub const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+pub const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+pub const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+pub const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+pub const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+pub const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+pub const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+pub const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+pub const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+pub const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+pub const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+pub const PSBT_IN_SEQUENCE: u8 = 0x10;
+pub const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+pub const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+pub const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+pub const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+pub const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+pub const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+pub const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+pub const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+
+#[derive(Debug, Clone)]
+pub struct PsbtInput {
+    pub previous_txid: [u8; 32],                            //TXID of the output being spent (big endian, like Input.txid)
+    pub vout: u32,                                          //Index of the output being spent
+    pub sequence: u32,
+    pub non_witness_utxo: Option<Tx>,                       //Full transaction containing the output being spent
+    pub witness_utxo: Option<Output>,                       //Output being spent
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,           //Public key -> signature with sighash byte
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, Vec<u8>>,       //Public key -> fingerprint and derivation path
+    pub final_script_sig: Option<Script>,
+    pub final_script_witness: Option<Witness>,
+    pub required_time_locktime: Option<u32>,               //v2 only. Minimum locktime as a timestamp needed to spend the input
+    pub required_height_locktime: Option<u32>,             //v2 only. Minimum locktime as a block height needed to spend the input
+    pub tap_key_sig: Option<Vec<u8>>,                       //Schnorr signature for the key path
+    pub tap_script_sigs: BTreeMap<Vec<u8>, Vec<u8>>,        //X-only key and leaf hash -> Schnorr signature
+    pub tap_leaf_scripts: BTreeMap<Vec<u8>, (Script, u8)>,  //Control block -> leaf script and leaf version
+    pub tap_bip32_derivation: BTreeMap<Vec<u8>, Vec<u8>>,   //X-only key -> leaf hashes, fingerprint and derivation path
+    pub tap_internal_key: Option<schnorrsig::PublicKey>,
+    pub tap_merkle_root: Option<[u8; 32]>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>                 //Unknown and proprietary key-value pairs
+}
+
+impl PsbtInput {
+    pub fn new(previous_txid: [u8; 32], vout: u32, sequence: u32) -> Self {
+        Self {
+            previous_txid,
+            vout,
+            sequence,
+            non_witness_utxo: None,
+            witness_utxo: None,
+            partial_sigs: BTreeMap::new(),
+            sighash_type: None,
+            redeem_script: None,
+            witness_script: None,
+            bip32_derivation: BTreeMap::new(),
+            final_script_sig: None,
+            final_script_witness: None,
+            required_time_locktime: None,
+            required_height_locktime: None,
+            tap_key_sig: None,
+            tap_script_sigs: BTreeMap::new(),
+            tap_leaf_scripts: BTreeMap::new(),
+            tap_bip32_derivation: BTreeMap::new(),
+            tap_internal_key: None,
+            tap_merkle_root: None,
+            unknown: BTreeMap::new()
+        }
+    }
+
+    /**
+        Returns true if the input has a final scriptSig or witness
+    */
+    pub fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    /**
+        Returns true if the input has any signatures
+    */
+    pub fn has_signatures(&self) -> bool {
+        !self.partial_sigs.is_empty() || self.tap_key_sig.is_some() || !self.tap_script_sigs.is_empty() || self.is_finalized()
+    }
+
+    /**
+        Remove everything apart from the UTXOs, final fields and unknown pairs.
+        Called once an input has been finalized.
+    */
+    pub(crate) fn clear_signing_fields(&mut self) {
+        self.partial_sigs.clear();
+        self.sighash_type = None;
+        self.redeem_script = None;
+        self.witness_script = None;
+        self.bip32_derivation.clear();
+        self.tap_key_sig = None;
+        self.tap_script_sigs.clear();
+        self.tap_leaf_scripts.clear();
+        self.tap_bip32_derivation.clear();
+        self.tap_internal_key = None;
+        self.tap_merkle_root = None;
+    }
+
+    /**
+        Add any data from another map of the same input that this one is missing
+    */
+    pub(crate) fn combine(&mut self, other: &PsbtInput) {
+        if self.non_witness_utxo.is_none() { self.non_witness_utxo = other.non_witness_utxo.clone() }
+        if self.witness_utxo.is_none() { self.witness_utxo = other.witness_utxo.clone() }
+        if self.sighash_type.is_none() { self.sighash_type = other.sighash_type }
+        if self.redeem_script.is_none() { self.redeem_script = other.redeem_script.clone() }
+        if self.witness_script.is_none() { self.witness_script = other.witness_script.clone() }
+        if self.final_script_sig.is_none() { self.final_script_sig = other.final_script_sig.clone() }
+        if self.final_script_witness.is_none() { self.final_script_witness = other.final_script_witness.clone() }
+        if self.tap_key_sig.is_none() { self.tap_key_sig = other.tap_key_sig.clone() }
+        if self.tap_internal_key.is_none() { self.tap_internal_key = other.tap_internal_key }
+        if self.tap_merkle_root.is_none() { self.tap_merkle_root = other.tap_merkle_root }
+        if self.required_time_locktime.is_none() { self.required_time_locktime = other.required_time_locktime }
+        if self.required_height_locktime.is_none() { self.required_height_locktime = other.required_height_locktime }
+
+        merge(&mut self.partial_sigs, &other.partial_sigs);
+        merge(&mut self.bip32_derivation, &other.bip32_derivation);
+        merge(&mut self.tap_script_sigs, &other.tap_script_sigs);
+        merge(&mut self.tap_leaf_scripts, &other.tap_leaf_scripts);
+        merge(&mut self.tap_bip32_derivation, &other.tap_bip32_derivation);
+        merge(&mut self.unknown, &other.unknown);
+    }
+
+    /**
+        Set the fields of the input from the key-value pairs of its map.
+        Version 2 inputs must have the outpoint being spent in their map.
+    */
+    pub(crate) fn read_pairs(&mut self, pairs: Vec<(Vec<u8>, Vec<u8>)>, version: u32) -> Result<(), PsbtErr> {
+        if version == 2 {
+            for key_type in [PSBT_IN_PREVIOUS_TXID, PSBT_IN_OUTPUT_INDEX].iter() {
+                if !pairs.iter().any(|(k, _)| k == &vec![*key_type]) {
+                    return Err(PsbtErr::MissingField(*key_type))
+                }
+            }
+        }
+
+        for (key, value) in pairs {
+            let key_type = key[0];
+            let key_data = &key[1..];
+
+            //Transaction fields are only stored in the input map in version 2
+            if version != 2 && (PSBT_IN_PREVIOUS_TXID..=PSBT_IN_REQUIRED_HEIGHT_LOCKTIME).contains(&key_type) {
+                return Err(PsbtErr::FieldNotAllowed(key_type))
+            }
+
+            match key_type {
+                PSBT_IN_NON_WITNESS_UTXO => {
+                    no_key_data(&key)?;
+                    self.non_witness_utxo = match Tx::from_bytes(&value) {
+                        Ok(x) => Some(x),
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                },
+                PSBT_IN_WITNESS_UTXO => {
+                    no_key_data(&key)?;
+                    self.witness_utxo = match Output::from_bytes(&value) {
+                        Ok(x) => Some(x),
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                },
+                PSBT_IN_PARTIAL_SIG => {
+                    if key_data.len() != 33 && key_data.len() != 65 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    self.partial_sigs.insert(key_data.to_vec(), value);
+                },
+                PSBT_IN_SIGHASH_TYPE => {
+                    no_key_data(&key)?;
+                    if value.len() != 4 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    self.sighash_type = Some(super::read_u32(&value));
+                },
+                PSBT_IN_REDEEM_SCRIPT => {
+                    no_key_data(&key)?;
+                    self.redeem_script = Some(Script::new(value));
+                },
+                PSBT_IN_WITNESS_SCRIPT => {
+                    no_key_data(&key)?;
+                    self.witness_script = Some(Script::new(value));
+                },
+                PSBT_IN_BIP32_DERIVATION => {
+                    if key_data.len() != 33 && key_data.len() != 65 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    self.bip32_derivation.insert(key_data.to_vec(), value);
+                },
+                PSBT_IN_FINAL_SCRIPTSIG => {
+                    no_key_data(&key)?;
+                    self.final_script_sig = Some(Script::new(value));
+                },
+                PSBT_IN_FINAL_SCRIPTWITNESS => {
+                    no_key_data(&key)?;
+                    self.final_script_witness = match Witness::from_bytes(&value) {
+                        Ok(x) => Some(x),
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                },
+                PSBT_IN_PREVIOUS_TXID => {
+                    no_key_data(&key)?;
+                    if value.len() != 32 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    //Serialized in little endian, stored in big endian
+                    self.previous_txid.copy_from_slice(&value);
+                    self.previous_txid.reverse();
+                },
+                PSBT_IN_OUTPUT_INDEX => self.vout = read_u32_value(&key, &value)?,
+                PSBT_IN_SEQUENCE => self.sequence = read_u32_value(&key, &value)?,
+                PSBT_IN_REQUIRED_TIME_LOCKTIME => {
+                    let locktime = read_u32_value(&key, &value)?;
+                    if locktime < LOCKTIME_THRESHOLD { return Err(PsbtErr::InvalidValue(key_type)) }
+                    self.required_time_locktime = Some(locktime);
+                },
+                PSBT_IN_REQUIRED_HEIGHT_LOCKTIME => {
+                    let locktime = read_u32_value(&key, &value)?;
+                    if locktime == 0 || locktime >= LOCKTIME_THRESHOLD { return Err(PsbtErr::InvalidValue(key_type)) }
+                    self.required_height_locktime = Some(locktime);
+                },
+                PSBT_IN_TAP_KEY_SIG => {
+                    no_key_data(&key)?;
+                    if value.len() != 64 && value.len() != 65 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    self.tap_key_sig = Some(value);
+                },
+                PSBT_IN_TAP_SCRIPT_SIG => {
+                    if key_data.len() != 64 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    if value.len() != 64 && value.len() != 65 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    self.tap_script_sigs.insert(key_data.to_vec(), value);
+                },
+                PSBT_IN_TAP_LEAF_SCRIPT => {
+                    if key_data.len() < 33 || (key_data.len() - 33) % 32 != 0 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    if value.is_empty() { return Err(PsbtErr::InvalidValue(key_type)) }
+                    let leaf_version = value[value.len() - 1];
+                    let script = Script::new(value[..value.len() - 1].to_vec());
+                    self.tap_leaf_scripts.insert(key_data.to_vec(), (script, leaf_version));
+                },
+                PSBT_IN_TAP_BIP32_DERIVATION => {
+                    if key_data.len() != 32 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    self.tap_bip32_derivation.insert(key_data.to_vec(), value);
+                },
+                PSBT_IN_TAP_INTERNAL_KEY => {
+                    no_key_data(&key)?;
+                    self.tap_internal_key = match schnorrsig::PublicKey::from_slice(&value) {
+                        Ok(x) => Some(x),
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                },
+                PSBT_IN_TAP_MERKLE_ROOT => {
+                    no_key_data(&key)?;
+                    if value.len() != 32 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    let mut root = [0; 32];
+                    root.copy_from_slice(&value);
+                    self.tap_merkle_root = Some(root);
+                },
+                _ => { self.unknown.insert(key, value); }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Write the key-value pairs of the input's map, without the separator
+    */
+    pub(crate) fn write_pairs(&self, bytes: &mut Vec<u8>, version: u32) -> Result<(), PsbtErr> {
+        if let Some(x) = &self.non_witness_utxo {
+            write_pair(bytes, PSBT_IN_NON_WITNESS_UTXO, &[], &x.serialize()?);
+        }
+        if let Some(x) = &self.witness_utxo {
+            write_pair(bytes, PSBT_IN_WITNESS_UTXO, &[], &x.serialize()?);
+        }
+        for (key, value) in &self.partial_sigs {
+            write_pair(bytes, PSBT_IN_PARTIAL_SIG, key, value);
+        }
+        if let Some(x) = self.sighash_type {
+            write_pair(bytes, PSBT_IN_SIGHASH_TYPE, &[], &x.to_le_bytes());
+        }
+        if let Some(x) = &self.redeem_script {
+            write_pair(bytes, PSBT_IN_REDEEM_SCRIPT, &[], &x.code);
+        }
+        if let Some(x) = &self.witness_script {
+            write_pair(bytes, PSBT_IN_WITNESS_SCRIPT, &[], &x.code);
+        }
+        for (key, value) in &self.bip32_derivation {
+            write_pair(bytes, PSBT_IN_BIP32_DERIVATION, key, value);
+        }
+        if let Some(x) = &self.final_script_sig {
+            write_pair(bytes, PSBT_IN_FINAL_SCRIPTSIG, &[], &x.code);
+        }
+        if let Some(x) = &self.final_script_witness {
+            write_pair(bytes, PSBT_IN_FINAL_SCRIPTWITNESS, &[], &x.serialize()?);
+        }
+        if version == 2 {
+            let mut txid: Vec<u8> = self.previous_txid.to_vec();
+            txid.reverse();
+            write_pair(bytes, PSBT_IN_PREVIOUS_TXID, &[], &txid);
+            write_pair(bytes, PSBT_IN_OUTPUT_INDEX, &[], &self.vout.to_le_bytes());
+            if self.sequence != 0xFFFFFFFF {
+                write_pair(bytes, PSBT_IN_SEQUENCE, &[], &self.sequence.to_le_bytes());
+            }
+            if let Some(x) = self.required_time_locktime {
+                write_pair(bytes, PSBT_IN_REQUIRED_TIME_LOCKTIME, &[], &x.to_le_bytes());
+            }
+            if let Some(x) = self.required_height_locktime {
+                write_pair(bytes, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, &[], &x.to_le_bytes());
+            }
+        }
+        if let Some(x) = &self.tap_key_sig {
+            write_pair(bytes, PSBT_IN_TAP_KEY_SIG, &[], x);
+        }
+        for (key, value) in &self.tap_script_sigs {
+            write_pair(bytes, PSBT_IN_TAP_SCRIPT_SIG, key, value);
+        }
+        for (key, (script, leaf_version)) in &self.tap_leaf_scripts {
+            let mut value: Vec<u8> = script.code.clone();
+            value.push(*leaf_version);
+            write_pair(bytes, PSBT_IN_TAP_LEAF_SCRIPT, key, &value);
+        }
+        for (key, value) in &self.tap_bip32_derivation {
+            write_pair(bytes, PSBT_IN_TAP_BIP32_DERIVATION, key, value);
+        }
+        if let Some(x) = &self.tap_internal_key {
+            write_pair(bytes, PSBT_IN_TAP_INTERNAL_KEY, &[], &x.serialize());
+        }
+        if let Some(x) = &self.tap_merkle_root {
+            write_pair(bytes, PSBT_IN_TAP_MERKLE_ROOT, &[], x);
+        }
+        write_unknown(bytes, &self.unknown);
+
+        Ok(())
+    }
+}
+
+/**
+    Error if a key type that takes no key data was given some
+*/
+pub(crate) fn no_key_data(key: &[u8]) -> Result<(), PsbtErr> {
+    if key.len() != 1 { return Err(PsbtErr::InvalidKey(key[0])) }
+    Ok(())
+}
+
+/**
+    Insert the entries of another map that are missing from this one
+*/
+pub(crate) fn merge<V: Clone>(map: &mut BTreeMap<Vec<u8>, V>, other: &BTreeMap<Vec<u8>, V>) {
+    for (key, value) in other {
+        map.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
@@ -0,0 +1,868 @@
+/*
+    Partially Signed Bitcoin Transactions (BIP-174 and BIP-370)
+
+    A PSBT carries an unsigned transaction along with everything signers need
+    to sign it, so that inputs can be signed by different people and devices
+    and then combined and finalized into a Tx.
+
+    Roles implemented:
+        - Creator (from a TxBuilder or an unsigned Tx)
+        - Constructor (adding inputs and outputs to v2 PSBTs)
+        - Signer
+        - Combiner
+        - Finalizer
+        - Extractor
+*/
+mod input;
+mod output;
+mod sign;
+mod v2;
+
+pub use input::PsbtInput;
+pub use output::PsbtOutput;
+pub use v2::{
+    PSBT_GLOBAL_TX_VERSION,
+    PSBT_GLOBAL_FALLBACK_LOCKTIME,
+    PSBT_GLOBAL_INPUT_COUNT,
+    PSBT_GLOBAL_OUTPUT_COUNT,
+    PSBT_GLOBAL_TX_MODIFIABLE,
+    TX_MODIFIABLE_INPUTS,
+    TX_MODIFIABLE_OUTPUTS,
+    TX_MODIFIABLE_SIGHASH_SINGLE,
+    LOCKTIME_THRESHOLD
+};
+
+use std::collections::BTreeMap;
+use crate::{
+    tx::{
+        Tx,
+        Input,
+        Output,
+        Script,
+        ScriptType,
+        TxBuilder,
+        BuilderErr
+    },
+    api::ChainSource,
+    util::{
+        serialize::{
+            Serialize,
+            Deserialize,
+            Decoder,
+            SerializationError
+        },
+        varint::VarInt,
+        bytes
+    }
+};
+
+//PSBT magic bytes "psbt" followed by 0xff
+pub const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+
+//Global key types
+pub const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+pub const PSBT_GLOBAL_XPUB: u8 = 0x01;
+pub const PSBT_GLOBAL_VERSION: u8 = 0xFB;
+
+#[derive(Debug)]
+pub enum PsbtErr {
+    InvalidMagic(),
+    InvalidKey(u8),                 //Key type with invalid key data
+    InvalidValue(u8),               //Key type with invalid value
+    DuplicateKey(Vec<u8>),
+    MissingUnsignedTx(),
+    MissingField(u8),               //Required key type missing from a v2 PSBT
+    FieldNotAllowed(u8),            //Key type not allowed in this PSBT version
+    UnsignedTxHasScriptSigs(),
+    UnsupportedVersion(u32),
+    InvalidBase64(),
+    Serialization(SerializationError),
+    MismatchedTx(),
+    InvalidInputIndex(usize),
+    MissingUtxo(usize),
+    SigHashMismatch(usize),
+    UnknownScriptType(usize),
+    ScriptMismatch(usize),          //Script or key does not match the output being spent
+    Builder(BuilderErr),
+    CannotFinalize(usize),
+    NotFinalized(usize),
+    NotModifiable(),                //Inputs or outputs cannot be added to the PSBT
+    LocktimeConflict()              //Inputs require both a time and height locktime, or a signed locktime would change
+}
+
+impl From<SerializationError> for PsbtErr {
+    fn from(err: SerializationError) -> Self {
+        PsbtErr::Serialization(err)
+    }
+}
+
+impl From<BuilderErr> for PsbtErr {
+    fn from(err: BuilderErr) -> Self {
+        PsbtErr::Builder(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Psbt {
+    pub version: u32,                                   //PSBT version (0 or 2)
+    pub tx_version: u32,                                //Version of the unsigned transaction
+    pub fallback_locktime: Option<u32>,                 //Locktime of the unsigned transaction. Only used in v2 if no input requires a locktime
+    pub tx_modifiable: u8,                              //v2 flags for whether inputs and outputs can be added
+    pub xpubs: BTreeMap<Vec<u8>, Vec<u8>>,              //Extended public keys and their derivation paths
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,            //Unknown and proprietary global key-value pairs
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>
+}
+
+impl Psbt {
+    /**
+        Create a PSBT from an unsigned transaction.
+        The transaction must not contain any scriptSigs or witnesses.
+    */
+    pub fn from_unsigned_tx(tx: &Tx) -> Result<Self, PsbtErr> {
+        if tx.inputs.iter().any(|x| x.scriptSig.len() > 0) || tx.segwit {
+            return Err(PsbtErr::UnsignedTxHasScriptSigs())
+        }
+
+        Ok(Self {
+            version: 0,
+            tx_version: tx.version,
+            fallback_locktime: Some(tx.locktime),
+            tx_modifiable: 0,
+            xpubs: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: tx.inputs.iter().map(|x| PsbtInput::new(x.txid, x.vout, x.sequence)).collect(),
+            outputs: tx.outputs.iter().map(|x| PsbtOutput::new(x.value, x.script_pub_key.clone())).collect()
+        })
+    }
+
+    /**
+        Create a PSBT from the inputs and outputs of a TxBuilder.
+
+        The outputs being spent are attached to each input. Segwit inputs get the spent output
+        as the witness UTXO and legacy inputs get the full previous transaction, which the chain
+        source has to provide. P2SH inputs fall back to the witness UTXO as they may be nested segwit.
+        Inputs that are already signed in the builder are added as finalized.
+    */
+    pub fn from_builder<C: ChainSource>(builder: &mut TxBuilder<C>) -> Result<Self, PsbtErr> {
+        let mut psbt = Self {
+            version: 0,
+            tx_version: builder.version,
+            fallback_locktime: Some(builder.locktime),
+            tx_modifiable: 0,
+            xpubs: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: builder.inputs.iter().map(|x| PsbtInput::new(x.txid, x.vout, x.sequence)).collect(),
+            outputs: builder.outputs.iter().map(|x| PsbtOutput::new(x.value, x.script_pub_key.clone())).collect()
+        };
+
+        for i in 0..builder.inputs.len() {
+            let prevout: Output = builder.get_prevout(i)?;
+            match prevout.script_pub_key.determine_type() {
+                ScriptType::P2PKH | ScriptType::P2SH | ScriptType::NonStandard => {
+                    let txid = bytes::encode_02x(&builder.inputs[i].txid);
+                    let previous_tx = match &builder.source {
+                        Some(source) => source.get_transaction(&txid).ok(),
+                        None => None
+                    };
+                    match (previous_tx, prevout.script_pub_key.determine_type()) {
+                        (Some(x), _) => psbt.inputs[i].non_witness_utxo = Some(x),
+                        (None, ScriptType::P2SH) => psbt.inputs[i].witness_utxo = Some(prevout),
+                        (None, _) => return Err(PsbtErr::MissingUtxo(i))
+                    }
+                },
+                _ => psbt.inputs[i].witness_utxo = Some(prevout)
+            }
+
+            //Carry over inputs that were already signed in the builder
+            if let Some(x) = &builder.script_sigs[i] {
+                psbt.inputs[i].final_script_sig = Some(x.clone());
+            }
+            if let Some(x) = &builder.witness[i] {
+                psbt.inputs[i].final_script_witness = Some(x.clone());
+            }
+        }
+
+        Ok(psbt)
+    }
+
+    /**
+        Returns the unsigned transaction described by the PSBT.
+        Inputs have empty scriptSigs and there is no witness data.
+    */
+    pub fn unsigned_tx(&self) -> Result<Tx, PsbtErr> {
+        let inputs: Vec<Input> = self.inputs.iter().map(|x| {
+            let mut input = Input::unsigned_input(x.previous_txid, x.vout, x.sequence);
+            input.scriptSig = Script::new(vec![]);
+            input.scriptSig_size = 0;
+            input
+        }).collect();
+        let outputs: Vec<Output> = self.outputs.iter().map(|x| Output {
+            value: x.amount,
+            script_pub_key: x.script.clone()
+        }).collect();
+
+        let mut tx = Tx::construct(inputs, outputs, self.locktime()?, false);
+        tx.version = self.tx_version;
+        Ok(tx)
+    }
+
+    /**
+        Identifier used to check that two PSBTs are for the same transaction.
+        This is the TXID of the unsigned transaction, with every sequence set to 0 for v2 PSBTs.
+    */
+    pub fn unique_id(&self) -> Result<String, PsbtErr> {
+        let mut tx = self.unsigned_tx()?;
+        if self.version == 2 {
+            for input in &mut tx.inputs {
+                input.sequence = 0;
+            }
+        }
+
+        Ok(tx.get_txid())
+    }
+
+    /**
+        Get the output being spent by an input from its UTXO fields
+    */
+    pub fn spent_output(&self, index: usize) -> Result<Output, PsbtErr> {
+        let input = match self.inputs.get(index) {
+            Some(x) => x,
+            None => return Err(PsbtErr::InvalidInputIndex(index))
+        };
+
+        if let Some(x) = &input.witness_utxo {
+            return Ok(x.clone())
+        }
+        if let Some(x) = &input.non_witness_utxo {
+            if x.get_txid() == bytes::encode_02x(&input.previous_txid) {
+                if let Some(o) = x.outputs.get(input.vout as usize) {
+                    return Ok(o.clone())
+                }
+            }
+        }
+
+        Err(PsbtErr::MissingUtxo(index))
+    }
+
+    /**
+        Combine another PSBT for the same transaction into this one
+    */
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), PsbtErr> {
+        if self.version != other.version || self.unique_id()? != other.unique_id()? {
+            return Err(PsbtErr::MismatchedTx())
+        }
+
+        //Modifiable flags are only ever cleared by signers, apart from SIGHASH_SINGLE which is only ever set
+        self.tx_modifiable = (self.tx_modifiable & other.tx_modifiable & (TX_MODIFIABLE_INPUTS | TX_MODIFIABLE_OUTPUTS))
+            | ((self.tx_modifiable | other.tx_modifiable) & TX_MODIFIABLE_SIGHASH_SINGLE);
+        self.xpubs.extend(other.xpubs.clone());
+        self.unknown.extend(other.unknown.clone());
+        for i in 0..self.inputs.len() {
+            self.inputs[i].combine(&other.inputs[i]);
+        }
+        for i in 0..self.outputs.len() {
+            self.outputs[i].combine(&other.outputs[i]);
+        }
+
+        Ok(())
+    }
+
+    /**
+        Serialize the PSBT and encode it in Base64
+    */
+    pub fn to_base64(&self) -> Result<String, PsbtErr> {
+        Ok(base64::encode(self.serialize()?))
+    }
+
+    /**
+        Parse a Base64 encoded PSBT
+    */
+    pub fn from_base64(s: &str) -> Result<Self, PsbtErr> {
+        match base64::decode(s.trim()) {
+            Ok(x) => Self::from_slice(&x),
+            Err(_) => Err(PsbtErr::InvalidBase64())
+        }
+    }
+
+    /**
+        Parse a binary PSBT of version 0 or 2
+    */
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, PsbtErr> {
+        let mut decoder = Decoder::new(bytes);
+        if decoder.read_bytes(5)? != PSBT_MAGIC.to_vec() {
+            return Err(PsbtErr::InvalidMagic())
+        }
+
+        //Global map
+        let mut unsigned_tx: Option<Tx> = None;
+        let mut version: u32 = 0;
+        let mut tx_version: Option<u32> = None;
+        let mut fallback_locktime: Option<u32> = None;
+        let mut input_count: Option<u64> = None;
+        let mut output_count: Option<u64> = None;
+        let mut tx_modifiable: u8 = 0;
+        let mut xpubs: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut unknown: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for (key, value) in read_map(&mut decoder)? {
+            let key_type = key[0];
+            match key_type {
+                PSBT_GLOBAL_UNSIGNED_TX => {
+                    input::no_key_data(&key)?;
+                    let tx = match Tx::from_bytes(&value) {
+                        Ok(x) => x,
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                    if tx.segwit || tx.inputs.iter().any(|x| x.scriptSig.len() > 0) {
+                        return Err(PsbtErr::UnsignedTxHasScriptSigs())
+                    }
+                    unsigned_tx = Some(tx);
+                },
+                PSBT_GLOBAL_XPUB => { xpubs.insert(key[1..].to_vec(), value); },
+                PSBT_GLOBAL_TX_VERSION => tx_version = Some(read_u32_value(&key, &value)?),
+                PSBT_GLOBAL_FALLBACK_LOCKTIME => fallback_locktime = Some(read_u32_value(&key, &value)?),
+                PSBT_GLOBAL_INPUT_COUNT => input_count = Some(read_varint_value(&key, &value)?),
+                PSBT_GLOBAL_OUTPUT_COUNT => output_count = Some(read_varint_value(&key, &value)?),
+                PSBT_GLOBAL_TX_MODIFIABLE => {
+                    input::no_key_data(&key)?;
+                    if value.len() != 1 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    tx_modifiable = value[0];
+                },
+                PSBT_GLOBAL_VERSION => version = read_u32_value(&key, &value)?,
+                _ => { unknown.insert(key, value); }
+            }
+        }
+
+        //Version 0 gets the transaction from the unsigned tx and version 2 from the v2 fields
+        let mut psbt: Psbt = match version {
+            0 => {
+                if tx_version.is_some() { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_TX_VERSION)) }
+                if fallback_locktime.is_some() { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_FALLBACK_LOCKTIME)) }
+                if input_count.is_some() { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_INPUT_COUNT)) }
+                if output_count.is_some() { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_OUTPUT_COUNT)) }
+                if tx_modifiable != 0 { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_TX_MODIFIABLE)) }
+                let mut psbt = match unsigned_tx {
+                    Some(x) => Self::from_unsigned_tx(&x)?,
+                    None => return Err(PsbtErr::MissingUnsignedTx())
+                };
+                for input in &mut psbt.inputs {
+                    input.read_pairs(read_map(&mut decoder)?, version)?;
+                }
+                for output in &mut psbt.outputs {
+                    output.read_pairs(read_map(&mut decoder)?, version)?;
+                }
+
+                psbt
+            },
+            2 => {
+                if unsigned_tx.is_some() { return Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_UNSIGNED_TX)) }
+                let (tx_version, input_count, output_count) = match (tx_version, input_count, output_count) {
+                    (Some(v), Some(i), Some(o)) => (v, i, o),
+                    (None, _, _) => return Err(PsbtErr::MissingField(PSBT_GLOBAL_TX_VERSION)),
+                    (_, None, _) => return Err(PsbtErr::MissingField(PSBT_GLOBAL_INPUT_COUNT)),
+                    (_, _, None) => return Err(PsbtErr::MissingField(PSBT_GLOBAL_OUTPUT_COUNT))
+                };
+                let mut psbt = Self::new_v2(fallback_locktime);
+                psbt.tx_version = tx_version;
+                psbt.tx_modifiable = tx_modifiable;
+                for _ in 0..input_count {
+                    let mut input = PsbtInput::new([0; 32], 0, 0xFFFFFFFF);
+                    input.read_pairs(read_map(&mut decoder)?, version)?;
+                    psbt.inputs.push(input);
+                }
+                for _ in 0..output_count {
+                    let mut output = PsbtOutput::new(0, Script::new(vec![]));
+                    output.read_pairs(read_map(&mut decoder)?, version)?;
+                    psbt.outputs.push(output);
+                }
+                psbt.locktime()?;
+
+                psbt
+            },
+            _ => return Err(PsbtErr::UnsupportedVersion(version))
+        };
+        if !decoder.is_empty() {
+            return Err(PsbtErr::Serialization(SerializationError::TrailingBytes(decoder.position())))
+        }
+        psbt.xpubs = xpubs;
+        psbt.unknown = unknown;
+
+        Ok(psbt)
+    }
+
+    /**
+        Serialize the PSBT into its binary format
+    */
+    pub fn serialize(&self) -> Result<Vec<u8>, PsbtErr> {
+        let mut bytes: Vec<u8> = PSBT_MAGIC.to_vec();
+
+        //Global map
+        if self.version == 0 {
+            write_pair(&mut bytes, PSBT_GLOBAL_UNSIGNED_TX, &[], &self.unsigned_tx()?.serialize()?);
+        }
+        for (key, value) in &self.xpubs {
+            write_pair(&mut bytes, PSBT_GLOBAL_XPUB, key, value);
+        }
+        if self.version == 2 {
+            write_pair(&mut bytes, PSBT_GLOBAL_TX_VERSION, &[], &self.tx_version.to_le_bytes());
+            if let Some(x) = self.fallback_locktime {
+                write_pair(&mut bytes, PSBT_GLOBAL_FALLBACK_LOCKTIME, &[], &x.to_le_bytes());
+            }
+            write_pair(&mut bytes, PSBT_GLOBAL_INPUT_COUNT, &[], &VarInt::from_usize(self.inputs.len()).unwrap());
+            write_pair(&mut bytes, PSBT_GLOBAL_OUTPUT_COUNT, &[], &VarInt::from_usize(self.outputs.len()).unwrap());
+            if self.tx_modifiable != 0 {
+                write_pair(&mut bytes, PSBT_GLOBAL_TX_MODIFIABLE, &[], &[self.tx_modifiable]);
+            }
+        }
+        if self.version > 0 {
+            write_pair(&mut bytes, PSBT_GLOBAL_VERSION, &[], &self.version.to_le_bytes());
+        }
+        write_unknown(&mut bytes, &self.unknown);
+        bytes.push(0x00);
+
+        for input in &self.inputs {
+            input.write_pairs(&mut bytes, self.version)?;
+            bytes.push(0x00);
+        }
+        for output in &self.outputs {
+            output.write_pairs(&mut bytes, self.version);
+            bytes.push(0x00);
+        }
+
+        Ok(bytes)
+    }
+}
+
+/**
+    Read key-value pairs until the 0x00 separator.
+    Keys are returned with their key type as the first byte.
+*/
+pub(crate) fn read_map(decoder: &mut Decoder) -> Result<Vec<(Vec<u8>, Vec<u8>)>, PsbtErr> {
+    let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+    loop {
+        let key = decoder.read_var_bytes()?;
+        if key.is_empty() { break }
+        if pairs.iter().any(|(k, _)| k == &key) {
+            return Err(PsbtErr::DuplicateKey(key))
+        }
+        let value = decoder.read_var_bytes()?;
+        pairs.push((key, value));
+    }
+
+    Ok(pairs)
+}
+
+/**
+    Write a key-value pair
+*/
+pub(crate) fn write_pair(bytes: &mut Vec<u8>, key_type: u8, key_data: &[u8], value: &[u8]) {
+    bytes.append(&mut VarInt::from_usize(key_data.len() + 1).unwrap());
+    bytes.push(key_type);
+    bytes.extend_from_slice(key_data);
+    bytes.append(&mut VarInt::from_usize(value.len()).unwrap());
+    bytes.extend_from_slice(value);
+}
+
+/**
+    Write unknown key-value pairs where the key already includes the key type
+*/
+pub(crate) fn write_unknown(bytes: &mut Vec<u8>, unknown: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in unknown {
+        bytes.append(&mut VarInt::from_usize(key.len()).unwrap());
+        bytes.extend_from_slice(key);
+        bytes.append(&mut VarInt::from_usize(value.len()).unwrap());
+        bytes.extend_from_slice(value);
+    }
+}
+
+pub(crate) fn read_u32(value: &[u8]) -> u32 {
+    let mut x = [0; 4];
+    x.copy_from_slice(&value[..4]);
+    u32::from_le_bytes(x)
+}
+
+/**
+    Read a 4 byte value for a key type that has no key data
+*/
+pub(crate) fn read_u32_value(key: &[u8], value: &[u8]) -> Result<u32, PsbtErr> {
+    input::no_key_data(key)?;
+    if value.len() != 4 { return Err(PsbtErr::InvalidValue(key[0])) }
+    Ok(read_u32(value))
+}
+
+/**
+    Read a VarInt value for a key type that has no key data
+*/
+pub(crate) fn read_varint_value(key: &[u8], value: &[u8]) -> Result<u64, PsbtErr> {
+    input::no_key_data(key)?;
+    let mut decoder = Decoder::new(value);
+    match decoder.read_varint() {
+        Ok(x) if decoder.is_empty() => Ok(x),
+        _ => Err(PsbtErr::InvalidValue(key[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PrivKey, PubKey, Key,
+        hash,
+        signature,
+        schnorrsig,
+        tx::{
+            SigHash,
+            SigningData,
+            ScriptBuilder,
+            ScriptCodes,
+            TapTreeBuilder,
+            taproot
+        },
+        util::Network,
+        test_utils::key
+    };
+
+    //BIP-174 test vector with one P2PKH input and two outputs
+    const P2PKH_PSBT: &str = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+
+    #[test]
+    fn parse_and_serialize() {
+        let psbt = Psbt::from_base64(P2PKH_PSBT).unwrap();
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(psbt.locktime().unwrap(), 1257139);
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert_eq!(psbt.spent_output(0).unwrap().value, 200000000);
+
+        //Serializing gives back the same PSBT
+        assert_eq!(psbt.to_base64().unwrap(), P2PKH_PSBT);
+    }
+
+    #[test]
+    fn invalid_psbts() {
+        //Wrong magic
+        assert!(matches!(Psbt::from_slice(&[0x70, 0x73, 0x62, 0x78, 0xff, 0x00]), Err(PsbtErr::InvalidMagic())));
+
+        //No unsigned transaction
+        assert!(matches!(Psbt::from_slice(&[0x70, 0x73, 0x62, 0x74, 0xff, 0x00]), Err(PsbtErr::MissingUnsignedTx())));
+
+        //Duplicate keys
+        let mut bytes = Psbt::from_base64(P2PKH_PSBT).unwrap().serialize().unwrap();
+        let global_end = 5 + 3 + 0x75;
+        let duplicate = bytes[5..global_end].to_vec();
+        bytes.splice(global_end..global_end, duplicate);
+        assert!(matches!(Psbt::from_slice(&bytes), Err(PsbtErr::DuplicateKey(_))));
+
+        //Not base64
+        assert!(matches!(Psbt::from_base64("not a psbt!"), Err(PsbtErr::InvalidBase64())));
+    }
+
+    #[test]
+    fn p2wsh_multisig_combine_and_finalize() {
+        let keys = vec![key(1), key(2)];
+        let witness_script = Script::multisig_locking(2, &keys);
+        let mut program: Vec<u8> = vec![0x00, 0x20];
+        program.append(&mut hash::sha256(&witness_script.code).to_vec());
+        let prevout = Output { value: 100000, script_pub_key: Script::new(program) };
+        let txid = "a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167";
+
+        //Sign with both keys in a single builder for comparison
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(txid, 0, prevout.clone()).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 90000).unwrap();
+        let mut psbt = Psbt::from_builder(&mut txb).unwrap();
+        txb.sign_input(0, &SigningData::new(keys.clone(), Some(witness_script.clone())), SigHash::ALL).unwrap();
+        let expected = txb.build().unwrap();
+
+        //Each signer signs their own copy of the PSBT
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+        let mut first = Psbt::from_base64(&psbt.to_base64().unwrap()).unwrap();
+        let mut second = Psbt::from_base64(&psbt.to_base64().unwrap()).unwrap();
+        first.sign_input(0, &SigningData::new(vec![keys[0]], None), SigHash::ALL).unwrap();
+        second.sign_input(0, &SigningData::new(vec![keys[1]], None), SigHash::ALL).unwrap();
+
+        //One signature is not enough to finalize
+        assert!(matches!(first.clone().finalize(), Err(PsbtErr::CannotFinalize(0))));
+
+        first.combine(&second).unwrap();
+        assert_eq!(first.inputs[0].partial_sigs.len(), 2);
+        first.finalize().unwrap();
+        assert!(first.inputs[0].partial_sigs.is_empty());
+        assert!(first.inputs[0].witness_script.is_none());
+
+        let tx = Psbt::from_base64(&first.to_base64().unwrap()).unwrap().extract_tx().unwrap();
+        assert_eq!(tx.serialize().unwrap(), expected.serialize().unwrap());
+    }
+
+    #[test]
+    fn combine_different_tx() {
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(
+            "a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 0,
+            Output { value: 1000, script_pub_key: Script::new(vec![0x00, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14]) }
+        ).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 900).unwrap();
+        let mut psbt = Psbt::from_builder(&mut txb).unwrap();
+        let mut other = psbt.clone();
+        other.outputs[0].amount = 800;
+
+        assert!(matches!(psbt.combine(&other), Err(PsbtErr::MismatchedTx())));
+    }
+
+    #[test]
+    fn p2tr_key_path() {
+        let signer = key(3);
+        let (output_key, _) = taproot::output_key(&taproot::internal_key(&signer), None).unwrap();
+        let prevout = Output { value: 50000, script_pub_key: Script::p2tr_locking(&output_key) };
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 1, prevout.clone()).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 40000).unwrap();
+        let mut psbt = Psbt::from_builder(&mut txb).unwrap();
+
+        //A key that cannot spend the output is rejected
+        assert!(matches!(psbt.sign_input(0, &SigningData::new(vec![key(4)], None), SigHash::DEFAULT), Err(PsbtErr::ScriptMismatch(0))));
+
+        psbt.sign_input(0, &SigningData::new(vec![signer], None), SigHash::DEFAULT).unwrap();
+        psbt.finalize().unwrap();
+        let tx = psbt.extract_tx().unwrap();
+
+        //Verify the signature in the witness against the sighash
+        let sig = &tx.witness.as_ref().unwrap()[0].stack()[0].code;
+        assert_eq!(sig.len(), 64);
+        let preimage = crate::tx::builder::hashpreimage::taproot(&psbt.unsigned_tx().unwrap(), &SigHash::DEFAULT, 0, &vec![prevout], &None, None).unwrap();
+        let msg = signature::new_msg(&hash::tagged_hash("TapSighash", preimage)).unwrap();
+        let sig = schnorrsig::Signature::from_slice(sig).unwrap();
+        assert!(signature::verify_schnorr(&sig, &msg, &output_key).is_ok());
+    }
+
+    #[test]
+    fn p2tr_script_path_threshold() {
+        let signers = vec![key(10), key(11)];
+        let xonly = |k: &PrivKey| taproot::internal_key(k).serialize().to_vec();
+
+        //1-of-2 CHECKSIGADD leaf and a CHECKSIG chain that also ends in OP_1 OP_NUMEQUAL.
+        //The second key of the chain contains 0xba bytes, which is the OP_CHECKSIGADD opcode.
+        let threshold = ScriptBuilder::new()
+            .push_slice(&xonly(&signers[0])).push_opcode(ScriptCodes::OP_CHECKSIG)
+            .push_slice(&xonly(&signers[1])).push_opcode(ScriptCodes::OP_CHECKSIGADD)
+            .push_opcode(ScriptCodes::OP_1).push_opcode(ScriptCodes::OP_NUMEQUAL)
+            .into_script();
+        let chain = ScriptBuilder::new()
+            .push_slice(&xonly(&signers[0])).push_opcode(ScriptCodes::OP_CHECKSIGVERIFY)
+            .push_slice(&[0xba; 32]).push_opcode(ScriptCodes::OP_CHECKSIG)
+            .push_opcode(ScriptCodes::OP_1).push_opcode(ScriptCodes::OP_NUMEQUAL)
+            .into_script();
+        let mut tree = TapTreeBuilder::new();
+        tree.add_leaf(1, threshold.clone());
+        tree.add_leaf(1, chain.clone());
+        let spend_info = tree.finalize(taproot::internal_key(&key(12))).unwrap();
+        let prevout = Output { value: 50000, script_pub_key: spend_info.script_pub_key() };
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 0, prevout).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 40000).unwrap();
+        let psbt = Psbt::from_builder(&mut txb).unwrap();
+
+        //One signature is not enough for the CHECKSIG chain
+        let mut chain_psbt = psbt.clone();
+        let control_block = spend_info.control_block(&chain).unwrap();
+        chain_psbt.sign_input(0, &SigningData::taproot_script_path(vec![signers[0]], chain, control_block), SigHash::DEFAULT).unwrap();
+        assert!(matches!(chain_psbt.finalize(), Err(PsbtErr::CannotFinalize(0))));
+
+        //One signature satisfies the threshold leaf and the missing one is left empty
+        let mut psbt = psbt;
+        let control_block = spend_info.control_block(&threshold).unwrap();
+        psbt.sign_input(0, &SigningData::taproot_script_path(vec![signers[0]], threshold, control_block), SigHash::DEFAULT).unwrap();
+        psbt.finalize().unwrap();
+        let witness = psbt.inputs[0].final_script_witness.clone().unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness.stack()[0].code.len(), 0);
+        assert_eq!(witness.stack()[1].code.len(), 64);
+    }
+
+    #[test]
+    fn legacy_inputs_need_previous_tx() {
+        let signer = key(15);
+        let mut script: Vec<u8> = vec![0x76, 0xa9, 0x14];
+        script.append(&mut hash::hash160(PubKey::from_priv_key(&signer).as_bytes::<33>()).to_vec());
+        script.append(&mut vec![0x88, 0xac]);
+        let prevout = Output { value: 20000, script_pub_key: Script::new(script) };
+        let previous_tx = Tx::construct(vec![Input::unsigned_input([0x01; 32], 0, 0xFFFFFFFF)], vec![prevout.clone()], 0, false);
+        let txid = previous_tx.get_txid();
+
+        //The previous transaction cannot be looked up
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(&txid, 0, prevout.clone()).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 10000).unwrap();
+        assert!(matches!(Psbt::from_builder(&mut txb), Err(PsbtErr::MissingUtxo(0))));
+
+        let mut chain = crate::api::MemoryChain::new();
+        chain.insert_tx(previous_tx);
+        let mut txb = TxBuilder::with_source(Network::Testnet, chain);
+        txb.add_input(&txid, 0).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 10000).unwrap();
+        let psbt = Psbt::from_builder(&mut txb).unwrap();
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert!(psbt.inputs[0].witness_utxo.is_none());
+
+        //Signers refuse legacy inputs that only have the witness UTXO
+        let mut witness_only = psbt.clone();
+        witness_only.inputs[0].non_witness_utxo = None;
+        witness_only.inputs[0].witness_utxo = Some(prevout);
+        assert!(matches!(witness_only.sign_input(0, &SigningData::new(vec![signer], None), SigHash::ALL), Err(PsbtErr::MissingUtxo(0))));
+
+        let mut psbt = psbt;
+        psbt.sign_input(0, &SigningData::new(vec![signer], None), SigHash::ALL).unwrap();
+        psbt.finalize().unwrap();
+        assert!(psbt.extract_tx().is_ok());
+    }
+
+    #[test]
+    fn signing_key_must_match_prevout() {
+        let mut psbt = Psbt::new_v2(None);
+        psbt.add_input(p2wpkh_input(&key(13), 1)).unwrap();
+
+        assert!(matches!(psbt.sign_input(0, &SigningData::new(vec![key(14)], None), SigHash::ALL), Err(PsbtErr::ScriptMismatch(0))));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        psbt.sign_input(0, &SigningData::new(vec![key(13)], None), SigHash::ALL).unwrap();
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+    }
+
+    //P2WPKH input spending an output locked to the given key
+    fn p2wpkh_input(signer: &PrivKey, txid_byte: u8) -> PsbtInput {
+        let mut program: Vec<u8> = vec![0x00, 0x14];
+        program.append(&mut hash::hash160(&PubKey::from_priv_key(signer).as_bytes::<33>()).to_vec());
+        let mut input = PsbtInput::new([txid_byte; 32], 0, 0xFFFFFFFD);
+        input.witness_utxo = Some(Output { value: 10000, script_pub_key: Script::new(program) });
+
+        input
+    }
+
+    #[test]
+    fn v2_add_inputs_and_outputs() {
+        let alice = key(5);
+        let bob = key(6);
+        let output = PsbtOutput::new(15000, Script::new(vec![0x00, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14]));
+
+        //Alice creates the PSBT and signs her input allowing others to add inputs
+        let mut psbt = Psbt::new_v2(Some(700000));
+        psbt.add_input(p2wpkh_input(&alice, 1)).unwrap();
+        psbt.add_output(output.clone()).unwrap();
+        psbt.sign_input(0, &SigningData::new(vec![alice], None), SigHash::ALL_ANYONECANPAY).unwrap();
+        assert!(psbt.inputs_modifiable());
+        assert!(!psbt.outputs_modifiable());
+        assert!(matches!(psbt.add_output(output.clone()), Err(PsbtErr::NotModifiable())));
+
+        //Bob receives it, adds his input and signs everything
+        let mut psbt = Psbt::from_base64(&psbt.to_base64().unwrap()).unwrap();
+        assert_eq!(psbt.version, 2);
+        psbt.add_input(p2wpkh_input(&bob, 2)).unwrap();
+        psbt.sign_input(1, &SigningData::new(vec![bob], None), SigHash::ALL).unwrap();
+        assert!(!psbt.inputs_modifiable());
+        assert!(matches!(psbt.add_input(p2wpkh_input(&bob, 3)), Err(PsbtErr::NotModifiable())));
+
+        //Serializing gives back the same PSBT
+        let bytes = psbt.serialize().unwrap();
+        assert_eq!(Psbt::from_slice(&bytes).unwrap().serialize().unwrap(), bytes);
+
+        //The transaction is the same when finalized as v2 or after converting to v0
+        let mut v0 = psbt.to_v0().unwrap();
+        psbt.finalize().unwrap();
+        v0.finalize().unwrap();
+        let tx = psbt.extract_tx().unwrap();
+        assert_eq!(tx.locktime, 700000);
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.get_txid(), v0.extract_tx().unwrap().get_txid());
+        assert!(Psbt::from_slice(&v0.serialize().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn v2_sighash_single_pairs() {
+        let alice = key(7);
+        let output = PsbtOutput::new(5000, Script::new(vec![0x00, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14]));
+
+        let mut psbt = Psbt::new_v2(None);
+        psbt.add_input_output_pair(p2wpkh_input(&alice, 1), output.clone()).unwrap();
+        psbt.sign_input(0, &SigningData::new(vec![alice], None), SigHash::SINGLE_ANYONECANPAY).unwrap();
+        assert!(psbt.has_sighash_single());
+
+        //Inputs and outputs can now only be added together
+        assert!(matches!(psbt.add_input(p2wpkh_input(&alice, 2)), Err(PsbtErr::NotModifiable())));
+        assert!(matches!(psbt.add_output(output.clone()), Err(PsbtErr::NotModifiable())));
+        psbt.add_input_output_pair(p2wpkh_input(&alice, 2), output).unwrap();
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(psbt.outputs.len(), 2);
+    }
+
+    #[test]
+    fn v2_sighash_single_pairs_with_sighash_all() {
+        let alice = key(7);
+        let bob = key(8);
+        let output = PsbtOutput::new(5000, Script::new(vec![0x00, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14]));
+
+        //Alice commits to every output, Bob only to the output paired with his input
+        let mut psbt = Psbt::new_v2(None);
+        psbt.add_input_output_pair(p2wpkh_input(&alice, 1), output.clone()).unwrap();
+        psbt.add_input_output_pair(p2wpkh_input(&bob, 2), output.clone()).unwrap();
+        psbt.sign_input(0, &SigningData::new(vec![alice], None), SigHash::ALL_ANYONECANPAY).unwrap();
+        psbt.sign_input(1, &SigningData::new(vec![bob], None), SigHash::SINGLE_ANYONECANPAY).unwrap();
+        assert!(psbt.has_sighash_single());
+        assert!(!psbt.outputs_modifiable());
+
+        //Adding a pair would invalidate Alice's signature
+        assert!(matches!(psbt.add_input_output_pair(p2wpkh_input(&alice, 3), output), Err(PsbtErr::NotModifiable())));
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(psbt.outputs.len(), 2);
+    }
+
+    #[test]
+    fn v2_locktime() {
+        let mut psbt = Psbt::new_v2(Some(10));
+        assert_eq!(psbt.locktime().unwrap(), 10);
+
+        let mut height = p2wpkh_input(&key(8), 1);
+        height.required_height_locktime = Some(650000);
+        let mut both = p2wpkh_input(&key(8), 2);
+        both.required_height_locktime = Some(660000);
+        both.required_time_locktime = Some(1650000000);
+        let mut time = p2wpkh_input(&key(8), 3);
+        time.required_time_locktime = Some(1660000000);
+
+        //Heights are used when every input with a requirement accepts one
+        psbt.add_input(height).unwrap();
+        psbt.add_input(both).unwrap();
+        assert_eq!(psbt.locktime().unwrap(), 660000);
+
+        //An input that only accepts a time cannot be added
+        assert!(matches!(psbt.add_input(time), Err(PsbtErr::LocktimeConflict())));
+        assert_eq!(psbt.inputs.len(), 2);
+
+        //Locktime fields survive serialization
+        let parsed = Psbt::from_slice(&psbt.serialize().unwrap()).unwrap();
+        assert_eq!(parsed.inputs[1].required_time_locktime, Some(1650000000));
+        assert_eq!(parsed.locktime().unwrap(), 660000);
+    }
+
+    #[test]
+    fn v2_invalid_fields() {
+        let mut psbt = Psbt::new_v2(None);
+        psbt.add_input(p2wpkh_input(&key(9), 1)).unwrap();
+        let v2 = psbt.serialize().unwrap();
+
+        //v2 PSBTs need an input count
+        let mut missing_count = v2.clone();
+        let position = missing_count.windows(3).position(|x| x == [0x01, PSBT_GLOBAL_INPUT_COUNT, 0x01]).unwrap();
+        missing_count.drain(position..position + 4);
+        assert!(matches!(Psbt::from_slice(&missing_count), Err(PsbtErr::MissingField(PSBT_GLOBAL_INPUT_COUNT))));
+
+        //v0 PSBTs cannot have v2 fields
+        let mut v0 = Psbt::from_base64(P2PKH_PSBT).unwrap().serialize().unwrap();
+        v0.splice(5..5, vec![0x01, PSBT_GLOBAL_TX_VERSION, 0x04, 0x02, 0x00, 0x00, 0x00]);
+        assert!(matches!(Psbt::from_slice(&v0), Err(PsbtErr::FieldNotAllowed(PSBT_GLOBAL_TX_VERSION))));
+
+        //Inputs and outputs cannot be added to v0 PSBTs
+        let mut v0 = Psbt::from_base64(P2PKH_PSBT).unwrap();
+        assert!(matches!(v0.add_input(p2wpkh_input(&key(9), 1)), Err(PsbtErr::UnsupportedVersion(0))));
+    }
+}
@@ -0,0 +1,167 @@
+/*
+    Per-output map of a PSBT
+*/
+use std::collections::BTreeMap;
+use crate::{
+    schnorrsig,
+    tx::Script
+};
+use super::{
+    PsbtErr,
+    write_pair,
+    write_unknown,
+    input::{
+        no_key_data,
+        merge
+    }
+};
+
+//Output key types
+pub const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+pub const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+pub const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+pub const PSBT_OUT_AMOUNT: u8 = 0x03;
+pub const PSBT_OUT_SCRIPT: u8 = 0x04;
+pub const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+pub const PSBT_OUT_TAP_TREE: u8 = 0x06;
+pub const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
+
+#[derive(Debug, Clone)]
+pub struct PsbtOutput {
+    pub amount: u64,
+    pub script: Script,                                     //scriptPubKey of the output
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, Vec<u8>>,       //Public key -> fingerprint and derivation path
+    pub tap_internal_key: Option<schnorrsig::PublicKey>,
+    pub tap_tree: Option<Vec<u8>>,                          //Serialized script tree
+    pub tap_bip32_derivation: BTreeMap<Vec<u8>, Vec<u8>>,   //X-only key -> leaf hashes, fingerprint and derivation path
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>                 //Unknown and proprietary key-value pairs
+}
+
+impl PsbtOutput {
+    pub fn new(amount: u64, script: Script) -> Self {
+        Self {
+            amount,
+            script,
+            redeem_script: None,
+            witness_script: None,
+            bip32_derivation: BTreeMap::new(),
+            tap_internal_key: None,
+            tap_tree: None,
+            tap_bip32_derivation: BTreeMap::new(),
+            unknown: BTreeMap::new()
+        }
+    }
+
+    /**
+        Add any data from another map of the same output that this one is missing
+    */
+    pub(crate) fn combine(&mut self, other: &PsbtOutput) {
+        if self.redeem_script.is_none() { self.redeem_script = other.redeem_script.clone() }
+        if self.witness_script.is_none() { self.witness_script = other.witness_script.clone() }
+        if self.tap_internal_key.is_none() { self.tap_internal_key = other.tap_internal_key }
+        if self.tap_tree.is_none() { self.tap_tree = other.tap_tree.clone() }
+
+        merge(&mut self.bip32_derivation, &other.bip32_derivation);
+        merge(&mut self.tap_bip32_derivation, &other.tap_bip32_derivation);
+        merge(&mut self.unknown, &other.unknown);
+    }
+
+    /**
+        Set the fields of the output from the key-value pairs of its map.
+        Version 2 outputs must have the amount and script in their map.
+    */
+    pub(crate) fn read_pairs(&mut self, pairs: Vec<(Vec<u8>, Vec<u8>)>, version: u32) -> Result<(), PsbtErr> {
+        if version == 2 {
+            for key_type in [PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT].iter() {
+                if !pairs.iter().any(|(k, _)| k == &vec![*key_type]) {
+                    return Err(PsbtErr::MissingField(*key_type))
+                }
+            }
+        }
+
+        for (key, value) in pairs {
+            let key_type = key[0];
+            let key_data = &key[1..];
+
+            //Transaction fields are only stored in the output map in version 2
+            if version != 2 && (key_type == PSBT_OUT_AMOUNT || key_type == PSBT_OUT_SCRIPT) {
+                return Err(PsbtErr::FieldNotAllowed(key_type))
+            }
+
+            match key_type {
+                PSBT_OUT_REDEEM_SCRIPT => {
+                    no_key_data(&key)?;
+                    self.redeem_script = Some(Script::new(value));
+                },
+                PSBT_OUT_WITNESS_SCRIPT => {
+                    no_key_data(&key)?;
+                    self.witness_script = Some(Script::new(value));
+                },
+                PSBT_OUT_BIP32_DERIVATION => {
+                    if key_data.len() != 33 && key_data.len() != 65 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    self.bip32_derivation.insert(key_data.to_vec(), value);
+                },
+                PSBT_OUT_AMOUNT => {
+                    no_key_data(&key)?;
+                    if value.len() != 8 { return Err(PsbtErr::InvalidValue(key_type)) }
+                    let mut amount = [0; 8];
+                    amount.copy_from_slice(&value);
+                    self.amount = u64::from_le_bytes(amount);
+                },
+                PSBT_OUT_SCRIPT => {
+                    no_key_data(&key)?;
+                    self.script = Script::new(value);
+                },
+                PSBT_OUT_TAP_INTERNAL_KEY => {
+                    no_key_data(&key)?;
+                    self.tap_internal_key = match schnorrsig::PublicKey::from_slice(&value) {
+                        Ok(x) => Some(x),
+                        Err(_) => return Err(PsbtErr::InvalidValue(key_type))
+                    };
+                },
+                PSBT_OUT_TAP_TREE => {
+                    no_key_data(&key)?;
+                    self.tap_tree = Some(value);
+                },
+                PSBT_OUT_TAP_BIP32_DERIVATION => {
+                    if key_data.len() != 32 { return Err(PsbtErr::InvalidKey(key_type)) }
+                    self.tap_bip32_derivation.insert(key_data.to_vec(), value);
+                },
+                _ => { self.unknown.insert(key, value); }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Write the key-value pairs of the output's map, without the separator
+    */
+    pub(crate) fn write_pairs(&self, bytes: &mut Vec<u8>, version: u32) {
+        if let Some(x) = &self.redeem_script {
+            write_pair(bytes, PSBT_OUT_REDEEM_SCRIPT, &[], &x.code);
+        }
+        if let Some(x) = &self.witness_script {
+            write_pair(bytes, PSBT_OUT_WITNESS_SCRIPT, &[], &x.code);
+        }
+        for (key, value) in &self.bip32_derivation {
+            write_pair(bytes, PSBT_OUT_BIP32_DERIVATION, key, value);
+        }
+        if version == 2 {
+            write_pair(bytes, PSBT_OUT_AMOUNT, &[], &self.amount.to_le_bytes());
+            write_pair(bytes, PSBT_OUT_SCRIPT, &[], &self.script.code);
+        }
+        if let Some(x) = &self.tap_internal_key {
+            write_pair(bytes, PSBT_OUT_TAP_INTERNAL_KEY, &[], &x.serialize());
+        }
+        if let Some(x) = &self.tap_tree {
+            write_pair(bytes, PSBT_OUT_TAP_TREE, &[], x);
+        }
+        for (key, value) in &self.tap_bip32_derivation {
+            write_pair(bytes, PSBT_OUT_TAP_BIP32_DERIVATION, key, value);
+        }
+        write_unknown(bytes, &self.unknown);
+    }
+}
@@ -0,0 +1,503 @@
+/*
+    Signer, Finalizer and Extractor roles of a PSBT.
+
+    Finalizing assumes that P2SH and P2WSH inputs are locked by multisig scripts
+    (the same assumption made by TxBuilder) and that tapscript leaves are either
+    CHECKSIG chains or CHECKSIGADD threshold scripts.
+*/
+use crate::{
+    PrivKey, PubKey, Key,
+    hash,
+    signature,
+    schnorrsig,
+    tx::{
+        Tx,
+        Output,
+        Script,
+        ScriptType,
+        SigHash,
+        SigningData,
+        Witness,
+        BuilderErr,
+        Instruction,
+        ScriptBuilder,
+        ScriptCodes,
+        taproot,
+        builder::hashpreimage
+    },
+    util::serialize::serialize_sig
+};
+use super::{
+    Psbt,
+    PsbtErr,
+    PsbtInput
+};
+
+impl Psbt {
+    /**
+        Sign an input with the keys in the signing data.
+
+        ECDSA signatures are added as partial signatures and Schnorr signatures are added as
+        Taproot key path or script path signatures. Scripts given in the signing data are stored
+        in the input so that other signers and the finalizer can use them.
+    */
+    pub fn sign_input(&mut self, index: usize, signing_data: &SigningData, sighash: SigHash) -> Result<(), PsbtErr> {
+        if index >= self.inputs.len() {
+            return Err(PsbtErr::InvalidInputIndex(index))
+        }
+
+        //A sighash type set by the creator must be used
+        if let Some(x) = self.inputs[index].sighash_type {
+            if x != sighash.clone() as u32 {
+                return Err(PsbtErr::SigHashMismatch(index))
+            }
+        }
+
+        let tx_copy: Tx = self.unsigned_tx()?;
+        let spent: Output = self.spent_output(index)?;
+        let script_type: ScriptType = spent.script_pub_key.determine_type();
+
+        //SigHash DEFAULT only exists for Taproot inputs
+        match (&script_type, &sighash) {
+            (ScriptType::P2TR, _) => { },
+            (_, SigHash::DEFAULT) => return Err(PsbtErr::Builder(BuilderErr::InvalidSigHash())),
+            _ => { }
+        }
+
+        match script_type {
+            ScriptType::P2PKH => {
+                self.require_non_witness_utxo(index)?;
+                check_key_hashes(&signing_data.keys, &spent.script_pub_key.code[3..23], index)?;
+                let hash_preimage = hashpreimage::legacy(&mut tx_copy.clone(), &sighash, index, &spent.script_pub_key)?;
+                self.add_partial_sigs(index, hash::sha256d(hash_preimage), &signing_data.keys, &sighash)
+            },
+            ScriptType::P2WPKH => {
+                check_key_hashes(&signing_data.keys, &spent.script_pub_key.code[2..22], index)?;
+                let script_code = hashpreimage::script_code(&spent.script_pub_key);
+                let hash_preimage = hashpreimage::segwit(&tx_copy, &sighash, index, &script_code, spent.value)?;
+                self.add_partial_sigs(index, hash::sha256d(hash_preimage), &signing_data.keys, &sighash)
+            },
+            ScriptType::P2SH => {
+                //Nested P2WSH signing data holds the witness script, so the redeem script is derived from it
+                let redeem_script: Script = match (&self.inputs[index].redeem_script, &signing_data.script) {
+                    (Some(x), _) => x.clone(),
+                    (None, Some(x)) if signing_data.force_segwit => Script::p2sh_p2wsh_redeem_script(x),
+                    (None, Some(x)) => x.clone(),
+                    (None, None) => return Err(PsbtErr::Builder(BuilderErr::RedeemScriptMissing()))
+                };
+                if hash::hash160(&redeem_script.code).to_vec() != spent.script_pub_key.code[2..22].to_vec() {
+                    return Err(PsbtErr::ScriptMismatch(index))
+                }
+                self.inputs[index].redeem_script = Some(redeem_script.clone());
+
+                let hash_preimage = match redeem_script.determine_type() {
+                    ScriptType::P2WPKH => {
+                        check_key_hashes(&signing_data.keys, &redeem_script.code[2..22], index)?;
+                        let script_code = hashpreimage::script_code(&redeem_script);
+                        hashpreimage::segwit(&tx_copy, &sighash, index, &script_code, spent.value)?
+                    },
+                    ScriptType::P2WSH => {
+                        let witness_script = self.witness_script(index, &redeem_script, signing_data)?;
+                        let script_code = hashpreimage::script_code(&witness_script);
+                        hashpreimage::segwit(&tx_copy, &sighash, index, &script_code, spent.value)?
+                    },
+                    _ => {
+                        self.require_non_witness_utxo(index)?;
+                        hashpreimage::legacy(&mut tx_copy.clone(), &sighash, index, &redeem_script)?
+                    }
+                };
+                self.add_partial_sigs(index, hash::sha256d(hash_preimage), &signing_data.keys, &sighash)
+            },
+            ScriptType::P2WSH => {
+                let witness_script = self.witness_script(index, &spent.script_pub_key, signing_data)?;
+                let script_code = hashpreimage::script_code(&witness_script);
+                let hash_preimage = hashpreimage::segwit(&tx_copy, &sighash, index, &script_code, spent.value)?;
+                self.add_partial_sigs(index, hash::sha256d(hash_preimage), &signing_data.keys, &sighash)
+            },
+            ScriptType::P2TR => self.sign_taproot(index, &tx_copy, &spent, signing_data, &sighash),
+            ScriptType::NonStandard => Err(PsbtErr::UnknownScriptType(index))
+        }?;
+
+        //Signatures in v2 PSBTs limit which inputs and outputs can be added afterwards
+        if self.version == 2 {
+            self.update_modifiable(&sighash);
+        }
+
+        Ok(())
+    }
+
+    /**
+        Legacy inputs must carry the full previous transaction (BIP-174).
+        The spent amount is not signed for these inputs, so a witness UTXO alone cannot be trusted.
+    */
+    fn require_non_witness_utxo(&self, index: usize) -> Result<(), PsbtErr> {
+        match self.inputs[index].non_witness_utxo {
+            Some(_) => Ok(()),
+            None => Err(PsbtErr::MissingUtxo(index))
+        }
+    }
+
+    /**
+        Get the witness script for a P2WSH or nested P2WSH input from the input or the signing data
+        and check that it matches the witness program being spent.
+    */
+    fn witness_script(&mut self, index: usize, program: &Script, signing_data: &SigningData) -> Result<Script, PsbtErr> {
+        let witness_script: Script = match (&self.inputs[index].witness_script, &signing_data.script) {
+            (Some(x), _) => x.clone(),
+            (None, Some(x)) => x.clone(),
+            (None, None) => return Err(PsbtErr::Builder(BuilderErr::RedeemScriptMissing()))
+        };
+        if hash::sha256(&witness_script.code).to_vec() != program.code[2..34].to_vec() {
+            return Err(PsbtErr::ScriptMismatch(index))
+        }
+        self.inputs[index].witness_script = Some(witness_script.clone());
+
+        Ok(witness_script)
+    }
+
+    /**
+        Sign the hash with each key and store the signatures by public key
+    */
+    fn add_partial_sigs(&mut self, index: usize, hash: [u8; 32], keys: &Vec<PrivKey>, sighash: &SigHash) -> Result<(), PsbtErr> {
+        let msg = match signature::new_msg(&hash) {
+            Ok(x) => x,
+            Err(_) => return Err(PsbtErr::Builder(BuilderErr::FailedToCreateMessageStruct()))
+        };
+
+        for key in keys {
+            let mut sig: Vec<u8> = serialize_sig(&signature::sign(&msg, &key.raw())).to_vec();
+            sig.push(sighash.clone() as u8);
+            let pubkey: Vec<u8> = PubKey::from_priv_key(key).as_bytes::<33>().to_vec();
+            self.inputs[index].partial_sigs.insert(pubkey, sig);
+        }
+
+        Ok(())
+    }
+
+    /**
+        Sign a Taproot input through the key path, or the script path if the signing data has a control block
+    */
+    fn sign_taproot(
+        &mut self,
+        index: usize,
+        tx_copy: &Tx,
+        spent: &Output,
+        signing_data: &SigningData,
+        sighash: &SigHash
+    ) -> Result<(), PsbtErr> {
+        let anyonecanpay: bool = match sighash {
+            SigHash::ALL_ANYONECANPAY | SigHash::NONE_ANYONECANPAY | SigHash::SINGLE_ANYONECANPAY => true,
+            _ => false
+        };
+        let mut prevouts: Vec<Output> = vec![];
+        for i in 0..self.inputs.len() {
+            if anyonecanpay && i != index {
+                prevouts.push(spent.clone());
+            } else {
+                prevouts.push(self.spent_output(i)?);
+            }
+        }
+        let output_key = match schnorrsig::PublicKey::from_slice(&spent.script_pub_key.code[2..]) {
+            Ok(x) => x,
+            Err(_) => return Err(PsbtErr::ScriptMismatch(index))
+        };
+
+        match (&signing_data.script, &signing_data.control_block) {
+            //Script path
+            (Some(script), Some(control_block)) => {
+                if !control_block.verify(script, &output_key) {
+                    return Err(PsbtErr::Builder(BuilderErr::InvalidControlBlock()))
+                }
+                let leaf_hash = taproot::tap_leaf_hash(control_block.leaf_version, script);
+                let hash_preimage = hashpreimage::taproot(tx_copy, sighash, index, &prevouts, &None, Some(leaf_hash))?;
+                let msg = match signature::new_msg(&hash::tagged_hash("TapSighash", hash_preimage)) {
+                    Ok(x) => x,
+                    Err(_) => return Err(PsbtErr::Builder(BuilderErr::FailedToCreateMessageStruct()))
+                };
+
+                for key in &signing_data.keys {
+                    let sig = signature::sign_schnorr(&msg, &taproot::keypair(key));
+                    let mut sig_key: Vec<u8> = taproot::internal_key(key).serialize().to_vec();
+                    sig_key.append(&mut leaf_hash.to_vec());
+                    self.inputs[index].tap_script_sigs.insert(sig_key, Witness::schnorr_sig_bytes(&sig, sighash));
+                }
+                self.inputs[index].tap_leaf_scripts.insert(control_block.serialize(), (script.clone(), control_block.leaf_version));
+                self.inputs[index].tap_internal_key = Some(control_block.internal_key);
+            },
+
+            //Key path
+            (_, None) => {
+                if signing_data.keys.len() != 1 {
+                    return Err(PsbtErr::Builder(BuilderErr::InvalidSigningData()))
+                }
+                let key: &PrivKey = &signing_data.keys[0];
+                let merkle_root = signing_data.merkle_root.or(self.inputs[index].tap_merkle_root);
+
+                //Check the key can spend the output before signing
+                let internal_key = taproot::internal_key(key);
+                match taproot::output_key(&internal_key, merkle_root) {
+                    Ok((x, _)) if x == output_key => { },
+                    _ => return Err(PsbtErr::ScriptMismatch(index))
+                }
+
+                let hash_preimage = hashpreimage::taproot(tx_copy, sighash, index, &prevouts, &None, None)?;
+                let msg = match signature::new_msg(&hash::tagged_hash("TapSighash", hash_preimage)) {
+                    Ok(x) => x,
+                    Err(_) => return Err(PsbtErr::Builder(BuilderErr::FailedToCreateMessageStruct()))
+                };
+                let keypair = match taproot::tweaked_keypair(key, merkle_root) {
+                    Ok(x) => x,
+                    Err(_) => return Err(PsbtErr::Builder(BuilderErr::FailedToTweakKey()))
+                };
+                let sig = signature::sign_schnorr(&msg, &keypair);
+
+                self.inputs[index].tap_key_sig = Some(Witness::schnorr_sig_bytes(&sig, sighash));
+                self.inputs[index].tap_internal_key = Some(internal_key);
+                self.inputs[index].tap_merkle_root = merkle_root;
+            },
+
+            (None, Some(_)) => return Err(PsbtErr::Builder(BuilderErr::RedeemScriptMissing()))
+        }
+
+        Ok(())
+    }
+
+    /**
+        Finalize every input that is not already finalized
+    */
+    pub fn finalize(&mut self) -> Result<(), PsbtErr> {
+        for i in 0..self.inputs.len() {
+            if !self.inputs[i].is_finalized() {
+                self.finalize_input(i)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Build the final scriptSig and witness of an input from its signatures and scripts.
+        Signing data that is no longer needed is removed from the input.
+    */
+    pub fn finalize_input(&mut self, index: usize) -> Result<(), PsbtErr> {
+        let spent: Output = self.spent_output(index)?;
+        let input: &PsbtInput = &self.inputs[index];
+
+        let (script_sig, witness): (Option<Script>, Option<Witness>) = match spent.script_pub_key.determine_type() {
+            ScriptType::P2PKH => {
+                let (pubkey, sig) = pkh_sig(input, &spent.script_pub_key.code[3..23], index)?;
+                let script_sig = ScriptBuilder::new().push_slice(&sig).push_slice(&pubkey).into_script();
+                (Some(script_sig), None)
+            },
+            ScriptType::P2WPKH => {
+                let (pubkey, sig) = pkh_sig(input, &spent.script_pub_key.code[2..22], index)?;
+                (None, Some(Witness::new(vec![Script::new(sig), Script::new(pubkey)])))
+            },
+            ScriptType::P2SH => {
+                let redeem_script: &Script = match &input.redeem_script {
+                    Some(x) => x,
+                    None => return Err(PsbtErr::CannotFinalize(index))
+                };
+                match redeem_script.determine_type() {
+                    ScriptType::P2WPKH => {
+                        let (pubkey, sig) = pkh_sig(input, &redeem_script.code[2..22], index)?;
+                        let script_sig = ScriptBuilder::new().push_slice(&redeem_script.code).into_script();
+                        (Some(script_sig), Some(Witness::new(vec![Script::new(sig), Script::new(pubkey)])))
+                    },
+                    ScriptType::P2WSH => {
+                        let witness = multisig_witness(input, index)?;
+                        let script_sig = ScriptBuilder::new().push_slice(&redeem_script.code).into_script();
+                        (Some(script_sig), Some(witness))
+                    },
+                    _ => {
+                        //OP_0 is pushed first due to the off by one bug in CHECKMULTISIG
+                        let mut script_sig = ScriptBuilder::new().push_opcode(ScriptCodes::OP_0);
+                        for sig in multisig_sigs(input, redeem_script, index)? {
+                            script_sig = script_sig.push_slice(&sig);
+                        }
+                        (Some(script_sig.push_slice(&redeem_script.code).into_script()), None)
+                    }
+                }
+            },
+            ScriptType::P2WSH => (None, Some(multisig_witness(input, index)?)),
+            ScriptType::P2TR => (None, Some(taproot_witness(input, index)?)),
+            ScriptType::NonStandard => return Err(PsbtErr::UnknownScriptType(index))
+        };
+
+        let input: &mut PsbtInput = &mut self.inputs[index];
+        input.final_script_sig = script_sig;
+        input.final_script_witness = witness;
+        input.clear_signing_fields();
+
+        Ok(())
+    }
+
+    /**
+        Create the signed transaction from a PSBT where every input is finalized
+    */
+    pub fn extract_tx(&self) -> Result<Tx, PsbtErr> {
+        let mut tx: Tx = self.unsigned_tx()?;
+        let mut witnesses: Vec<Witness> = vec![];
+        for i in 0..self.inputs.len() {
+            if !self.inputs[i].is_finalized() {
+                return Err(PsbtErr::NotFinalized(i))
+            }
+
+            if let Some(x) = &self.inputs[i].final_script_sig {
+                tx.inputs[i].scriptSig = x.clone();
+                tx.inputs[i].scriptSig_size = x.len();
+            }
+            match &self.inputs[i].final_script_witness {
+                Some(x) => {
+                    tx.inputs[i].segwit = true;
+                    witnesses.push(x.clone());
+                },
+                None => witnesses.push(Witness::empty())
+            }
+        }
+
+        //Marker and flag are set the same way as in TxBuilder::build
+        if witnesses.iter().any(|x| x.len() > 0) {
+            tx.segwit = true;
+            tx.flag = Some(0x00);
+            tx.marker = Some(0x01);
+            tx.witness = Some(witnesses);
+        }
+
+        Ok(tx)
+    }
+}
+
+/**
+    Check that every signing key hashes to the public key hash being spent
+*/
+fn check_key_hashes(keys: &Vec<PrivKey>, pubkey_hash: &[u8], index: usize) -> Result<(), PsbtErr> {
+    for key in keys {
+        if hash::hash160(PubKey::from_priv_key(key).as_bytes::<33>()).to_vec() != pubkey_hash.to_vec() {
+            return Err(PsbtErr::ScriptMismatch(index))
+        }
+    }
+
+    Ok(())
+}
+
+/**
+    Find the signature made by the key with the given hash
+*/
+fn pkh_sig(input: &PsbtInput, pubkey_hash: &[u8], index: usize) -> Result<(Vec<u8>, Vec<u8>), PsbtErr> {
+    for (pubkey, sig) in &input.partial_sigs {
+        if hash::hash160(pubkey).to_vec() == pubkey_hash.to_vec() {
+            return Ok((pubkey.clone(), sig.clone()))
+        }
+    }
+
+    Err(PsbtErr::CannotFinalize(index))
+}
+
+/**
+    Get the signatures for a multisig script in the order of the keys in the script.
+    Errors if there are not enough signatures to satisfy the script.
+*/
+fn multisig_sigs(input: &PsbtInput, script: &Script, index: usize) -> Result<Vec<Vec<u8>>, PsbtErr> {
+    //M-of-N multisig is OP_M <keys> OP_N OP_CHECKMULTISIG
+    let code: &Vec<u8> = &script.code;
+    if code.len() < 3 || !(0x51..=0x60).contains(&code[0]) || code[code.len() - 1] != 0xae {
+        return Err(PsbtErr::CannotFinalize(index))
+    }
+    let m: usize = (code[0] - 0x50) as usize;
+
+    let instructions: Vec<Instruction> = match script.instructions() {
+        Ok(x) => x,
+        Err(_) => return Err(PsbtErr::CannotFinalize(index))
+    };
+    let sigs: Vec<Vec<u8>> = instructions.iter()
+        .filter_map(|x| x.push_bytes())
+        .filter_map(|data| input.partial_sigs.get(data))
+        .take(m)
+        .cloned()
+        .collect();
+    if sigs.len() < m {
+        return Err(PsbtErr::CannotFinalize(index))
+    }
+
+    Ok(sigs)
+}
+
+/**
+    Witness for P2WSH multisig inputs
+*/
+fn multisig_witness(input: &PsbtInput, index: usize) -> Result<Witness, PsbtErr> {
+    let witness_script: &Script = match &input.witness_script {
+        Some(x) => x,
+        None => return Err(PsbtErr::CannotFinalize(index))
+    };
+
+    let mut stack_items: Vec<Script> = vec![Script::new(vec![])];
+    for sig in multisig_sigs(input, witness_script, index)? {
+        stack_items.push(Script::new(sig));
+    }
+    stack_items.push(witness_script.clone());
+
+    Ok(Witness::new(stack_items))
+}
+
+/**
+    Witness for Taproot inputs.
+    The key path is used if there is a key path signature, otherwise the first
+    leaf that has enough signatures is used.
+*/
+fn taproot_witness(input: &PsbtInput, index: usize) -> Result<Witness, PsbtErr> {
+    if let Some(x) = &input.tap_key_sig {
+        return Ok(Witness::new(vec![Script::new(x.clone())]))
+    }
+
+    for (control_block, (script, leaf_version)) in &input.tap_leaf_scripts {
+        let leaf_hash = taproot::tap_leaf_hash(*leaf_version, script);
+        let instructions: Vec<Instruction> = match script.instructions() {
+            Ok(x) => x,
+            Err(_) => continue
+        };
+        let keys: Vec<Vec<u8>> = instructions.iter()
+            .filter_map(|x| x.push_bytes())
+            .filter(|data| data.len() == 32)
+            .cloned()
+            .collect();
+
+        //Threshold scripts use OP_CHECKSIGADD, end in OP_K OP_NUMEQUAL and can have missing signatures
+        let opcodes: Vec<u8> = instructions.iter().map(|x| x.opcode()).collect();
+        let n: usize = opcodes.len();
+        let threshold: usize = if opcodes.contains(&(ScriptCodes::OP_CHECKSIGADD as u8))
+            && n > 2
+            && opcodes[n - 1] == ScriptCodes::OP_NUMEQUAL as u8
+            && (ScriptCodes::OP_1 as u8..=ScriptCodes::OP_16 as u8).contains(&opcodes[n - 2])
+        {
+            (opcodes[n - 2] - ScriptCodes::OP_1 as u8 + 1) as usize
+        } else {
+            keys.len()
+        };
+
+        let sigs: Vec<Option<&Vec<u8>>> = keys.iter().map(|key| {
+            let mut sig_key: Vec<u8> = key.clone();
+            sig_key.append(&mut leaf_hash.to_vec());
+            input.tap_script_sigs.get(&sig_key)
+        }).collect();
+        if keys.is_empty() || sigs.iter().filter(|x| x.is_some()).count() < threshold {
+            continue
+        }
+
+        //The first key's signature needs to be at the top of the stack
+        let mut stack_items: Vec<Script> = vec![];
+        for sig in sigs.iter().rev() {
+            match sig {
+                Some(x) => stack_items.push(Script::new(x.to_vec())),
+                None => stack_items.push(Script::new(vec![]))
+            }
+        }
+        stack_items.push(script.clone());
+        stack_items.push(Script::new(control_block.clone()));
+
+        return Ok(Witness::new(stack_items))
+    }
+
+    Err(PsbtErr::CannotFinalize(index))
+}
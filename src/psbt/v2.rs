@@ -0,0 +1,228 @@
+/*
+    PSBT version 2 (BIP-370)
+
+    Version 2 PSBTs do not carry an unsigned transaction. The transaction fields
+    are stored in the global, input and output maps instead, so inputs and outputs
+    can be added after the PSBT is created for as long as the modifiable flags allow it.
+
+    Signers clear the flags in the same way TxBuilder refuses new inputs and outputs
+    once a committing SigHash has been used.
+*/
+use std::collections::BTreeMap;
+use crate::tx::{
+    SigHash,
+    TxBuilder
+};
+use crate::api::ChainSource;
+use super::{
+    Psbt,
+    PsbtErr,
+    PsbtInput,
+    PsbtOutput
+};
+
+//Global key types added in version 2
+pub const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+pub const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+pub const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+pub const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+pub const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+
+//Bits of the modifiable flags
+pub const TX_MODIFIABLE_INPUTS: u8 = 0x01;
+pub const TX_MODIFIABLE_OUTPUTS: u8 = 0x02;
+pub const TX_MODIFIABLE_SIGHASH_SINGLE: u8 = 0x04;
+
+//Locktimes below this value are block heights, the rest are timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500000000;
+
+impl Psbt {
+    /**
+        Create an empty version 2 PSBT that inputs and outputs can be added to.
+        The fallback locktime is used if no input requires a locktime.
+    */
+    pub fn new_v2(fallback_locktime: Option<u32>) -> Self {
+        Self {
+            version: 2,
+            tx_version: 2,
+            fallback_locktime,
+            tx_modifiable: TX_MODIFIABLE_INPUTS | TX_MODIFIABLE_OUTPUTS,
+            xpubs: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: vec![],
+            outputs: vec![]
+        }
+    }
+
+    /**
+        Create a version 2 PSBT from a TxBuilder.
+        Inputs already signed in the builder clear the modifiable flags according to their SigHash.
+    */
+    pub fn from_builder_v2<C: ChainSource>(builder: &mut TxBuilder<C>) -> Result<Self, PsbtErr> {
+        let mut psbt = Self::from_builder(builder)?;
+        psbt.version = 2;
+        psbt.tx_modifiable = TX_MODIFIABLE_INPUTS | TX_MODIFIABLE_OUTPUTS;
+        for sighash in builder.sighashes.iter().flatten() {
+            psbt.update_modifiable(sighash);
+        }
+
+        Ok(psbt)
+    }
+
+    pub fn inputs_modifiable(&self) -> bool {
+        self.tx_modifiable & TX_MODIFIABLE_INPUTS != 0
+    }
+
+    pub fn outputs_modifiable(&self) -> bool {
+        self.tx_modifiable & TX_MODIFIABLE_OUTPUTS != 0
+    }
+
+    /**
+        Returns true if an input has been signed with SIGHASH_SINGLE
+    */
+    pub fn has_sighash_single(&self) -> bool {
+        self.tx_modifiable & TX_MODIFIABLE_SIGHASH_SINGLE != 0
+    }
+
+    /**
+        Add an input to a version 2 PSBT.
+        Inputs cannot be added on their own once an input is signed with SIGHASH_SINGLE,
+        use add_input_output_pair instead.
+    */
+    pub fn add_input(&mut self, input: PsbtInput) -> Result<(), PsbtErr> {
+        if self.version != 2 { return Err(PsbtErr::UnsupportedVersion(self.version)) }
+        if !self.inputs_modifiable() || self.has_sighash_single() {
+            return Err(PsbtErr::NotModifiable())
+        }
+
+        self.push_input(input)
+    }
+
+    /**
+        Add an output to a version 2 PSBT.
+        Outputs cannot be added on their own once an input is signed with SIGHASH_SINGLE,
+        use add_input_output_pair instead.
+    */
+    pub fn add_output(&mut self, output: PsbtOutput) -> Result<(), PsbtErr> {
+        if self.version != 2 { return Err(PsbtErr::UnsupportedVersion(self.version)) }
+        if !self.outputs_modifiable() || self.has_sighash_single() {
+            return Err(PsbtErr::NotModifiable())
+        }
+        self.outputs.push(output);
+
+        Ok(())
+    }
+
+    /**
+        Add an input and an output at the same index.
+        Inputs signed with SIGHASH_SINGLE commit to the output at their own index, so
+        after such a signature inputs and outputs can only be added this way.
+        Both flags must still be set as other signatures may commit to every input or output.
+    */
+    pub fn add_input_output_pair(&mut self, input: PsbtInput, output: PsbtOutput) -> Result<(), PsbtErr> {
+        if self.version != 2 { return Err(PsbtErr::UnsupportedVersion(self.version)) }
+        if !self.inputs_modifiable() || !self.outputs_modifiable() {
+            return Err(PsbtErr::NotModifiable())
+        }
+        if self.inputs.len() != self.outputs.len() {
+            return Err(PsbtErr::NotModifiable())
+        }
+
+        self.push_input(input)?;
+        self.outputs.push(output);
+
+        Ok(())
+    }
+
+    /**
+        Add an input if its locktime requirement is compatible with the other inputs
+        and does not change the locktime already committed to by a signature.
+    */
+    fn push_input(&mut self, input: PsbtInput) -> Result<(), PsbtErr> {
+        let locktime: u32 = self.locktime()?;
+        self.inputs.push(input);
+
+        let new_locktime = match self.locktime() {
+            Ok(x) => x,
+            Err(x) => {
+                self.inputs.pop();
+                return Err(x)
+            }
+        };
+        if new_locktime != locktime && self.inputs.iter().any(|x| x.has_signatures()) {
+            self.inputs.pop();
+            return Err(PsbtErr::LocktimeConflict())
+        }
+
+        Ok(())
+    }
+
+    /**
+        Determine the locktime of the transaction.
+
+        If no input requires a locktime, the fallback locktime is used (or 0).
+        Otherwise the highest required block height is used if every input with a requirement
+        accepts a height, or the highest required time if they all accept a time.
+    */
+    pub fn locktime(&self) -> Result<u32, PsbtErr> {
+        let constrained: Vec<&PsbtInput> = self.inputs.iter()
+            .filter(|x| x.required_time_locktime.is_some() || x.required_height_locktime.is_some())
+            .collect();
+        if constrained.is_empty() {
+            return Ok(self.fallback_locktime.unwrap_or(0))
+        }
+
+        if constrained.iter().all(|x| x.required_height_locktime.is_some()) {
+            return Ok(constrained.iter().filter_map(|x| x.required_height_locktime).max().unwrap_or(0))
+        }
+        if constrained.iter().all(|x| x.required_time_locktime.is_some()) {
+            return Ok(constrained.iter().filter_map(|x| x.required_time_locktime).max().unwrap_or(0))
+        }
+
+        Err(PsbtErr::LocktimeConflict())
+    }
+
+    /**
+        Clear the modifiable flags that a signature with the given SigHash commits to.
+        SIGHASH_SINGLE only commits to the output at its own index, so outputs stay
+        modifiable but can only be added in pairs with inputs.
+    */
+    pub(crate) fn update_modifiable(&mut self, sighash: &SigHash) {
+        if !sighash.allows_new_inputs() {
+            self.tx_modifiable &= !TX_MODIFIABLE_INPUTS;
+        }
+        match sighash {
+            SigHash::NONE | SigHash::NONE_ANYONECANPAY => { },
+            SigHash::SINGLE | SigHash::SINGLE_ANYONECANPAY => self.tx_modifiable |= TX_MODIFIABLE_SIGHASH_SINGLE,
+            _ => self.tx_modifiable &= !TX_MODIFIABLE_OUTPUTS
+        }
+    }
+
+    /**
+        Convert into a version 0 PSBT once no more inputs or outputs will be added
+    */
+    pub fn to_v0(&self) -> Result<Self, PsbtErr> {
+        let mut psbt = self.clone();
+        psbt.fallback_locktime = Some(self.locktime()?);
+        psbt.version = 0;
+        psbt.tx_modifiable = 0;
+        for input in &mut psbt.inputs {
+            input.required_time_locktime = None;
+            input.required_height_locktime = None;
+        }
+
+        Ok(psbt)
+    }
+
+    /**
+        Convert into a version 2 PSBT.
+        No inputs or outputs can be added to the result.
+    */
+    pub fn to_v2(&self) -> Self {
+        let mut psbt = self.clone();
+        psbt.version = 2;
+        psbt.tx_modifiable = 0;
+
+        psbt
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
     Signature, Message, Secp256k1,
-    SecretKey, PublicKey
+    SecretKey, PublicKey,
+    schnorrsig
 };
+use rand::Rng;
 
 #[derive(Debug)]
 pub enum SigError {
@@ -35,4 +37,22 @@ pub fn verify(sig: &Signature, msg: &Message, pk: &PublicKey) -> Result<(), SigE
         Ok(_) => Ok(()),
         Err(_) => Err(SigError::CantVerify())
     }
+}
+
+/**
+    Create a BIP-340 Schnorr signature from a message and key pair
+*/
+pub fn sign_schnorr(msg: &Message, keypair: &schnorrsig::KeyPair) -> schnorrsig::Signature {
+    let aux_rand: [u8; 32] = rand::thread_rng().gen();
+    Secp256k1::signing_only().schnorrsig_sign_with_aux_rand(msg, keypair, &aux_rand)
+}
+
+/**
+    Verify a BIP-340 Schnorr signature using the x-only public key
+*/
+pub fn verify_schnorr(sig: &schnorrsig::Signature, msg: &Message, pk: &schnorrsig::PublicKey) -> Result<(), SigError> {
+    match Secp256k1::new().schnorrsig_verify(sig, msg, pk) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(SigError::CantVerify())
+    }
 }
\ No newline at end of file
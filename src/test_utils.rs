@@ -0,0 +1,39 @@
+/*
+    Fixtures shared by the unit tests
+*/
+use crate::{
+    PrivKey, PubKey, Key,
+    hash,
+    tx::{
+        Output,
+        Address,
+        Payload
+    },
+    util::Network
+};
+
+//Previous txid for inputs whose parent transaction does not matter to the test
+pub const PREV_TXID: &str = "36e336b364abaf48b46c415903b1d93c7a740d7a3bde1691e30fec3d7a180245";
+
+/**
+    Private key with every byte set to the given value
+*/
+pub fn key(byte: u8) -> PrivKey {
+    PrivKey::from_slice(&[byte; 32]).unwrap()
+}
+
+/**
+    Native P2WPKH address of the key
+*/
+pub fn p2wpkh_address(key: &PrivKey) -> Address {
+    let pubkey_hash = hash::hash160(PubKey::from_priv_key(key).as_bytes::<33>());
+
+    Address { network: Network::Testnet, payload: Payload::WitnessProgram(0, pubkey_hash.to_vec()) }
+}
+
+/**
+    Output of the given value locked to the native P2WPKH address of the key
+*/
+pub fn p2wpkh_output(key: &PrivKey, value: u64) -> Output {
+    Output::from_address(&p2wpkh_address(key), value)
+}
@@ -0,0 +1,334 @@
+/*
+    Addresses.
+
+    Base58check addresses (P2PKH and P2SH) are checked against their checksum and
+    version byte. Bech32 and Bech32m addresses (SegWit) are checked against their
+    checksum, human readable part, witness version and program length.
+
+    Addresses are parsed for a network and an address for another network is an error
+    so that testnet outputs cannot end up in a mainnet transaction by mistake.
+
+    Standard scriptPubKeys can be turned back into addresses to show where the outputs
+    of a decoded transaction pay to.
+*/
+use std::fmt;
+use crate::{
+    hash,
+    util::{
+        Network,
+        bech32::{
+            self,
+            Bech32Err
+        }
+    },
+    tx::{
+        Script,
+        ScriptBuilder,
+        ScriptCodes
+    }
+};
+
+//Base58check version bytes
+const PUBKEY_HASH_MAINNET: u8 = 0x00;
+const SCRIPT_HASH_MAINNET: u8 = 0x05;
+const PUBKEY_HASH_TESTNET: u8 = 0x6f;
+const SCRIPT_HASH_TESTNET: u8 = 0xc4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressErr {
+    InvalidBase58(),
+    InvalidLength(usize),           //Length of the decoded base58 address
+    InvalidChecksum(),
+    UnknownVersion(u8),             //Base58 version byte
+    UnknownHrp(String),             //Bech32 human readable part
+    Bech32(Bech32Err),
+    NetworkMismatch(),
+    NonStandardScript(),
+    WrongType()                     //Address is valid but not of the type asked for
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    PubkeyHash([u8; 20]),
+    ScriptHash([u8; 20]),
+    WitnessProgram(u8, Vec<u8>)     //Witness version and program
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub network: Network,
+    pub payload: Payload
+}
+
+impl Address {
+    /**
+        Parse an address that must be for the given network
+    */
+    pub fn parse(address: &str, network: &Network) -> Result<Self, AddressErr> {
+        let parsed: Self = Self::parse_any(address)?;
+        if &parsed.network != network {
+            return Err(AddressErr::NetworkMismatch())
+        }
+
+        Ok(parsed)
+    }
+
+    /**
+        Parse an address for any network
+    */
+    pub fn parse_any(address: &str) -> Result<Self, AddressErr> {
+        //Bech32 addresses are the only ones with the separator after a known human readable part
+        let lowercase: String = address.to_lowercase();
+        if lowercase.starts_with("bc1") || lowercase.starts_with("tb1") {
+            return Self::parse_segwit(address)
+        }
+
+        Self::parse_base58(address)
+    }
+
+    fn parse_base58(address: &str) -> Result<Self, AddressErr> {
+        let bytes: Vec<u8> = match bs58::decode(address).into_vec() {
+            Ok(x) => x,
+            Err(_) => return Err(AddressErr::InvalidBase58())
+        };
+        if bytes.len() != 25 {
+            return Err(AddressErr::InvalidLength(bytes.len()))
+        }
+
+        //The checksum is the first 4 bytes of the SHA256D of the version and hash
+        if hash::sha256d(&bytes[..21])[..4] != bytes[21..] {
+            return Err(AddressErr::InvalidChecksum())
+        }
+
+        let mut hash: [u8; 20] = [0; 20];
+        hash.copy_from_slice(&bytes[1..21]);
+        let (network, payload) = match bytes[0] {
+            PUBKEY_HASH_MAINNET => (Network::Bitcoin, Payload::PubkeyHash(hash)),
+            SCRIPT_HASH_MAINNET => (Network::Bitcoin, Payload::ScriptHash(hash)),
+            PUBKEY_HASH_TESTNET => (Network::Testnet, Payload::PubkeyHash(hash)),
+            SCRIPT_HASH_TESTNET => (Network::Testnet, Payload::ScriptHash(hash)),
+            x => return Err(AddressErr::UnknownVersion(x))
+        };
+
+        Ok(Self {
+            network,
+            payload
+        })
+    }
+
+    fn parse_segwit(address: &str) -> Result<Self, AddressErr> {
+        let (hrp, version, program) = match bech32::decode_segwit(address) {
+            Ok(x) => x,
+            Err(x) => return Err(AddressErr::Bech32(x))
+        };
+        let network: Network = match hrp.as_str() {
+            "bc" => Network::Bitcoin,
+            "tb" => Network::Testnet,
+            _ => return Err(AddressErr::UnknownHrp(hrp))
+        };
+
+        Ok(Self {
+            network,
+            payload: Payload::WitnessProgram(version, program)
+        })
+    }
+
+    /**
+        Get the address a scriptPubKey pays to. Errors if the script is not P2PKH, P2SH
+        or a witness program.
+    */
+    pub fn from_script(script_pub_key: &Script, network: &Network) -> Result<Self, AddressErr> {
+        let code: &Vec<u8> = &script_pub_key.code;
+        let payload: Payload = match code.len() {
+            //OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+            25 if code[..3] == [0x76, 0xa9, 0x14] && code[23..] == [0x88, 0xac] => {
+                let mut hash: [u8; 20] = [0; 20];
+                hash.copy_from_slice(&code[3..23]);
+                Payload::PubkeyHash(hash)
+            },
+            //OP_HASH160 <20 bytes> OP_EQUAL
+            23 if code[..2] == [0xa9, 0x14] && code[22] == 0x87 => {
+                let mut hash: [u8; 20] = [0; 20];
+                hash.copy_from_slice(&code[2..22]);
+                Payload::ScriptHash(hash)
+            },
+            //OP_0 to OP_16 followed by a single push of 2 to 40 bytes
+            4..=42 if code[1] as usize == code.len() - 2 => {
+                let version: u8 = match code[0] {
+                    0x00 => 0,
+                    0x51..=0x60 => code[0] - 0x50,
+                    _ => return Err(AddressErr::NonStandardScript())
+                };
+                let program: Vec<u8> = code[2..].to_vec();
+                if version == 0 && program.len() != 20 && program.len() != 32 {
+                    return Err(AddressErr::NonStandardScript())
+                }
+                Payload::WitnessProgram(version, program)
+            },
+            _ => return Err(AddressErr::NonStandardScript())
+        };
+
+        Ok(Self {
+            network: network.clone(),
+            payload
+        })
+    }
+
+    /**
+        The scriptPubKey that locks outputs to this address
+    */
+    pub fn script_pub_key(&self) -> Script {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => ScriptBuilder::new()
+                .push_opcode(ScriptCodes::OP_DUP)
+                .push_opcode(ScriptCodes::OP_HASH160)
+                .push_slice(hash)
+                .push_opcode(ScriptCodes::OP_EQUALVERIFY)
+                .push_opcode(ScriptCodes::OP_CHECKSIG)
+                .into_script(),
+            Payload::ScriptHash(hash) => ScriptBuilder::new()
+                .push_opcode(ScriptCodes::OP_HASH160)
+                .push_slice(hash)
+                .push_opcode(ScriptCodes::OP_EQUAL)
+                .into_script(),
+
+            //Witness version 0 is OP_0 and versions 1-16 are OP_1 to OP_16
+            Payload::WitnessProgram(version, program) => ScriptBuilder::new()
+                .push_int(*version as i64)
+                .push_slice(program)
+                .into_script()
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (version, hash) = match (&self.payload, &self.network) {
+            (Payload::PubkeyHash(x), Network::Bitcoin) => (PUBKEY_HASH_MAINNET, x),
+            (Payload::ScriptHash(x), Network::Bitcoin) => (SCRIPT_HASH_MAINNET, x),
+            (Payload::PubkeyHash(x), Network::Testnet) => (PUBKEY_HASH_TESTNET, x),
+            (Payload::ScriptHash(x), Network::Testnet) => (SCRIPT_HASH_TESTNET, x),
+            (Payload::WitnessProgram(version, program), network) => {
+                let hrp: &str = match network {
+                    Network::Bitcoin => "bc",
+                    Network::Testnet => "tb"
+                };
+                return match bech32::encode_segwit(hrp, *version, program) {
+                    Ok(x) => write!(f, "{}", x),
+                    Err(_) => Err(fmt::Error)
+                }
+            }
+        };
+
+        //Version byte, hash and the first 4 bytes of the SHA256D of both
+        let mut bytes: Vec<u8> = vec![version];
+        bytes.extend_from_slice(hash);
+        let checksum = hash::sha256d(&bytes);
+        bytes.extend_from_slice(&checksum[..4]);
+
+        write!(f, "{}", bs58::encode(bytes).into_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes::encode_02x;
+
+    #[test]
+    fn base58_addresses() {
+        let address = Address::parse("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", &Network::Bitcoin).unwrap();
+        assert_eq!(address.payload, Payload::PubkeyHash(crate::util::bytes::try_into(crate::util::bytes::decode_02x("77bff20c60e522dfaa3350c39b030a5d004e839a"))));
+        assert_eq!(encode_02x(&address.script_pub_key().code), "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac");
+
+        let address = Address::parse("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", &Network::Bitcoin).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87");
+
+        let address = Address::parse("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", &Network::Testnet).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "76a91482c0b4af9fd90ed137194b4946b0b86d44a47f8c88ac");
+
+        let address = Address::parse("2MtW3B2Zddk5waMcoH86QjM4cz4eZFnZwRv", &Network::Testnet).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "a9140dc5566687477efc32c1da11bf686ef3b1e538e587");
+
+        //Bad checksum, unknown version, bad characters and the wrong network
+        assert_eq!(Address::parse_any("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN1"), Err(AddressErr::InvalidChecksum()));
+        assert_eq!(Address::parse_any("LW98ceYNxYki9e9QxDACLn82TtVEPm4qmy"), Err(AddressErr::UnknownVersion(0x30)));
+        assert_eq!(Address::parse_any("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN0"), Err(AddressErr::InvalidBase58()));
+        assert_eq!(Address::parse_any("1BvBMSEYstWetqTFn5Au4m4GFg"), Err(AddressErr::InvalidLength(20)));
+        assert_eq!(Address::parse("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", &Network::Bitcoin), Err(AddressErr::NetworkMismatch()));
+    }
+
+    #[test]
+    fn segwit_addresses() {
+        let address = Address::parse("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", &Network::Bitcoin).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "0014751e76e8199196d454941c45d1b3a323f1433bd6");
+
+        let address = Address::parse("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", &Network::Testnet).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262");
+
+        let address = Address::parse("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", &Network::Bitcoin).unwrap();
+        assert_eq!(encode_02x(&address.script_pub_key().code), "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+
+        //Future witness versions are allowed
+        let address = Address::parse_any("bc1sw50qgdz25j").unwrap();
+        assert_eq!(address.payload, Payload::WitnessProgram(16, vec![0x75, 0x1e]));
+        assert_eq!(encode_02x(&address.script_pub_key().code), "6002751e");
+
+        //Version 0 with Bech32m, an unknown human readable part and the wrong network
+        assert_eq!(Address::parse_any("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47"), Err(AddressErr::Bech32(Bech32Err::WrongVariant())));
+        assert!(Address::parse_any("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").is_err());
+        assert_eq!(Address::parse("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", &Network::Bitcoin), Err(AddressErr::NetworkMismatch()));
+    }
+
+    #[test]
+    fn script_to_address() {
+        let addresses = [
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", Network::Bitcoin),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", Network::Bitcoin),
+            ("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", Network::Testnet),
+            ("2MtW3B2Zddk5waMcoH86QjM4cz4eZFnZwRv", Network::Testnet),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Bitcoin),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", Network::Testnet),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", Network::Bitcoin),
+            ("bc1sw50qgdz25j", Network::Bitcoin),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", Network::Bitcoin)
+        ];
+        for (address, network) in addresses.iter() {
+            let script_pub_key = Address::parse(address, network).unwrap().script_pub_key();
+            let rendered = Address::from_script(&script_pub_key, network).unwrap();
+            assert_eq!(&rendered.to_string(), address);
+            assert_eq!(&script_pub_key.to_address(network).unwrap(), address);
+        }
+
+        //The same script renders differently on each network
+        let script_pub_key = Script::p2pkh_locking("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", &Network::Testnet).unwrap();
+        assert_eq!(script_pub_key.to_address(&Network::Bitcoin).unwrap(), "1CvMhNaRn11nvpUFLMTL1y6hJoSSiCVtMh");
+
+        //Scripts without an address
+        let non_standard = [
+            "6a0461626364",                                                           //OP_RETURN
+            "0014751e76e8199196d454941c45d1b3a323f1433bd600",                         //Trailing byte
+            "00150000000000000000000000000000000000000000ff",                         //21 byte version 0 program
+            "5101ff",                                                                 //1 byte program
+            "5121030000000000000000000000000000000000000000000000000000000000000051ae" //1-of-1 multisig
+        ];
+        for code in non_standard.iter() {
+            let script = Script::new(crate::util::bytes::decode_02x(code));
+            assert_eq!(Address::from_script(&script, &Network::Bitcoin), Err(AddressErr::NonStandardScript()));
+        }
+    }
+
+    #[test]
+    fn locking_constructors() {
+        assert_eq!(encode_02x(&Script::p2sh_locking("2MtW3B2Zddk5waMcoH86QjM4cz4eZFnZwRv", &Network::Testnet).unwrap().code), "a9140dc5566687477efc32c1da11bf686ef3b1e538e587");
+        assert_eq!(encode_02x(&Script::segwit_locking("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", &Network::Testnet).unwrap().code), "001491c6c31a62b37a16447ccf44224eb1ab78ca0299");
+        assert_eq!(crate::tx::Output::new("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 1000, &Network::Testnet).unwrap().value, 1000);
+
+        //The wrong network, the wrong type and an invalid address are errors rather than panics
+        assert_eq!(Script::p2pkh_locking("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", &Network::Bitcoin).err(), Some(AddressErr::NetworkMismatch()));
+        assert_eq!(Script::p2sh_locking("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", &Network::Testnet).err(), Some(AddressErr::WrongType()));
+        assert_eq!(Script::segwit_locking("2MtW3B2Zddk5waMcoH86QjM4cz4eZFnZwRv", &Network::Testnet).err(), Some(AddressErr::WrongType()));
+        assert!(crate::tx::Output::new("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 1000, &Network::Bitcoin).is_err());
+        assert!(crate::tx::Output::new("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN1", 1000, &Network::Bitcoin).is_err());
+    }
+}
@@ -0,0 +1,521 @@
+/*
+    Coin selection for funding a transaction from a set of UTXOs.
+
+    UTXOs are compared by their effective value, which is their value minus the fee
+    needed to spend them at the target feerate. Branch and Bound is tried first to find
+    a set of inputs that pays for the outputs closely enough that no change output is
+    needed. If there is no such set the knapsack solver is used and the leftover value
+    goes to a change output.
+
+    Selections are scored by their waste:
+        waste = sum(input fee - input fee at long term feerate) + cost of change or excess
+    where the cost of change is the fee to create the change output now and spend it later,
+    and the excess is the value dropped to fees when there is no change output.
+*/
+use rand::Rng;
+use crate::{
+    util::serialize::Serialize,
+    util::varint::VarInt,
+    tx::{
+        Output,
+        Script,
+        FeeRate,
+        SigningData
+    }
+};
+use super::{
+    BuilderErr,
+    estimate
+};
+
+//Branch and Bound gives up after this many tries and keeps the best selection found
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+//Rounds of random subsets tried by the knapsack solver
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+//Change outputs are P2WPKH unless set otherwise
+const DEFAULT_CHANGE_OUTPUT_WEIGHT: u64 = 31 * 4;
+const DEFAULT_CHANGE_SPEND_WEIGHT: u64 = 41 * 4 + 109;
+const DEFAULT_CHANGE_DUST_LIMIT: u64 = 294;
+
+#[derive(Debug, Clone)]
+pub struct Utxo {
+    pub txid: String,       //Txid of the transaction creating the output
+    pub vout: u32,          //Index of the output
+    pub output: Output,     //The output itself
+    pub weight: u64         //Weight of the input spending the output once signed
+}
+
+impl Utxo {
+    /**
+        Create a UTXO with the weight of spending it with the given signing data
+    */
+    pub fn new(txid: &str, vout: u32, output: Output, signing_data: &SigningData) -> Result<Self, BuilderErr> {
+        let weight: u64 = estimate::input_weight(&output.script_pub_key, signing_data)?;
+
+        Ok(Self::with_weight(txid, vout, output, weight))
+    }
+
+    /**
+        Create a UTXO with a known input weight
+    */
+    pub fn with_weight(txid: &str, vout: u32, output: Output, weight: u64) -> Self {
+        Self {
+            txid: txid.to_string(),
+            vout,
+            output,
+            weight
+        }
+    }
+
+    /**
+        Value of the UTXO after paying for the input spending it.
+        This is negative if the UTXO costs more to spend than it is worth.
+    */
+    pub fn effective_value(&self, feerate: FeeRate) -> i64 {
+        self.output.value as i64 - feerate.fee_for_weight(self.weight) as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionAlgorithm {
+    BranchAndBound,
+    Knapsack
+}
+
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub utxos: Vec<Utxo>,                   //Selected UTXOs
+    pub algorithm: SelectionAlgorithm,      //Algorithm that found the selection
+    pub fee: u64,                           //Fee paid by the transaction
+    pub change: Option<u64>,                //Value of the change output if one is needed
+    pub waste: i64                          //Waste of the selection
+}
+
+impl Selection {
+    /**
+        Total value of the selected UTXOs
+    */
+    pub fn value(&self) -> u64 {
+        self.utxos.iter().map(|x| x.output.value).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoinSelector {
+    pub feerate: FeeRate,               //Feerate the transaction will pay
+    pub long_term_feerate: FeeRate,     //Feerate UTXOs are expected to be spent at in the future
+    pub change_output_weight: u64,      //Weight of the change output
+    pub change_spend_weight: u64,       //Weight of the input that will spend the change output
+    pub change_dust_limit: u64          //Smallest change output that will be created
+}
+
+impl CoinSelector {
+    /**
+        Create a selector for the given feerate.
+        The long term feerate is 10 sat/vB and the change output is assumed to be P2WPKH.
+    */
+    pub fn new(feerate: FeeRate) -> Self {
+        Self {
+            feerate,
+            long_term_feerate: FeeRate::from_sat_per_vb(10),
+            change_output_weight: DEFAULT_CHANGE_OUTPUT_WEIGHT,
+            change_spend_weight: DEFAULT_CHANGE_SPEND_WEIGHT,
+            change_dust_limit: DEFAULT_CHANGE_DUST_LIMIT
+        }
+    }
+
+    /**
+        Set the long term feerate used to calculate waste
+    */
+    pub fn with_long_term_feerate(mut self, feerate: FeeRate) -> Self {
+        self.long_term_feerate = feerate;
+        self
+    }
+
+    /**
+        Set the scriptPubKey of the change output.
+        The weight of spending it is estimated for single key outputs and assumed
+        to be the same as P2WPKH for anything else.
+    */
+    pub fn with_change_script(mut self, script_pub_key: &Script) -> Self {
+        let change: Output = Output {
+            value: 0,
+            script_pub_key: script_pub_key.clone()
+        };
+        self.change_output_weight = change.serialize().unwrap().len() as u64 * 4;
+        self.change_spend_weight = estimate::input_weight(script_pub_key, &SigningData::new(vec![], None)).unwrap_or(DEFAULT_CHANGE_SPEND_WEIGHT);
+        self.change_dust_limit = change.dust_limit();
+        self
+    }
+
+    /**
+        Fee to create the change output now and spend it later
+    */
+    pub fn cost_of_change(&self) -> u64 {
+        self.feerate.fee_for_weight(self.change_output_weight) + self.long_term_feerate.fee_for_weight(self.change_spend_weight)
+    }
+
+    /**
+        Select UTXOs to pay for the outputs and the fee.
+
+        Branch and Bound is tried first for a selection without change.
+        If none is found the knapsack solver selects UTXOs and the leftover value
+        is returned as change, or added to the fee if it would be dust.
+    */
+    pub fn select(&self, utxos: &Vec<Utxo>, outputs: &Vec<Output>) -> Result<Selection, BuilderErr> {
+        let output_value: u64 = outputs.iter().map(|x| x.value).sum();
+        let target: u64 = output_value + self.feerate.fee_for_weight(base_weight(outputs)?);
+
+        //UTXOs that cost more to spend than they are worth are never selected
+        let mut pool: Vec<&Utxo> = utxos.iter().filter(|x| x.effective_value(self.feerate) > 0).collect();
+        pool.sort_by_key(|x| std::cmp::Reverse(x.effective_value(self.feerate)));
+        let effective_values: Vec<u64> = pool.iter().map(|x| x.effective_value(self.feerate) as u64).collect();
+        let wastes: Vec<i64> = pool.iter().map(|x| self.input_waste(x)).collect();
+
+        let available: u64 = effective_values.iter().sum();
+        if available < target {
+            return Err(BuilderErr::InsufficientFunds(available, target))
+        }
+
+        let (indexes, algorithm) = match branch_and_bound(&effective_values, &wastes, target, self.cost_of_change(), self.feerate > self.long_term_feerate) {
+            Some(x) => (x, SelectionAlgorithm::BranchAndBound),
+            None => {
+                let min_change: u64 = self.feerate.fee_for_weight(self.change_output_weight) + self.change_dust_limit;
+                match knapsack(&effective_values, target, min_change) {
+                    Some(x) => (x, SelectionAlgorithm::Knapsack),
+                    None => return Err(BuilderErr::InsufficientFunds(available, target))
+                }
+            }
+        };
+
+        let selected: Vec<Utxo> = indexes.iter().map(|i| pool[*i].clone()).collect();
+        self.finish(selected, outputs, algorithm)
+    }
+
+    /**
+        Work out the fee, change and waste of a selection
+    */
+    fn finish(&self, utxos: Vec<Utxo>, outputs: &Vec<Output>, algorithm: SelectionAlgorithm) -> Result<Selection, BuilderErr> {
+        let output_value: u64 = outputs.iter().map(|x| x.value).sum();
+        let input_value: u64 = utxos.iter().map(|x| x.output.value).sum();
+        let input_weight: u64 = utxos.iter().map(|x| x.weight).sum();
+        let input_waste: i64 = utxos.iter().map(|x| self.input_waste(x)).sum();
+
+        let fee: u64 = self.feerate.fee_for_weight(base_weight(outputs)? + input_weight);
+        let excess: u64 = input_value - output_value - fee;
+
+        //Change is only made if it is worth more than the dust limit after paying for itself
+        let change_fee: u64 = self.feerate.fee_for_weight(self.change_output_weight);
+        let selection = match algorithm {
+            SelectionAlgorithm::Knapsack if excess >= change_fee + self.change_dust_limit => Selection {
+                utxos,
+                algorithm,
+                fee: fee + change_fee,
+                change: Some(excess - change_fee),
+                waste: input_waste + self.cost_of_change() as i64
+            },
+            _ => Selection {
+                utxos,
+                algorithm,
+                fee: fee + excess,
+                change: None,
+                waste: input_waste + excess as i64
+            }
+        };
+
+        Ok(selection)
+    }
+
+    /**
+        Fee paid to spend the UTXO now minus the fee to spend it at the long term feerate
+    */
+    fn input_waste(&self, utxo: &Utxo) -> i64 {
+        self.feerate.fee_for_weight(utxo.weight) as i64 - self.long_term_feerate.fee_for_weight(utxo.weight) as i64
+    }
+}
+
+/**
+    Weight of a transaction with the given outputs and no inputs.
+    The segwit marker and flag are always counted.
+*/
+fn base_weight(outputs: &Vec<Output>) -> Result<u64, BuilderErr> {
+    //Version, locktime, input count and output count
+    let mut size: usize = 4 + 4 + 1 + VarInt::from_usize(outputs.len()).unwrap().len();
+    for output in outputs {
+        size += match output.serialize() {
+            Ok(x) => x.len(),
+            Err(_) => return Err(BuilderErr::FailedToSerialize())
+        };
+    }
+
+    Ok(size as u64 * 4 + 2)
+}
+
+/**
+    Depth first search for the selection with the least waste that pays for the target
+    without exceeding it by more than the cost of change.
+
+    Values must be sorted from largest to smallest. Each UTXO is first included and then
+    excluded, and a branch is cut when it can no longer reach the target, when it overshoots
+    the target or, when the feerate is above the long term feerate, when its waste is already
+    higher than the best selection since adding more inputs will only increase it.
+*/
+fn branch_and_bound(values: &Vec<u64>, wastes: &Vec<i64>, target: u64, cost_of_change: u64, high_feerate: bool) -> Option<Vec<usize>> {
+    let mut available: u64 = values.iter().sum();
+    let mut current_value: u64 = 0;
+    let mut current_waste: i64 = 0;
+    let mut selection: Vec<usize> = vec![];
+    let mut best: Option<Vec<usize>> = None;
+    let mut best_waste: i64 = i64::MAX;
+
+    let mut index: usize = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack: bool = false;
+        if current_value + available < target ||
+           current_value > target + cost_of_change ||
+           (high_feerate && current_waste > best_waste) {
+            backtrack = true;
+        } else if current_value >= target {
+            //Found a solution. The excess is added to the waste since it goes to the fee.
+            let waste: i64 = current_waste + (current_value - target) as i64;
+            if waste <= best_waste {
+                best = Some(selection.clone());
+                best_waste = waste;
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            //Every branch has been searched
+            let last: usize = match selection.last() {
+                Some(x) => *x,
+                None => break
+            };
+
+            //Put the UTXOs after the last included one back into the lookahead
+            index -= 1;
+            while index > last {
+                available += values[index];
+                index -= 1;
+            }
+
+            //Exclude the last included UTXO and search that branch next
+            current_value -= values[index];
+            current_waste -= wastes[index];
+            selection.pop();
+        } else {
+            available -= values[index];
+
+            //Skip a UTXO that is the same as the previous one if that one was excluded,
+            //since the branch would be the same as one already searched
+            if selection.is_empty() ||
+               index - 1 == *selection.last().unwrap() ||
+               values[index] != values[index - 1] ||
+               wastes[index] != wastes[index - 1] {
+                selection.push(index);
+                current_value += values[index];
+                current_waste += wastes[index];
+            }
+        }
+        index += 1;
+    }
+
+    best
+}
+
+/**
+    Knapsack solver that selects UTXOs to pay for the target with enough left over for change.
+
+    A single UTXO paying exactly the target is used if there is one. Otherwise random subsets
+    of the UTXOs smaller than the target plus the minimum change are tried, and the closest one
+    is compared with the smallest UTXO that pays for everything by itself.
+*/
+fn knapsack(values: &Vec<u64>, target: u64, min_change: u64) -> Option<Vec<usize>> {
+    let mut lowest_larger: Option<usize> = None;
+    let mut applicable: Vec<usize> = vec![];
+    let mut total_lower: u64 = 0;
+    for i in 0..values.len() {
+        if values[i] == target {
+            return Some(vec![i])
+        } else if values[i] < target + min_change {
+            applicable.push(i);
+            total_lower += values[i];
+        } else if lowest_larger.is_none() || values[i] < values[lowest_larger.unwrap()] {
+            lowest_larger = Some(i);
+        }
+    }
+
+    if total_lower == target {
+        return Some(applicable)
+    }
+    if total_lower < target {
+        return lowest_larger.map(|x| vec![x])
+    }
+
+    //Values are sorted from largest to smallest so the applicable ones are too
+    let applicable_values: Vec<u64> = applicable.iter().map(|i| values[*i]).collect();
+    let (mut best, mut best_value) = approximate_best_subset(&applicable_values, total_lower, target);
+    if best_value != target && total_lower >= target + min_change {
+        let subset = approximate_best_subset(&applicable_values, total_lower, target + min_change);
+        best = subset.0;
+        best_value = subset.1;
+    }
+
+    //Use the smallest larger UTXO if the subset leaves too little for change or is no smaller
+    if let Some(x) = lowest_larger {
+        if (best_value != target && best_value < target + min_change) || values[x] <= best_value {
+            return Some(vec![x])
+        }
+    }
+
+    Some(applicable.iter().zip(best.iter()).filter(|(_, included)| **included).map(|(i, _)| *i).collect())
+}
+
+/**
+    Randomly pick subsets of the values to find the one closest to the target without going under it
+*/
+fn approximate_best_subset(values: &Vec<u64>, total: u64, target: u64) -> (Vec<bool>, u64) {
+    let mut rng = rand::thread_rng();
+    let mut best: Vec<bool> = vec![true; values.len()];
+    let mut best_value: u64 = total;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break
+        }
+
+        let mut included: Vec<bool> = vec![false; values.len()];
+        let mut value: u64 = 0;
+        let mut reached_target: bool = false;
+        for pass in 0..2 {
+            if reached_target {
+                break
+            }
+
+            //The first pass picks values at random and the second adds the ones left out
+            for i in 0..values.len() {
+                let pick: bool = if pass == 0 { rng.gen() } else { !included[i] };
+                if pick {
+                    value += values[i];
+                    included[i] = true;
+                    if value >= target {
+                        reached_target = true;
+                        if value < best_value {
+                            best_value = value;
+                            best = included.clone();
+                        }
+                        value -= values[i];
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    (best, best_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PrivKey,
+        Key,
+        tx::{
+            ScriptBuilder,
+            ScriptCodes
+        }
+    };
+
+    //P2WPKH input weight
+    const WEIGHT: u64 = 41 * 4 + 109;
+
+    fn p2wpkh(value: u64) -> Output {
+        Output {
+            value,
+            script_pub_key: ScriptBuilder::new().push_opcode(ScriptCodes::OP_0).push_slice(&[0x22; 20]).into_script()
+        }
+    }
+
+    fn utxos(values: &[u64]) -> Vec<Utxo> {
+        values.iter().enumerate().map(|(i, x)| Utxo::with_weight(&format!("{:064x}", i), 0, p2wpkh(*x), WEIGHT)).collect()
+    }
+
+    #[test]
+    fn branch_and_bound_without_change() {
+        let selector = CoinSelector::new(FeeRate::from_sat_per_vb(1)).with_long_term_feerate(FeeRate::from_sat_per_vb(1));
+        let outputs = vec![p2wpkh(50_000)];
+
+        //A transaction with one output and no inputs is 42 vbytes and each input is 69 vbytes,
+        //so the second and third UTXO pay for the output and fee with nothing left over
+        let candidates = utxos(&[100_000, 30_000 + 69 + 42, 20_000 + 69, 5_000]);
+        let selection = selector.select(&candidates, &outputs).unwrap();
+        assert_eq!(selection.algorithm, SelectionAlgorithm::BranchAndBound);
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.value(), 50_000 + 42 + 2 * 69);
+        assert_eq!(selection.fee, 42 + 2 * 69);
+
+        //The fee of each part is rounded up on its own, so the whole transaction
+        //needs 2 sats less and those are counted as excess
+        assert_eq!(FeeRate::from_sat_per_vb(1).fee_for_weight(base_weight(&outputs).unwrap() + 2 * WEIGHT), 42 + 2 * 69 - 2);
+        assert_eq!(selection.waste, 2);
+
+        //Excess below the cost of change is dropped to the fee
+        let candidates = utxos(&[100_000, 50_000 + 42 + 69 + 50]);
+        let selection = selector.select(&candidates, &outputs).unwrap();
+        assert_eq!(selection.algorithm, SelectionAlgorithm::BranchAndBound);
+        assert_eq!(selection.fee, 42 + 69 + 50);
+        assert_eq!(selection.waste, 51);
+    }
+
+    #[test]
+    fn knapsack_with_change() {
+        let selector = CoinSelector::new(FeeRate::from_sat_per_vb(2));
+        let outputs = vec![p2wpkh(50_000), p2wpkh(10_000)];
+
+        let candidates = utxos(&[40_000, 30_000, 25_000, 1_000, 100]);
+        let selection = selector.select(&candidates, &outputs).unwrap();
+        assert_eq!(selection.algorithm, SelectionAlgorithm::Knapsack);
+
+        //The value of the selection is split between the outputs, fee and change
+        let change = selection.change.unwrap();
+        assert!(change >= selector.change_dust_limit);
+        assert_eq!(selection.value(), 60_000 + selection.fee + change);
+
+        //The fee pays the feerate for the inputs, outputs and change output
+        let weight: u64 = base_weight(&outputs).unwrap() + selection.utxos.len() as u64 * WEIGHT + selector.change_output_weight;
+        assert_eq!(selection.fee, FeeRate::from_sat_per_vb(2).fee_for_weight(weight));
+
+        //The 100 sat UTXO costs more to spend than it is worth and is never selected
+        assert!(selection.utxos.iter().all(|x| x.output.value > 100));
+    }
+
+    #[test]
+    fn selection_errors_and_weights() {
+        let selector = CoinSelector::new(FeeRate::from_sat_per_vb(5));
+        match selector.select(&utxos(&[20_000, 20_000]), &vec![p2wpkh(40_000)]) {
+            Err(BuilderErr::InsufficientFunds(available, needed)) => {
+                assert_eq!(available, 2 * (20_000 - 5 * 69));
+                assert!(needed > 40_000);
+            },
+            x => panic!("{:?}", x)
+        }
+
+        //Spending weight comes from the signing data
+        let key = PrivKey::from_slice(&[1; 32]).unwrap();
+        let utxo = Utxo::new(&format!("{:064x}", 0), 0, p2wpkh(1_000), &SigningData::new(vec![key], None)).unwrap();
+        assert_eq!(utxo.weight, WEIGHT);
+        assert_eq!(utxo.effective_value(FeeRate::from_sat_per_vb(10)), 1_000 - 690);
+
+        //Change scripts set the weights and dust limit of the change output
+        let p2pkh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_DUP).push_opcode(ScriptCodes::OP_HASH160).push_slice(&[0x11; 20])
+            .push_opcode(ScriptCodes::OP_EQUALVERIFY).push_opcode(ScriptCodes::OP_CHECKSIG).into_script();
+        let selector = selector.with_change_script(&p2pkh);
+        assert_eq!(selector.change_output_weight, 34 * 4);
+        assert_eq!(selector.change_spend_weight, 149 * 4);
+        assert_eq!(selector.change_dust_limit, 546);
+        assert_eq!(CoinSelector::new(FeeRate::from_sat_per_vb(5)).with_change_script(&p2wpkh(0).script_pub_key).change_dust_limit, 294);
+    }
+}
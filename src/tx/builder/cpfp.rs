@@ -0,0 +1,185 @@
+/*
+    Child-pays-for-parent.
+
+    Miners select transactions by the feerate of the package made of a transaction and
+    its unconfirmed ancestors. A stuck parent can be pulled into a block by a child
+    spending one of its outputs that pays enough fee for both:
+        child fee = target feerate * (parent vsize + child vsize) - parent fee
+*/
+use std::cmp::max;
+use crate::{
+    api::ChainSource,
+    tx::{
+        Output,
+        Tx,
+        FeeRate,
+        ScriptType
+    }
+};
+use super::{
+    BuilderErr,
+    txbuilder::{
+        TxBuilder,
+        SigningData,
+        SigHash
+    }
+};
+
+impl<C: ChainSource> TxBuilder<C> {
+    /**
+        Create and sign a child spending an output of the parent so that the parent and child
+        together pay the given feerate. The parent's fee is worked out from the outputs spent
+        by each of its inputs.
+
+        The builder must not have any inputs. Outputs already in the builder are kept and the
+        value left over is sent to the change address, which must be set.
+        The child always pays at least the feerate for its own size.
+        If the child cannot be made the builder is left without the parent's output as an input.
+    */
+    pub fn cpfp(&mut self, parent: &Tx, parent_prevouts: &Vec<Output>, vout: u32, signing_data: &SigningData, feerate: FeeRate) -> Result<Tx, BuilderErr> {
+        if !self.inputs.is_empty() {
+            return Err(BuilderErr::HasInputs())
+        }
+        if self.change_address.is_none() {
+            return Err(BuilderErr::ChangeAddressMissing())
+        }
+        let output: Output = match parent.outputs.get(vout as usize) {
+            Some(x) => x.clone(),
+            None => return Err(BuilderErr::OutputIndexMissing(vout as usize))
+        };
+        let parent_fee: u64 = match parent.fee(parent_prevouts) {
+            Ok(x) => x,
+            Err(_) => return Err(BuilderErr::CannotGetInputValue())
+        };
+        let parent_vsize: u64 = parent.vsize();
+
+        //Fee for the package minus what the parent already pays
+        let required_fee = |vsize: u64| max(feerate.fee_for_vsize(parent_vsize + vsize).saturating_sub(parent_fee), feerate.fee_for_vsize(vsize));
+
+        self.add_input_with_prevout(&parent.get_txid(), vout, output.clone())?;
+        let child = self.fund_and_sign_child(required_fee, &output, signing_data);
+        if child.is_err() {
+            if let Some(index) = self.change_index.take() {
+                self.outputs.remove(index);
+            }
+            self.inputs.clear();
+            self.script_sigs.clear();
+            self.witness.clear();
+            self.sighashes.clear();
+            self.prevouts.clear();
+        }
+
+        child
+    }
+
+    /**
+        Add the change output and sign the input spending the parent
+    */
+    fn fund_and_sign_child<F: Fn(u64) -> u64>(&mut self, required_fee: F, output: &Output, signing_data: &SigningData) -> Result<Tx, BuilderErr> {
+        self.finalize_with_fee(required_fee, &vec![signing_data.clone()])?;
+
+        //Change below the dust limit is dropped, which leaves nothing to spend to
+        if self.outputs.is_empty() {
+            return Err(BuilderErr::NoOutputs())
+        }
+
+        let sighash: SigHash = match output.script_pub_key.determine_type() {
+            ScriptType::P2TR => SigHash::DEFAULT,
+            _ => SigHash::ALL
+        };
+        self.sign_input(0, signing_data, sighash)?;
+
+        self.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tx::VerifyFlags,
+        util::{
+            Network,
+            bytes
+        },
+        test_utils::{ PREV_TXID, key, p2wpkh_output }
+    };
+
+    #[test]
+    fn child_pays_for_parent() {
+        let parent_key = key(11);
+        let child_key = key(12);
+        let signing_data = SigningData::new(vec![child_key], None);
+
+        //Parent paying 200 sats, under 2 sat/vB
+        let parent_prevouts = vec![p2wpkh_output(&parent_key, 100000)];
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, parent_prevouts[0].clone()).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 50000).unwrap();
+        txb.outputs.push(p2wpkh_output(&child_key, 49800));
+        txb.sign_input(0, &SigningData::new(vec![parent_key], None), SigHash::ALL).unwrap();
+        let parent = txb.build().unwrap();
+        let parent_fee = parent.fee(&parent_prevouts).unwrap();
+        assert_eq!(parent_fee, 200);
+
+        //Spend the second output so the package pays 20 sat/vB
+        let mut child_builder = TxBuilder::new(Network::Testnet);
+        assert!(matches!(child_builder.cpfp(&parent, &parent_prevouts, 1, &signing_data, FeeRate::from_sat_per_vb(20)), Err(BuilderErr::ChangeAddressMissing())));
+        child_builder.set_change_address("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e").unwrap();
+        assert!(matches!(child_builder.cpfp(&parent, &parent_prevouts, 2, &signing_data, FeeRate::from_sat_per_vb(20)), Err(BuilderErr::OutputIndexMissing(2))));
+        let child = child_builder.cpfp(&parent, &parent_prevouts, 1, &signing_data, FeeRate::from_sat_per_vb(20)).unwrap();
+
+        let child_prevouts = vec![parent.outputs[1].clone()];
+        child.verify(&child_prevouts, VerifyFlags::standard()).unwrap();
+        assert_eq!(bytes::encode_02x(&child.inputs[0].txid), parent.get_txid());
+        assert_eq!(child.inputs[0].vout, 1);
+        assert_eq!(child.outputs.len(), 1);
+
+        //The package pays the target feerate and the child pays for more than itself
+        let child_fee = child.fee(&child_prevouts).unwrap();
+        let package_feerate = FeeRate::from_fee_and_vsize(parent_fee + child_fee, parent.vsize() + child.vsize());
+        assert!(package_feerate >= FeeRate::from_sat_per_vb(20));
+        assert!(package_feerate < FeeRate::from_sat_per_vb(21));
+        assert!(child.feerate(&child_prevouts).unwrap() > FeeRate::from_sat_per_vb(20));
+
+        //The builder now has an input so it cannot make another child
+        assert!(matches!(child_builder.cpfp(&parent, &parent_prevouts, 1, &signing_data, FeeRate::from_sat_per_vb(20)), Err(BuilderErr::HasInputs())));
+    }
+
+    #[test]
+    fn failed_child_is_rolled_back() {
+        let parent_key = key(13);
+        let child_key = key(14);
+        let signing_data = SigningData::new(vec![child_key], None);
+
+        //Parent with a small and a large output to the child key
+        let parent_prevouts = vec![p2wpkh_output(&parent_key, 100000)];
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, parent_prevouts[0].clone()).unwrap();
+        txb.outputs.push(p2wpkh_output(&child_key, 350));
+        txb.outputs.push(p2wpkh_output(&child_key, 99250));
+        txb.sign_input(0, &SigningData::new(vec![parent_key], None), SigHash::ALL).unwrap();
+        let parent = txb.build().unwrap();
+
+        let mut child_builder = TxBuilder::new(Network::Testnet);
+        child_builder.set_change_address("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e").unwrap();
+
+        //The small output cannot pay a high feerate
+        match child_builder.cpfp(&parent, &parent_prevouts, 0, &signing_data, FeeRate::from_sat_per_vb(20)) {
+            Err(BuilderErr::InsufficientFunds(350, _)) => { },
+            x => panic!("{:?}", x.map(|x| x.get_txid()))
+        }
+        assert!(child_builder.inputs.is_empty());
+
+        //At a low feerate its change is dust, which would leave the child without outputs
+        assert!(matches!(child_builder.cpfp(&parent, &parent_prevouts, 0, &signing_data, FeeRate::from_sat_per_vb(1)), Err(BuilderErr::NoOutputs())));
+        assert!(child_builder.inputs.is_empty());
+        assert!(child_builder.outputs.is_empty());
+
+        //The same builder can still spend the large output
+        let child = child_builder.cpfp(&parent, &parent_prevouts, 1, &signing_data, FeeRate::from_sat_per_vb(1)).unwrap();
+        child.verify(&vec![parent.outputs[1].clone()], VerifyFlags::standard()).unwrap();
+        assert_eq!(child.inputs.len(), 1);
+        assert_eq!(child.outputs.len(), 1);
+    }
+}
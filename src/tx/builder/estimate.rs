@@ -0,0 +1,216 @@
+/*
+    Size estimation for inputs that have not been signed yet.
+
+    A placeholder scriptSig and witness is made for each input with the same
+    shape as the one the signing pipes will create, using the largest possible
+    signatures, and then serialized to get its size. ECDSA signatures are at most
+    72 bytes in DER plus the sighash byte and Schnorr signatures are 64 bytes plus
+    the sighash byte when it is not DEFAULT.
+*/
+use crate::{
+    util::{
+        serialize::Serialize,
+        varint::VarInt
+    },
+    tx::{
+        Script,
+        ScriptType,
+        ScriptBuilder,
+        ScriptCodes,
+        SigningData,
+        ScriptInput,
+        Witness
+    }
+};
+use super::BuilderErr;
+
+pub const MAX_ECDSA_SIG_SIZE: usize = 73;
+pub const MAX_SCHNORR_SIG_SIZE: usize = 65;
+pub const PUBKEY_SIZE: usize = 33;
+
+//Outpoint and sequence of an input
+const INPUT_BASE_SIZE: usize = 36 + 4;
+
+/**
+    Placeholder scriptSig and witness with the size of the ones the input will have once signed
+*/
+pub(crate) fn dummy_satisfaction(script_pub_key: &Script, signing_data: &SigningData) -> Result<(Script, Option<Witness>), BuilderErr> {
+    let ecdsa_sig: Vec<u8> = vec![0; MAX_ECDSA_SIG_SIZE];
+    let pubkey: Vec<u8> = vec![0; PUBKEY_SIZE];
+
+    let satisfaction = match script_pub_key.determine_type() {
+        ScriptType::P2PKH => {
+            let script_sig = ScriptBuilder::new().push_slice(&ecdsa_sig).push_slice(&pubkey).into_script();
+            (script_sig, None)
+        },
+        ScriptType::P2WPKH => {
+            (Script::new(vec![]), Some(Witness::new(vec![Script::new(ecdsa_sig), Script::new(pubkey)])))
+        },
+        ScriptType::P2SH => {
+            let redeem_script: &Script = match &signing_data.script {
+                Some(x) => x,
+                None => return Err(BuilderErr::RedeemScriptMissing())
+            };
+
+            //Same choice of nested or regular P2SH as the signing pipe
+            match redeem_script.determine_type() {
+                ScriptType::P2WPKH => {
+                    let script_sig = ScriptBuilder::new().push_slice(&redeem_script.code).into_script();
+                    (script_sig, Some(Witness::new(vec![Script::new(ecdsa_sig), Script::new(pubkey)])))
+                },
+                _ if signing_data.force_segwit => {
+                    let program = Script::p2sh_p2wsh_redeem_script(redeem_script);
+                    let script_sig = ScriptBuilder::new().push_slice(&program.code).into_script();
+                    (script_sig, Some(multisig_witness(signing_data.keys.len(), redeem_script)))
+                },
+                _ => {
+                    let mut script_sig = ScriptBuilder::new().push_opcode(ScriptCodes::OP_0);
+                    for _ in 0..signing_data.keys.len() {
+                        script_sig = script_sig.push_slice(&ecdsa_sig);
+                    }
+                    (script_sig.push_slice(&redeem_script.code).into_script(), None)
+                }
+            }
+        },
+        ScriptType::P2WSH => {
+            let witness_script: &Script = match &signing_data.script {
+                Some(x) => x,
+                None => return Err(BuilderErr::RedeemScriptMissing())
+            };
+            (Script::new(vec![]), Some(multisig_witness(signing_data.keys.len(), witness_script)))
+        },
+        ScriptType::P2TR => {
+            let schnorr_sig: Vec<u8> = vec![0; MAX_SCHNORR_SIG_SIZE];
+            let mut stack_items: Vec<Script> = vec![];
+            match (&signing_data.control_block, &signing_data.script) {
+                (Some(control_block), Some(script)) => {
+                    match &signing_data.script_inputs {
+                        Some(inputs) => for input in inputs {
+                            match input {
+                                ScriptInput::Signature(_) => stack_items.push(Script::new(schnorr_sig.clone())),
+                                ScriptInput::Item(x) => stack_items.push(Script::new(x.clone()))
+                            }
+                        },
+                        None => for _ in 0..signing_data.keys.len() {
+                            stack_items.push(Script::new(schnorr_sig.clone()));
+                        }
+                    }
+                    stack_items.push(script.clone());
+                    stack_items.push(Script::new(control_block.serialize()));
+                },
+                (Some(_), None) => return Err(BuilderErr::InvalidSigningData()),
+                _ => stack_items.push(Script::new(schnorr_sig))
+            }
+            if let Some(x) = &signing_data.annex {
+                stack_items.push(Script::new(x.clone()));
+            }
+            (Script::new(vec![]), Some(Witness::new(stack_items)))
+        },
+        ScriptType::NonStandard => return Err(BuilderErr::UnknownScriptType())
+    };
+
+    Ok(satisfaction)
+}
+
+/**
+    Witness for a multisig witness script signed by the given number of keys
+*/
+fn multisig_witness(signatures: usize, witness_script: &Script) -> Witness {
+    let mut stack_items: Vec<Script> = vec![Script::new(vec![])];
+    for _ in 0..signatures {
+        stack_items.push(Script::new(vec![0; MAX_ECDSA_SIG_SIZE]));
+    }
+    stack_items.push(witness_script.clone());
+
+    Witness::new(stack_items)
+}
+
+/**
+    Weight of an input with the given scriptSig and witness
+*/
+pub(crate) fn satisfied_weight(script_sig: &Script, witness: &Option<Witness>) -> u64 {
+    let script_sig_size: usize = VarInt::from_usize(script_sig.code.len()).unwrap().len() + script_sig.code.len();
+    let witness_size: usize = match witness {
+        Some(x) => x.serialize().unwrap().len(),
+        None => 0
+    };
+
+    ((INPUT_BASE_SIZE + script_sig_size) * 4 + witness_size) as u64
+}
+
+/**
+    Worst case weight of an input spending the given output once it is signed with the signing data.
+    The weight of the witness is included for segwit inputs.
+*/
+pub fn input_weight(script_pub_key: &Script, signing_data: &SigningData) -> Result<u64, BuilderErr> {
+    let (script_sig, witness) = dummy_satisfaction(script_pub_key, signing_data)?;
+
+    Ok(satisfied_weight(&script_sig, &witness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PrivKey,
+        hash,
+        tx::taproot,
+        test_utils::key
+    };
+
+    #[test]
+    fn single_key_inputs() {
+        let signing_data = SigningData::new(vec![key(1)], None);
+        let keyhash = [0x11; 20];
+
+        //P2PKH inputs are 149 bytes
+        let p2pkh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_DUP).push_opcode(ScriptCodes::OP_HASH160)
+            .push_slice(&keyhash).push_opcode(ScriptCodes::OP_EQUALVERIFY).push_opcode(ScriptCodes::OP_CHECKSIG).into_script();
+        assert_eq!(input_weight(&p2pkh, &signing_data).unwrap(), 149 * 4);
+
+        //P2WPKH inputs are 41 bytes and 109 witness bytes
+        let p2wpkh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_0).push_slice(&keyhash).into_script();
+        assert_eq!(input_weight(&p2wpkh, &signing_data).unwrap(), 41 * 4 + 109);
+
+        //P2SH-P2WPKH inputs also push the 22 byte witness program
+        let p2sh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_HASH160).push_slice(&keyhash).push_opcode(ScriptCodes::OP_EQUAL).into_script();
+        let nested = SigningData::new(vec![key(1)], Some(Script::p2sh_p2wpkh_redeem_script(&key(1))));
+        assert_eq!(input_weight(&p2sh, &nested).unwrap(), 64 * 4 + 109);
+
+        //Taproot key path inputs are 41 bytes and a 66 byte signature. The annex adds to the witness
+        let p2tr = Script::p2tr_locking(&taproot::internal_key(&key(1)));
+        assert_eq!(input_weight(&p2tr, &signing_data).unwrap(), 41 * 4 + 67);
+        assert_eq!(input_weight(&p2tr, &signing_data.clone().with_annex(vec![0x50, 0x01])).unwrap(), 41 * 4 + 70);
+
+        assert!(input_weight(&Script::new(vec![0x6a]), &signing_data).is_err());
+    }
+
+    #[test]
+    fn multisig_inputs() {
+        //2-of-3 redeem script is 105 bytes
+        let keys: Vec<PrivKey> = (1..4).map(|x| key(x)).collect();
+        let multisig = Script::multisig_locking(2, &keys);
+        assert_eq!(multisig.code.len(), 105);
+        let signing_data = SigningData::new(keys[..2].to_vec(), Some(multisig.clone()));
+
+        //OP_0, two signatures and the redeem script pushed with OP_PUSHDATA1
+        let p2sh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_HASH160).push_slice(&hash::hash160(&multisig.code)).push_opcode(ScriptCodes::OP_EQUAL).into_script();
+        let script_sig_size = 1 + 2 * 74 + 2 + 105;
+        assert_eq!(input_weight(&p2sh, &signing_data).unwrap(), (40 + 3 + script_sig_size) as u64 * 4);
+
+        //Witness of the item count, empty dummy, two signatures and the witness script
+        let p2wsh = ScriptBuilder::new().push_opcode(ScriptCodes::OP_0).push_slice(&hash::sha256(&multisig.code)).into_script();
+        let witness_size = 1 + 1 + 2 * 74 + 1 + 105;
+        assert_eq!(input_weight(&p2wsh, &signing_data).unwrap(), 41 * 4 + witness_size);
+
+        //Nested P2WSH pushes the 34 byte witness program
+        let nested = SigningData::nested_p2wsh(keys[..2].to_vec(), multisig.clone());
+        assert_eq!(input_weight(&p2sh, &nested).unwrap(), 76 * 4 + witness_size);
+
+        //Scripts are needed for P2SH and P2WSH inputs
+        match input_weight(&p2wsh, &SigningData::new(keys, None)) {
+            Err(BuilderErr::RedeemScriptMissing()) => { },
+            x => panic!("{:?}", x)
+        }
+    }
+}
@@ -3,11 +3,11 @@ use crate::{
     tx::ScriptType,
     tx::SigHash,
     tx::Tx,
+    tx::Output,
     util::serialize::Serialize,
-    util::Network,
+    util::varint::VarInt,
     util::bytes,
-    hash,
-    api
+    hash
 };
 use super::BuilderErr;
 
@@ -21,54 +21,46 @@ pub fn legacy(
     script_pub_key: &Script
 ) -> Result<Vec<u8>, BuilderErr> {
     //Add the locking script of the input being signed to the tx_copy to sign it.
-    tx_copy.inputs[index].scriptSig = script_pub_key.clone();
-    tx_copy.inputs[index].scriptSig_size = tx_copy.inputs[index].scriptSig.len() as u64;
+    //Every other input is signed with an empty scriptSig.
+    for i in 0..tx_copy.inputs.len() {
+        tx_copy.inputs[i].scriptSig = if i == index { script_pub_key.clone() } else { Script::new(vec![]) };
+        tx_copy.inputs[i].scriptSig_size = tx_copy.inputs[i].scriptSig.len() as u64;
+    }
     
     match sighash {
-        SigHash::ALL => { 
+        SigHash::DEFAULT => return Err(BuilderErr::InvalidSigHash()),
+        SigHash::ALL | SigHash::ALL_ANYONECANPAY => { 
             /*No need to modify txdata for sighash_all*/ 
         },
-        SigHash::NONE => {
+        SigHash::NONE | SigHash::NONE_ANYONECANPAY => {
             //Remove all outputs and set output count to 0
             tx_copy.output_count = 0x00;
             tx_copy.outputs = vec![];
+            zero_other_sequences(tx_copy, index);
         },
-        SigHash::SINGLE => {
-            //Remove all outputs apart from the output at the same index as the input being signed
+        SigHash::SINGLE | SigHash::SINGLE_ANYONECANPAY => {
+            //Keep the outputs up to the index of the input being signed.
+            //Outputs before it are blanked with a value of -1 and an empty script.
             if tx_copy.outputs.len() > index {
-                tx_copy.outputs = vec![tx_copy.outputs[index].clone()];
-                tx_copy.output_count = 0x01;
+                tx_copy.outputs.truncate(index + 1);
+                for i in 0..index {
+                    tx_copy.outputs[i] = Output { value: 0xFFFFFFFFFFFFFFFF, script_pub_key: Script::new(vec![]) };
+                }
+                tx_copy.output_count = tx_copy.outputs.len() as u64;
             } else {
                 return Err(BuilderErr::OutputIndexMissing(index))
             }
-        },
-        SigHash::ALL_ANYONECANPAY => {
-            //Remove all inputs apart from the one being signed
-            tx_copy.inputs = vec![tx_copy.inputs[index].clone()];
-            tx_copy.input_count = 0x01;
-        },
-        SigHash::NONE_ANYONECANPAY => {
-            //Remove all inputs apart from the one being signed
-            tx_copy.inputs = vec![tx_copy.inputs[index].clone()];
-            tx_copy.input_count = 0x01;
+            zero_other_sequences(tx_copy, index);
+        }
+    }
 
-            //Remove all outputs and set output count to 0
-            tx_copy.output_count = 0x00;
-            tx_copy.outputs = vec![];
-        },
-        SigHash::SINGLE_ANYONECANPAY => {
-            //Remove all inputs apart from the one being signed
+    //Remove all inputs apart from the one being signed if ANYONECANPAY is set
+    match sighash {
+        SigHash::ALL_ANYONECANPAY | SigHash::NONE_ANYONECANPAY | SigHash::SINGLE_ANYONECANPAY => {
             tx_copy.inputs = vec![tx_copy.inputs[index].clone()];
             tx_copy.input_count = 0x01;
-
-            //Remove all outputs apart from the output at the same index as the input being signed
-            if tx_copy.outputs.len() > index {
-                tx_copy.outputs = vec![tx_copy.outputs[index].clone()];
-                tx_copy.output_count = 0x01;
-            } else {
-                return Err(BuilderErr::OutputIndexMissing(index))
-            }
-        }
+        },
+        _ => { }
     }
 
     Ok(match tx_copy.serialize() {
@@ -81,17 +73,27 @@ pub fn legacy(
     })
 }
 
+/**
+    Set the sequence of every input apart from the one being signed to 0
+    so that other inputs can be updated without invalidating the signature.
+    Used with SIGHASH_NONE and SIGHASH_SINGLE.
+*/
+fn zero_other_sequences(tx_copy: &mut Tx, index: usize) {
+    for i in 0..tx_copy.inputs.len() {
+        if i != index { tx_copy.inputs[i].sequence = 0 }
+    }
+}
+
 /**
     Create the hash preimage for legacy transactions.
     BIP-143
 */
 pub fn segwit(
-    network: &Network,
     tx_copy: &Tx,
     sighash: &SigHash,
     index: usize,
     script_code: &Script,
-    electrum_url: &Option<String>
+    input_value: u64
 ) -> Result<Vec<u8>, BuilderErr> {
     let n_version = tx_copy.version.to_le_bytes();
     
@@ -128,13 +130,13 @@ pub fn segwit(
     //hashOutputs is the SHA256D of all outputs if SigHash is not single, [0; 32] is SigHash is none
     //and the output at the same index as the input being signed if the SigHash is single.
     let hash_outputs: [u8; 32] = match sighash {
-        SigHash::SINGLE => {
+        SigHash::SINGLE | SigHash::SINGLE_ANYONECANPAY => {
             if index >= tx_copy.outputs.len() { return Err(BuilderErr::OutputIndexMissing(index)) }
             let mut output = vec![];
             output.append(&mut tx_copy.outputs[index].serialize().unwrap());
             hash::sha256d(output)
         },
-        SigHash::NONE => [0; 32],
+        SigHash::NONE | SigHash::NONE_ANYONECANPAY => [0; 32],
         _ => {
             let mut outputs = vec![];
             for i in 0..tx_copy.outputs.len() {
@@ -144,27 +146,6 @@ pub fn segwit(
         },
     };
 
-    //THERE IS A BUG HERE THAT OCCURS SOMETIMES WHEN FLOATING POINT ARITHMATIC FAILS
-    //SEE API MODULE
-    // let input_value: u64 = match api::JsonRPC::new(network).get_input_value(&bytes::encode_02x(&tx_copy.inputs[index].txid), tx_copy.inputs[index].vout){
-    //     Ok(x) => x,
-    //     Err(_) => return Err(BuilderErr::CannotGetInputValue())
-    // };
-
-    // let input_value = match network {
-    //     Network::Bitcoin => {
-    //         let electrum = api::Electrum::mainnet(electrum_url);
-    //         electrum.get_input_value(&bytes::encode_02x(&tx_copy.inputs[index].txid), tx_copy.inputs[index].vout as usize)
-    //     }
-    // };
-
-    //Get the input value of the input being signed using Electrum
-    let client = match api::Electrum::new(electrum_url, network) {
-        Ok(x) => x,
-        Err(_) => return Err(BuilderErr::CannotGetElectrum)
-    };
-    let input_value: u64 = client.get_input_value(&bytes::encode_02x(&tx_copy.inputs[index].txid), tx_copy.inputs[index].vout as usize).unwrap();
-
     let mut outpoint: Vec<u8> = vec![];
     outpoint.append(&mut bytes::reverse(&tx_copy.inputs[index].txid.to_vec()));
     outpoint.append(&mut tx_copy.inputs[index].vout.to_le_bytes().to_vec());
@@ -181,6 +162,7 @@ pub fn segwit(
     bip143_prehash_serialization.append(&mut hash_outputs.to_vec());                                     //hashOutputs
     bip143_prehash_serialization.append(&mut tx_copy.locktime.to_le_bytes().to_vec());                   //Locktime of the transaction
     let sh: u32 = match sighash {
+        SigHash::DEFAULT => return Err(BuilderErr::InvalidSigHash()),
         SigHash::ALL => 0x01,
         SigHash::NONE => 0x02,
         SigHash::SINGLE => 0x03,
@@ -195,6 +177,109 @@ pub fn segwit(
     Ok(bip143_prehash_serialization)
 }
 
+/**
+    Create the hash preimage for Taproot transactions.
+    BIP-341
+
+    The returned preimage is prefixed with the sighash epoch and should be hashed
+    with the "TapSighash" tagged hash.
+    A leaf hash is given when signing for a script path spend.
+*/
+pub fn taproot(
+    tx_copy: &Tx,
+    sighash: &SigHash,
+    index: usize,
+    prevouts: &Vec<Output>,
+    annex: &Option<Vec<u8>>,
+    leaf_hash: Option<[u8; 32]>
+) -> Result<Vec<u8>, BuilderErr> {
+    let anyonecanpay: bool = match sighash {
+        SigHash::ALL_ANYONECANPAY | SigHash::NONE_ANYONECANPAY | SigHash::SINGLE_ANYONECANPAY => true,
+        _ => false
+    };
+
+    let mut sig_msg: Vec<u8> = vec![0x00];                                        //Sighash epoch
+    sig_msg.push(sighash.clone() as u8);                                         //Sighash type
+    sig_msg.append(&mut tx_copy.version.to_le_bytes().to_vec());                 //Version
+    sig_msg.append(&mut tx_copy.locktime.to_le_bytes().to_vec());                //Locktime
+
+    //If ANYONECANPAY is not set, commit to the outpoints, amounts, scriptPubKeys and sequences of all inputs
+    if !anyonecanpay {
+        let mut outpoints: Vec<u8> = vec![];
+        let mut amounts: Vec<u8> = vec![];
+        let mut script_pub_keys: Vec<u8> = vec![];
+        let mut sequences: Vec<u8> = vec![];
+        for i in 0..tx_copy.inputs.len() {
+            outpoints.append(&mut bytes::reverse(&tx_copy.inputs[i].txid.to_vec()));
+            outpoints.append(&mut tx_copy.inputs[i].vout.to_le_bytes().to_vec());
+            amounts.append(&mut prevouts[i].value.to_le_bytes().to_vec());
+            script_pub_keys.append(&mut VarInt::from_usize(prevouts[i].script_pub_key.code.len()).unwrap());
+            script_pub_keys.append(&mut prevouts[i].script_pub_key.code.clone());
+            sequences.append(&mut tx_copy.inputs[i].sequence.to_le_bytes().to_vec());
+        }
+
+        sig_msg.append(&mut hash::sha256(outpoints).to_vec());                   //sha_prevouts
+        sig_msg.append(&mut hash::sha256(amounts).to_vec());                     //sha_amounts
+        sig_msg.append(&mut hash::sha256(script_pub_keys).to_vec());             //sha_scriptpubkeys
+        sig_msg.append(&mut hash::sha256(sequences).to_vec());                   //sha_sequences
+    }
+
+    //If SigHash is not NONE or SINGLE, commit to all outputs
+    match sighash {
+        SigHash::NONE | SigHash::SINGLE | SigHash::NONE_ANYONECANPAY | SigHash::SINGLE_ANYONECANPAY => { },
+        _ => {
+            let mut outputs: Vec<u8> = vec![];
+            for i in 0..tx_copy.outputs.len() {
+                outputs.append(&mut tx_copy.outputs[i].serialize().unwrap());
+            }
+            sig_msg.append(&mut hash::sha256(outputs).to_vec());                 //sha_outputs
+        }
+    }
+
+    //Spend type is 2 * ext_flag + annex_present
+    let mut spend_type: u8 = 0;
+    if leaf_hash.is_some() { spend_type += 2 }
+    if annex.is_some() { spend_type += 1 }
+    sig_msg.push(spend_type);
+
+    //Data about the input being signed
+    if anyonecanpay {
+        sig_msg.append(&mut bytes::reverse(&tx_copy.inputs[index].txid.to_vec()));
+        sig_msg.append(&mut tx_copy.inputs[index].vout.to_le_bytes().to_vec());
+        sig_msg.append(&mut prevouts[index].value.to_le_bytes().to_vec());
+        sig_msg.append(&mut VarInt::from_usize(prevouts[index].script_pub_key.code.len()).unwrap());
+        sig_msg.append(&mut prevouts[index].script_pub_key.code.clone());
+        sig_msg.append(&mut tx_copy.inputs[index].sequence.to_le_bytes().to_vec());
+    } else {
+        sig_msg.append(&mut (index as u32).to_le_bytes().to_vec());
+    }
+
+    //sha_annex
+    if let Some(x) = annex {
+        let mut annex_bytes: Vec<u8> = VarInt::from_usize(x.len()).unwrap();
+        annex_bytes.append(&mut x.clone());
+        sig_msg.append(&mut hash::sha256(annex_bytes).to_vec());
+    }
+
+    //If SigHash is SINGLE, commit to the output with the same index as the input being signed
+    match sighash {
+        SigHash::SINGLE | SigHash::SINGLE_ANYONECANPAY => {
+            if index >= tx_copy.outputs.len() { return Err(BuilderErr::OutputIndexMissing(index)) }
+            sig_msg.append(&mut hash::sha256(tx_copy.outputs[index].serialize().unwrap()).to_vec());
+        },
+        _ => { }
+    }
+
+    //Script path extension (BIP-342)
+    if let Some(x) = leaf_hash {
+        sig_msg.append(&mut x.to_vec());                                          //tapleaf_hash
+        sig_msg.push(0x00);                                                       //key_version
+        sig_msg.append(&mut 0xFFFFFFFFu32.to_le_bytes().to_vec());                //codesep_pos
+    }
+
+    Ok(sig_msg)
+}
+
 /**
     Creates the scriptCode to be used in hashing segwit Tx's from
     scriptPubKey or redeemScripts
@@ -221,4 +306,58 @@ pub fn script_code(script: &Script) -> Script {
             Script::new(x)
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes::{ decode_02x, encode_02x };
+
+    fn output(value: u64, script_pub_key: &str) -> Output {
+        Output { value, script_pub_key: Script::new(decode_02x(script_pub_key)) }
+    }
+
+    #[test]
+    fn bip341_key_path_sighashes() {
+        //keyPathSpending vectors from BIP-341 wallet-test-vectors.json
+        let tx = Tx::from_hex("02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d").unwrap();
+        let prevouts = vec![
+            output(420000000, "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"),
+            output(462000000, "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3"),
+            output(294000000, "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac"),
+            output(504000000, "5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e"),
+            output(630000000, "512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605"),
+            output(378000000, "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"),
+            output(672000000, "512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831"),
+            output(546000000, "5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5"),
+            output(588000000, "512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220")
+        ];
+
+        //(input index, sighash, sigMsg, sigHash)
+        let vectors = vec![
+            (0, SigHash::SINGLE, "0003020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957e0000000000d0418f0e9a36245b9a50ec87f8bf5be5bcae434337b87139c3a5b1f56e33cba0", "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555"),
+            (1, SigHash::SINGLE_ANYONECANPAY, "0083020000000065cd1d00d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd9900000000808f891b00000000225120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3ffffffffffcef8fb4ca7efc5433f591ecfc57391811ce1e186a3793024def5c884cba51d", "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d"),
+            (3, SigHash::ALL, "0001020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957ea2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3cc37dcbad7f90d4eb4bc50003000000", "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669"),
+            (4, SigHash::DEFAULT, "0000020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957ea2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3cc37dcbad7f90d4eb4bc50004000000", "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef"),
+            (6, SigHash::NONE, "0002020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957e0006000000", "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85"),
+            (7, SigHash::NONE_ANYONECANPAY, "0082020000000065cd1d00e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf00000000804c8b2000000000225120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5ffffffff", "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10"),
+            (8, SigHash::ALL_ANYONECANPAY, "0081020000000065cd1da2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3cc37dcbad7f90d4eb4bc500a778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af101000000002b0c230000000022512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220ffffffff", "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2")
+        ];
+        for (index, sighash, sig_msg, sig_hash) in vectors {
+            let preimage = taproot(&tx, &sighash, index, &prevouts, &None, None).unwrap();
+            assert_eq!(encode_02x(&preimage), sig_msg);
+            assert_eq!(encode_02x(&hash::tagged_hash("TapSighash", preimage)), sig_hash);
+        }
+    }
+
+    #[test]
+    fn taproot_sighash_with_annex() {
+        //Key path spend with an annex and SIGHASH_SINGLE|ANYONECANPAY
+        let tx = Tx::from_hex("0200000001df8123752e8f37d132c4e9f1ff7e4f9b986ade9211267e9ebd5fd22a5e718dec6d01000000ce4023b903cb7b23000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787580200000000000017a914afd0d512a2c5c2b40e25669e9cc460303c325b8b87580200000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787f6020000").unwrap();
+        let prevouts = vec![output(2509290, "5120ab5e9800806bf18cb246edcf5fe63441208fe955a4b5a35bbff65f5db622a010")];
+        let annex = Some(decode_02x("507b979802e62d397acb29f56743a791894b99372872fc5af06a4f6e8d242d0615cda53062bb20e6ec79756fe39183f0c128adfe85559a8fa042b042c018aa8010143799e44f0893c40e1e"));
+
+        let preimage = taproot(&tx, &SigHash::SINGLE_ANYONECANPAY, 0, &prevouts, &annex, None).unwrap();
+        assert_eq!(preimage[preimage.len() - 148], 0x01); //spend_type has annex_present set
+        assert_eq!(encode_02x(&hash::tagged_hash("TapSighash", preimage)), "3b003000add359a364a156e73e02846782a59d0d95ca8c4638aaad99f2ef915c");
+    }
+}
@@ -1,5 +1,9 @@
 pub mod txbuilder;
+pub mod coinselect;
+pub mod rbf;
+mod cpfp;
 mod pipes;
-mod hashpreimage;
+pub(crate) mod estimate;
+pub(crate) mod hashpreimage;
 
 use txbuilder::BuilderErr as BuilderErr;
\ No newline at end of file
@@ -1,15 +1,21 @@
 use crate::{
     PrivKey,
+    schnorrsig,
+    api::ChainSource,
     hash,
     signature, Signature,
     tx::{
         Script,
+        ScriptBuilder,
         SigningData,
+        ScriptInput,
         SigHash,
         Tx,
         TxBuilder,
         Witness,
-        ScriptType
+        ScriptType,
+        Output,
+        taproot
     }
 };
 use super::{ 
@@ -22,8 +28,8 @@ use super::{
 
     Signing data needs one private key to sign the input
 */
-pub fn p2pkh(
-    builder: &mut TxBuilder,
+pub fn p2pkh<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -56,8 +62,8 @@ pub fn p2pkh(
 
     Signing data need one private key to sign the input
 */
-pub fn p2wpkh(
-    builder: &mut TxBuilder, 
+pub fn p2wpkh<C: ChainSource>(
+    builder: &mut TxBuilder<C>, 
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -67,7 +73,8 @@ pub fn p2wpkh(
     //Get the BIP143 defined hash preimage of the transaction and hash it
     //The scriptCode under this circumstance is derived from the scriptPubKey from the input being signed
     let script_code = hashpreimage::script_code(script_pub_key);
-    let hash_preimage = hashpreimage::segwit(&builder.network, tx_copy, sighash, index, &script_code, &builder.electrum_url)?;
+    let input_value: u64 = builder.get_prevout(index)?.value;
+    let hash_preimage = hashpreimage::segwit(tx_copy, sighash, index, &script_code, input_value)?;
     let hash: [u8; 32] = hash::sha256d(hash_preimage);
 
     //Sign the hash preimage with the provided key
@@ -86,6 +93,144 @@ pub fn p2wpkh(
     Ok(())
 }
 
+/**
+    Signing pipe for P2TR key path spends
+
+    Signing data needs one private key (the internal key) to sign the input.
+    The key is tweaked with the merkle root in the signing data.
+*/
+pub fn p2tr<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
+    tx_copy: &Tx,
+    index: usize,
+    sighash: &SigHash,
+    signing_data: &SigningData
+) -> Result<(), BuilderErr> {
+    //The BIP341 sighash commits to the outputs spent by every input
+    check_annex(&signing_data.annex)?;
+    let prevouts: Vec<Output> = taproot_prevouts(builder, index, sighash)?;
+    let hash_preimage = hashpreimage::taproot(tx_copy, sighash, index, &prevouts, &signing_data.annex, None)?;
+    let hash: [u8; 32] = hash::tagged_hash("TapSighash", hash_preimage);
+
+    //Tweak the internal key and sign the hash with it
+    let keypair = match taproot::tweaked_keypair(&signing_data.keys[0], signing_data.merkle_root) {
+        Ok(x) => x,
+        Err(_) => return Err(BuilderErr::FailedToTweakKey())
+    };
+    let msg = match signature::new_msg(&hash) {
+        Ok(x) => x,
+        Err(_) => return Err(BuilderErr::FailedToCreateMessageStruct())
+    };
+    let signature = signature::sign_schnorr(&msg, &keypair);
+
+    //Create the witness and store it and the sighash
+    let witness: Witness = Witness::p2tr_key_path(&signature, sighash, &signing_data.annex);
+    builder.witness[index] = Some(witness);
+    builder.sighashes[index] = Some(sighash.clone());
+
+    Ok(())
+}
+
+/**
+    Signing pipe for P2TR script path spends
+
+    Signing data needs the leaf script and control block.
+    The keys are used untweaked and one signature is created for each key.
+*/
+pub fn p2tr_script_path<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
+    tx_copy: &Tx,
+    index: usize,
+    sighash: &SigHash,
+    signing_data: &SigningData
+) -> Result<(), BuilderErr> {
+    let (script, control_block) = match (&signing_data.script, &signing_data.control_block) {
+        (Some(s), Some(c)) => (s, c),
+        (None, _) => return Err(BuilderErr::RedeemScriptMissing()),
+        (_, None) => return Err(BuilderErr::InvalidControlBlock())
+    };
+    check_annex(&signing_data.annex)?;
+
+    //Check that the control block commits the script to the output key being spent
+    let prevouts: Vec<Output> = taproot_prevouts(builder, index, sighash)?;
+    let output_key = match schnorrsig::PublicKey::from_slice(&prevouts[index].script_pub_key.code[2..]) {
+        Ok(x) => x,
+        Err(_) => return Err(BuilderErr::InvalidControlBlock())
+    };
+    if !control_block.verify(script, &output_key) {
+        return Err(BuilderErr::InvalidControlBlock())
+    }
+
+    //Get the BIP341 hash preimage committing to the leaf being spent and hash it
+    let leaf_hash = taproot::tap_leaf_hash(control_block.leaf_version, script);
+    let hash_preimage = hashpreimage::taproot(tx_copy, sighash, index, &prevouts, &signing_data.annex, Some(leaf_hash))?;
+    let hash: [u8; 32] = hash::tagged_hash("TapSighash", hash_preimage);
+    let msg = match signature::new_msg(&hash) {
+        Ok(x) => x,
+        Err(_) => return Err(BuilderErr::FailedToCreateMessageStruct())
+    };
+
+    //Create a signature for each private key provided, in place of any other script inputs
+    let inputs: Vec<ScriptInput> = match &signing_data.script_inputs {
+        Some(x) => x.clone(),
+        None => signing_data.keys.iter().map(|x| ScriptInput::Signature(*x)).collect()
+    };
+    let mut items: Vec<Vec<u8>> = vec![];
+    for input in inputs {
+        match input {
+            ScriptInput::Signature(key) => {
+                let signature = signature::sign_schnorr(&msg, &taproot::keypair(&key));
+                items.push(Witness::schnorr_sig_bytes(&signature, sighash));
+            },
+            ScriptInput::Item(x) => items.push(x)
+        }
+    }
+
+    //Create the witness and store it and the sighash
+    let witness: Witness = Witness::p2tr_script_path(&items, script, control_block, &signing_data.annex);
+    builder.witness[index] = Some(witness);
+    builder.sighashes[index] = Some(sighash.clone());
+
+    Ok(())
+}
+
+/**
+    The annex must start with 0x50 so it can be told apart from a control block
+*/
+fn check_annex(annex: &Option<Vec<u8>>) -> Result<(), BuilderErr> {
+    match annex {
+        Some(x) if x.first() != Some(&0x50) => Err(BuilderErr::InvalidAnnex()),
+        _ => Ok(())
+    }
+}
+
+/**
+    Get the outputs being spent for creating a Taproot sighash.
+    Only the output being spent by the input being signed is needed if ANYONECANPAY is set,
+    so other inputs are filled with the same output instead of being looked up.
+*/
+fn taproot_prevouts<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
+    index: usize,
+    sighash: &SigHash
+) -> Result<Vec<Output>, BuilderErr> {
+    let anyonecanpay: bool = match sighash {
+        SigHash::ALL_ANYONECANPAY | SigHash::NONE_ANYONECANPAY | SigHash::SINGLE_ANYONECANPAY => true,
+        _ => false
+    };
+
+    let mut prevouts: Vec<Output> = vec![];
+    for i in 0..builder.inputs.len() {
+        if anyonecanpay && i != index {
+            prevouts.push(builder.get_prevout(index)?);
+        } else {
+            prevouts.push(builder.get_prevout(i)?);
+        }
+    }
+
+    Ok(prevouts)
+}
+
 /**
     Sign a legacy P2SH input.
     When constructing a scriptSig, it assumes the P2SH input is
@@ -94,8 +239,8 @@ pub fn p2wpkh(
     However, if no keys are provided in the signing_data, it can handle 
     custom scripts.
 */
-pub fn p2sh(
-    builder: &mut TxBuilder,
+pub fn p2sh<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -161,8 +306,8 @@ pub fn p2sh(
 
     Signing Data needs a redeem script
 */
-pub fn p2wsh(
-    builder: &mut TxBuilder,
+pub fn p2wsh<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -176,7 +321,8 @@ pub fn p2wsh(
         None => return Err(BuilderErr::RedeemScriptMissing())
     };
     let script_code = hashpreimage::script_code(witness_script);
-    let hash_preimage = hashpreimage::segwit(&builder.network, tx_copy, sighash, index, &script_code, &builder.electrum_url)?;
+    let input_value: u64 = builder.get_prevout(index)?.value;
+    let hash_preimage = hashpreimage::segwit(tx_copy, sighash, index, &script_code, input_value)?;
     let hash: [u8; 32] = hash::sha256d(hash_preimage);
 
     //Create a signature for each private key provided. 
@@ -210,8 +356,8 @@ pub fn p2wsh(
 
     Signing data needs one private key to sign the input
 */
-fn p2sh_p2wpkh(
-    builder: &mut TxBuilder,
+fn p2sh_p2wpkh<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -227,9 +373,7 @@ fn p2sh_p2wpkh(
                 
     //Redeem script goes into the scriptSig
     //The scriptSig here is the Witness program
-    let mut script_sig: Vec<u8> = vec![x.code.len() as u8];
-    script_sig.append(&mut x.code.clone());
-    builder.script_sigs[index] = Some(Script::new(script_sig));
+    builder.script_sigs[index] = Some(ScriptBuilder::new().push_slice(&x.code).into_script());
 
     p2wpkh(builder, tx_copy, index, sighash, &x, &signing_data.keys[0])?;
 
@@ -242,8 +386,8 @@ fn p2sh_p2wpkh(
     Signing Data needs to be marked as forcing segwit for this function to be entered.
     Signing data also needs a redeem script.
 */
-fn p2sh_p2wsh(
-    builder: &mut TxBuilder,
+fn p2sh_p2wsh<C: ChainSource>(
+    builder: &mut TxBuilder<C>,
     tx_copy: &Tx,
     index: usize,
     sighash: &SigHash,
@@ -260,9 +404,7 @@ fn p2sh_p2wsh(
     //Redeem script goes into the scriptSig
     //The scriptSig here is the Witness program of the redeemScript
     let x = Script::p2sh_p2wsh_redeem_script(&x);
-    let mut script_sig: Vec<u8> = vec![x.code.len() as u8];
-    script_sig.append(&mut x.code.clone());
-    builder.script_sigs[index] = Some(Script::new(script_sig));
+    builder.script_sigs[index] = Some(ScriptBuilder::new().push_slice(&x.code).into_script());
 
 
     //The script passed into here is the regular redeemScript
@@ -0,0 +1,287 @@
+/*
+    Replace-by-fee (BIP125).
+
+    A transaction can be replaced by one spending any of the same outputs if it
+    signals replaceability with an input sequence below 0xFFFFFFFE and the
+    replacement pays:
+        - at least the absolute fee of the original
+        - on top of that, the incremental relay feerate for its own size
+        - a higher feerate than the original
+*/
+use std::cmp::max;
+use crate::{
+    api::ChainSource,
+    tx::{
+        Input,
+        Output,
+        Tx,
+        FeeRate,
+        ScriptType
+    }
+};
+use super::{
+    BuilderErr,
+    coinselect::Utxo,
+    txbuilder::{
+        TxBuilder,
+        SigningData,
+        SigHash
+    }
+};
+
+//Sequence used to signal replaceability while keeping locktime enabled
+pub const SEQUENCE_RBF: u32 = 0xFFFFFFFD;
+
+impl<C: ChainSource> TxBuilder<C> {
+    /**
+        Signal that the transaction can be replaced by setting the sequence of every
+        input that does not already signal to 0xFFFFFFFD
+    */
+    pub fn enable_rbf(&mut self) -> Result<(), BuilderErr> {
+        for i in 0..self.inputs.len() {
+            if self.inputs[i].sequence > SEQUENCE_RBF {
+                self.set_sequence(i, SEQUENCE_RBF)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Create and sign a replacement for a transaction that pays at least the given feerate.
+
+        The builder is loaded with the inputs and outputs of the original transaction, keeping
+        its version, locktime and sequences. The fee is raised by taking value from the output
+        paying the change address and, if that is not enough, by adding inputs from the given
+        UTXOs starting with the largest. Inputs are only added when a change address is set
+        so a change output can take the excess, otherwise it would all go to the fee.
+        Signing data is given for every input of the original transaction in order and with each UTXO.
+    */
+    pub fn bump_fee(&mut self, original: &Tx, feerate: FeeRate, signing_data: &Vec<SigningData>, utxos: &Vec<(Utxo, SigningData)>) -> Result<Tx, BuilderErr> {
+        if !original.signals_rbf() {
+            return Err(BuilderErr::NotReplaceable())
+        }
+        if signing_data.len() != original.inputs.len() {
+            return Err(BuilderErr::InvalidSigningData())
+        }
+
+        self.load_tx(original);
+        let original_fee: u64 = match original.fee(&self.get_prevouts()?) {
+            Ok(x) => x,
+            Err(_) => return Err(BuilderErr::CannotGetInputValue())
+        };
+        let original_feerate: FeeRate = FeeRate::from_fee_and_vsize(original_fee, original.vsize());
+
+        //Pay more than the original feerate and the incremental relay fee on top of the original fee
+        let min_feerate: FeeRate = max(feerate, FeeRate::from_sat_per_kvb(original_feerate.sat_per_kvb() + 1));
+        let required_fee = |vsize: u64| max(min_feerate.fee_for_vsize(vsize), original_fee + FeeRate::INCREMENTAL_RELAY.fee_for_vsize(vsize));
+
+        //Add inputs until the fee can be paid
+        let mut signing_data: Vec<SigningData> = signing_data.clone();
+        let mut extra: Vec<&(Utxo, SigningData)> = utxos.iter().collect();
+        extra.sort_by_key(|x| std::cmp::Reverse(x.0.output.value));
+        let mut extra = extra.into_iter();
+        loop {
+            match self.finalize_with_fee(required_fee, &signing_data) {
+                Ok(_) => break,
+                Err(BuilderErr::InsufficientFunds(available, needed)) => match extra.next() {
+                    Some(_) if self.change_address.is_none() => return Err(BuilderErr::ChangeAddressMissing()),
+                    Some((utxo, data)) => {
+                        self.add_input_with_prevout(&utxo.txid, utxo.vout, utxo.output.clone())?;
+                        let index: usize = self.inputs.len() - 1;
+                        self.inputs[index].sequence = SEQUENCE_RBF;
+                        signing_data.push(data.clone());
+                    },
+                    None => return Err(BuilderErr::InsufficientFunds(available, needed))
+                },
+                Err(x) => return Err(x)
+            }
+        }
+
+        for i in 0..self.inputs.len() {
+            let sighash: SigHash = match self.get_prevout(i)?.script_pub_key.determine_type() {
+                ScriptType::P2TR => SigHash::DEFAULT,
+                _ => SigHash::ALL
+            };
+            self.sign_input(i, &signing_data[i], sighash)?;
+        }
+        let tx: Tx = self.build()?;
+
+        //Check the replacement rules against the signed transaction
+        let prevouts: Vec<Output> = self.get_prevouts()?;
+        let (fee, replacement_feerate) = match (tx.fee(&prevouts), tx.feerate(&prevouts)) {
+            (Ok(x), Ok(y)) => (x, y),
+            _ => return Err(BuilderErr::CannotGetInputValue())
+        };
+        let needed: u64 = original_fee + FeeRate::INCREMENTAL_RELAY.fee_for_vsize(tx.vsize());
+        if fee < needed || replacement_feerate <= original_feerate {
+            return Err(BuilderErr::ReplacementFeeTooLow(fee, needed))
+        }
+
+        Ok(tx)
+    }
+
+    /**
+        Replace the contents of the builder with an unsigned copy of the transaction.
+        Prevouts already known for the same outpoints are kept and the output paying
+        the change address becomes the change output.
+    */
+    fn load_tx(&mut self, tx: &Tx) {
+        let prevouts: Vec<Option<Output>> = tx.inputs.iter().map(|input| {
+            let known = self.inputs.iter().position(|x| x.txid == input.txid && x.vout == input.vout);
+            match known {
+                Some(i) => self.prevouts[i].clone(),
+                None => None
+            }
+        }).collect();
+
+        self.version = tx.version;
+        self.locktime = tx.locktime;
+        self.inputs = tx.inputs.iter().map(|x| Input::unsigned_input(x.txid, x.vout, x.sequence)).collect();
+        self.outputs = tx.outputs.clone();
+        self.script_sigs = vec![None; tx.inputs.len()];
+        self.witness = vec![None; tx.inputs.len()];
+        self.sighashes = vec![None; tx.inputs.len()];
+        self.prevouts = prevouts;
+        self.change_index = match &self.change_address {
+            Some(address) => {
+                let change_script = address.script_pub_key();
+                self.outputs.iter().position(|x| x.script_pub_key.code == change_script.code)
+            },
+            None => None
+        };
+    }
+
+    /**
+        Outputs spent by every input in order
+    */
+    fn get_prevouts(&mut self) -> Result<Vec<Output>, BuilderErr> {
+        let mut prevouts: Vec<Output> = vec![];
+        for i in 0..self.inputs.len() {
+            prevouts.push(self.get_prevout(i)?);
+        }
+
+        Ok(prevouts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PrivKey,
+        tx::VerifyFlags,
+        util::Network,
+        test_utils::{ PREV_TXID, key, p2wpkh_output }
+    };
+
+    const CHANGE: &str = "tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e";
+
+    //Builder with a signed transaction spending a 100000 sat P2WPKH output to 60000 sats and change
+    fn signed_original(key: PrivKey) -> (TxBuilder, Tx) {
+        let signing_data = vec![SigningData::new(vec![key], None)];
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, p2wpkh_output(&key, 100000)).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 60000).unwrap();
+        txb.set_change_address(CHANGE).unwrap();
+        txb.enable_rbf().unwrap();
+        txb.finalize_with_feerate(FeeRate::from_sat_per_vb(2), &signing_data).unwrap();
+        txb.sign_input(0, &signing_data[0], SigHash::ALL).unwrap();
+        let tx = txb.build().unwrap();
+
+        (txb, tx)
+    }
+
+    #[test]
+    fn bump_fee_from_change() {
+        let signer = key(5);
+        let signing_data = vec![SigningData::new(vec![signer], None)];
+        let (mut txb, original) = signed_original(signer);
+        assert!(original.signals_rbf());
+        assert_eq!(original.inputs[0].sequence, SEQUENCE_RBF);
+        let prevouts = txb.get_prevouts().unwrap();
+        let original_fee = original.fee(&prevouts).unwrap();
+
+        //The fee comes out of the change output
+        let replacement = txb.bump_fee(&original, FeeRate::from_sat_per_vb(10), &signing_data, &vec![]).unwrap();
+        replacement.verify(&prevouts, VerifyFlags::standard()).unwrap();
+        assert_ne!(replacement.get_txid(), original.get_txid());
+        assert_eq!(replacement.outputs[0].value, 60000);
+        assert!(replacement.outputs[1].value < original.outputs[1].value);
+        assert!(replacement.feerate(&prevouts).unwrap() >= FeeRate::from_sat_per_vb(10));
+        assert!(replacement.signals_rbf());
+
+        //Bumping at the same feerate still pays the incremental relay fee on top of the original fee
+        let replacement = txb.bump_fee(&original, FeeRate::from_sat_per_vb(2), &signing_data, &vec![]).unwrap();
+        assert!(replacement.fee(&prevouts).unwrap() >= original_fee + replacement.vsize());
+        assert!(replacement.feerate(&prevouts).unwrap() > original.feerate(&prevouts).unwrap());
+
+        //Transactions that do not signal cannot be replaced
+        let mut final_tx = original.clone();
+        final_tx.inputs[0].sequence = 0xFFFFFFFF;
+        assert!(matches!(txb.bump_fee(&final_tx, FeeRate::from_sat_per_vb(10), &signing_data, &vec![]), Err(BuilderErr::NotReplaceable())));
+    }
+
+    #[test]
+    fn bump_fee_with_new_inputs() {
+        let signer = key(6);
+        let signing_data = vec![SigningData::new(vec![signer], None)];
+        let (mut txb, original) = signed_original(signer);
+
+        //Change alone cannot pay 500 sat/vB
+        match txb.bump_fee(&original, FeeRate::from_sat_per_vb(500), &signing_data, &vec![]) {
+            Err(BuilderErr::InsufficientFunds(100000, _)) => { },
+            x => panic!("{:?}", x)
+        }
+
+        //The largest UTXO is added first and is enough by itself
+        let extra_key = key(7);
+        let utxo = |value: u64, vout: u32| {
+            let output = p2wpkh_output(&extra_key, value);
+            let data = SigningData::new(vec![extra_key], None);
+            (Utxo::new("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", vout, output, &data).unwrap(), data)
+        };
+        let utxos = vec![utxo(1000, 0), utxo(200000, 1)];
+        let replacement = txb.bump_fee(&original, FeeRate::from_sat_per_vb(500), &signing_data, &utxos).unwrap();
+        assert_eq!(replacement.inputs.len(), 2);
+        assert_eq!(replacement.inputs[1].vout, 1);
+        assert_eq!(replacement.inputs[1].sequence, SEQUENCE_RBF);
+
+        let prevouts = txb.get_prevouts().unwrap();
+        replacement.verify(&prevouts, VerifyFlags::standard()).unwrap();
+        assert!(replacement.feerate(&prevouts).unwrap() >= FeeRate::from_sat_per_vb(500));
+        assert_eq!(replacement.outputs[1].script_pub_key.code, Output::new(CHANGE, 0, &Network::Testnet).unwrap().script_pub_key.code);
+    }
+
+    #[test]
+    fn bump_fee_without_change() {
+        let signer = key(8);
+        let signing_data = vec![SigningData::new(vec![signer], None)];
+        let extra_key = key(9);
+        let extra_data = SigningData::new(vec![extra_key], None);
+        let utxos = vec![(Utxo::new(PREV_TXID, 1, p2wpkh_output(&extra_key, 200000), &extra_data).unwrap(), extra_data)];
+
+        //Original paying 200 sats without a change output
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, p2wpkh_output(&signer, 100000)).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 99800).unwrap();
+        txb.enable_rbf().unwrap();
+        txb.sign_input(0, &signing_data[0], SigHash::ALL).unwrap();
+        let original = txb.build().unwrap();
+
+        //Added inputs would be burnt entirely as fee without a change address
+        assert!(matches!(txb.bump_fee(&original, FeeRate::from_sat_per_vb(5), &signing_data, &utxos), Err(BuilderErr::ChangeAddressMissing())));
+        assert_eq!(txb.inputs.len(), 1);
+
+        //With a change address the excess goes to a new change output
+        txb.set_change_address(CHANGE).unwrap();
+        let replacement = txb.bump_fee(&original, FeeRate::from_sat_per_vb(5), &signing_data, &utxos).unwrap();
+        assert_eq!(replacement.inputs.len(), 2);
+        assert_eq!(replacement.outputs.len(), 2);
+        assert_eq!(replacement.outputs[1].script_pub_key.code, Output::new(CHANGE, 0, &Network::Testnet).unwrap().script_pub_key.code);
+
+        let prevouts = txb.get_prevouts().unwrap();
+        replacement.verify(&prevouts, VerifyFlags::standard()).unwrap();
+        assert!(replacement.outputs[1].value > 100000);
+    }
+}
@@ -11,22 +11,41 @@ use crate::{
         Input,
         Output,
         Tx,
+        FeeRate,
         Script,
-        ScriptType
+        ScriptType,
+        ScriptErr,
+        Address,
+        AddressErr,
+        VerifyFlags,
+        InterpreterErr
     },
     PrivKey,
     api,
+    api::ChainSource,
     util::{
         Network,
-        bytes
+        bytes,
+        serialize::Serialize,
+        varint::VarInt
     },
-    tx::Witness
+    tx::Witness,
+    tx::taproot::ControlBlock
+};
+use super::{
+    pipes,
+    estimate,
+    coinselect::{
+        CoinSelector,
+        Selection,
+        Utxo
+    }
 };
-use super::pipes;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone)]
 pub enum SigHash {
+    DEFAULT = 0x00,             //Taproot only. Same as ALL but the signature has no sighash byte.
     ALL = 0x01,                 //All inputs and outputs are committed. 
     NONE = 0x02,                //All inputs are committed. No outputs.
     SINGLE = 0x03,              //All inputs and the output with the same index as the input being signed are committed.
@@ -35,22 +54,83 @@ pub enum SigHash {
     SINGLE_ANYONECANPAY = 0x83  //Current input and output with the same index are committed.
 }
 
+impl SigHash {
+    /**
+        Get the SigHash from its numeric value
+    */
+    pub fn from_u32(sighash: u32) -> Option<Self> {
+        match sighash {
+            0x00 => Some(SigHash::DEFAULT),
+            0x01 => Some(SigHash::ALL),
+            0x02 => Some(SigHash::NONE),
+            0x03 => Some(SigHash::SINGLE),
+            0x81 => Some(SigHash::ALL_ANYONECANPAY),
+            0x82 => Some(SigHash::NONE_ANYONECANPAY),
+            0x83 => Some(SigHash::SINGLE_ANYONECANPAY),
+            _ => None
+        }
+    }
+
+    /**
+        Returns true if inputs can still be added after signing with this SigHash
+    */
+    pub fn allows_new_inputs(&self) -> bool {
+        match self {
+            SigHash::ALL_ANYONECANPAY |
+            SigHash::NONE_ANYONECANPAY |
+            SigHash::SINGLE_ANYONECANPAY => true,
+            _ => false
+        }
+    }
+
+    /**
+        Returns true if outputs can still be added after signing with this SigHash
+    */
+    pub fn allows_new_outputs(&self) -> bool {
+        match self {
+            SigHash::DEFAULT |
+            SigHash::ALL |
+            SigHash::ALL_ANYONECANPAY => false,
+            _ => true
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct TxBuilder {
+pub struct TxBuilder<C: ChainSource = api::Electrum> {
     pub network: Network,
+    pub version: u32,
+    pub locktime: u32,
     pub inputs: Vec<Input>,
     pub outputs: Vec<Output>,
     pub script_sigs: Vec<Option<Script>>, //scriptSigs are stored in this attribute
     pub witness: Vec<Option<Witness>>,    //witnesses are stored in this attribute
     pub sighashes: Vec<Option<SigHash>>,  //SigHash is stored to detect if new inputs/outputs can be added
-    pub electrum_url: Option<String>      //Electrum server url
+    pub prevouts: Vec<Option<Output>>,    //Outputs being spent by each input. Looked up through the chain source if not provided
+    pub source: Option<C>,                //Chain source used for lookups. Electrum is used if not set
+    pub electrum_url: Option<String>,     //Electrum server url
+    pub change_address: Option<Address>,  //Address that receives the value left over after the fee
+    pub change_index: Option<usize>       //Index of the change output once it has been added
 }
 
 #[derive(Clone)]
 pub struct SigningData {
     pub keys: Vec<PrivKey>,
     pub script: Option<Script>,
-    pub force_segwit: bool
+    pub force_segwit: bool,
+    pub merkle_root: Option<[u8; 32]>,          //Taproot script tree root used to tweak the key
+    pub control_block: Option<ControlBlock>,    //Taproot control block for script path spends
+    pub annex: Option<Vec<u8>>,                 //Taproot annex
+    pub script_inputs: Option<Vec<ScriptInput>> //Taproot script path inputs, the first at the top of the stack
+}
+
+/**
+    An item of the witness that satisfies a Taproot leaf script
+*/
+#[derive(Clone)]
+pub enum ScriptInput {
+    Signature(PrivKey),     //Schnorr signature by the key
+    Item(Vec<u8>)           //Any other item such as a hash preimage, an empty vector for an absent key or a branch selector
 }
 
 impl SigningData {
@@ -61,7 +141,11 @@ impl SigningData {
         Self {
             keys,
             script,
-            force_segwit: false
+            force_segwit: false,
+            merkle_root: None,
+            control_block: None,
+            annex: None,
+            script_inputs: None
         }
     }
 
@@ -78,9 +162,80 @@ impl SigningData {
         Self {
             keys,
             script: Some(script),
-            force_segwit: true
+            force_segwit: true,
+            merkle_root: None,
+            control_block: None,
+            annex: None,
+            script_inputs: None
+        }
+    }
+
+    /**
+        Signing data for Taproot key path spends.
+        The merkle root of the output's script tree is needed to tweak the internal key. 
+        If the output has no script tree, SigningData::new can be used instead.
+    */
+    pub fn taproot_key_path(key: PrivKey, merkle_root: Option<[u8; 32]>) -> Self {
+        Self {
+            keys: vec![key],
+            script: None,
+            force_segwit: false,
+            merkle_root,
+            control_block: None,
+            annex: None,
+            script_inputs: None
+        }
+    }
+
+    /**
+        Signing data for Taproot script path spends.
+        The script is the leaf script being spent and the control block proves it is in the output's script tree.
+
+        A signature is created for each key and placed in the witness so that the first key's
+        signature is at the top of the stack.
+    */
+    pub fn taproot_script_path(keys: Vec<PrivKey>, script: Script, control_block: ControlBlock) -> Self {
+        Self {
+            keys,
+            script: Some(script),
+            force_segwit: false,
+            merkle_root: None,
+            control_block: Some(control_block),
+            annex: None,
+            script_inputs: None
+        }
+    }
+
+    /**
+        Signing data for Taproot script path spends that need more than signatures.
+        The inputs are placed in the witness in order so that the first is at the top of the stack,
+        with a signature created for each ScriptInput::Signature.
+    */
+    pub fn taproot_script_path_with_inputs(inputs: Vec<ScriptInput>, script: Script, control_block: ControlBlock) -> Self {
+        let keys: Vec<PrivKey> = inputs.iter().filter_map(|x| match x {
+            ScriptInput::Signature(key) => Some(*key),
+            ScriptInput::Item(_) => None
+        }).collect();
+
+        Self {
+            keys,
+            script: Some(script),
+            force_segwit: false,
+            merkle_root: None,
+            control_block: Some(control_block),
+            annex: None,
+            script_inputs: Some(inputs)
         }
     }
+
+    /**
+        Attach an annex to Taproot signing data.
+        The annex must start with 0x50.
+    */
+    pub fn with_annex(mut self, annex: Vec<u8>) -> Self {
+        self.annex = Some(annex);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -96,19 +251,66 @@ pub enum BuilderErr {
     CannotGetInputValue(),
     InvalidSigningData(),
     RedeemScriptMissing(),
-    CannotGetElectrum
+    CannotGetElectrum,
+    InvalidSigHash(),
+    FailedToTweakKey(),
+    InvalidControlBlock(),
+    InvalidAnnex(),
+    ScriptVerifyFailed(usize, InterpreterErr),
+    InsufficientFunds(u64, u64),    //Value available and value needed
+    NotReplaceable(),
+    ChangeAddressMissing(),
+    InvalidScript(ScriptErr),
+    InvalidAddress(AddressErr),
+    FeeUnavailable(),
+    ReplacementFeeTooLow(u64, u64), //Fee paid and fee needed
+    HasInputs(),                    //Builder must be empty of inputs
+    NoOutputs(),                    //Transaction would have no outputs
+    MultipleOpReturn()
 }
 
 impl TxBuilder {
+    /**
+        Create a new builder that looks up missing data through Electrum
+    */
     pub fn new(network: Network) -> Self {
         Self {
             network,
+            version: 1,
+            locktime: 0,
             inputs: vec![],
             outputs: vec![],
             script_sigs: vec![],
             witness: vec![],
             sighashes: vec![],
-            electrum_url: None
+            prevouts: vec![],
+            source: None,
+            electrum_url: None,
+            change_address: None,
+            change_index: None
+        }
+    }
+}
+
+impl<C: ChainSource> TxBuilder<C> {
+    /**
+        Create a new builder that looks up missing data through the given chain source
+    */
+    pub fn with_source(network: Network, source: C) -> Self {
+        Self {
+            network,
+            version: 1,
+            locktime: 0,
+            inputs: vec![],
+            outputs: vec![],
+            script_sigs: vec![],
+            witness: vec![],
+            sighashes: vec![],
+            prevouts: vec![],
+            source: Some(source),
+            electrum_url: None,
+            change_address: None,
+            change_index: None
         }
     }
 
@@ -120,15 +322,108 @@ impl TxBuilder {
     }
 
     /**
-        Add a new input from txid and output index
+        Set the version of the transaction.
+        Version 2 or higher is needed for inputs to use relative locktimes (BIP68).
+    */
+    pub fn set_version(&mut self, version: u32) -> Result<(), BuilderErr> {
+        //Every signature commits to the version
+        if self.sighashes.iter().any(|x| x.is_some()) {
+            return Err(BuilderErr::TxCommitted())
+        }
+        self.version = version;
+
+        Ok(())
+    }
+
+    /**
+        Set the locktime of the transaction.
+        The locktime is only enforced if at least one input has a sequence below 0xFFFFFFFF.
+    */
+    pub fn set_locktime(&mut self, locktime: u32) -> Result<(), BuilderErr> {
+        //Every signature commits to the locktime
+        if self.sighashes.iter().any(|x| x.is_some()) {
+            return Err(BuilderErr::TxCommitted())
+        }
+        self.locktime = locktime;
+
+        Ok(())
+    }
+
+    /**
+        Set the sequence of the input at the given index.
+        Inputs are added with a sequence of 0xFFFFFFFF.
+    */
+    pub fn set_sequence(&mut self, index: usize, sequence: u32) -> Result<(), BuilderErr> {
+        if self.inputs.len() <= index {
+            return Err(BuilderErr::InvalidInputIndex(index))
+        }
+
+        //The input's own signature commits to its sequence and so do signatures on
+        //other inputs that commit to every input
+        for i in 0..self.sighashes.len() {
+            let sighash: SigHash = match &self.sighashes[i] {
+                Some(_) if i == index => return Err(BuilderErr::TxCommitted()),
+                Some(x) => x.clone(),
+                None => continue
+            };
+
+            //Signed inputs already have their prevout stored so this does not look it up.
+            //Taproot signatures commit to all sequences unless ANYONECANPAY is set (BIP-341),
+            //other signatures only do so with SIGHASH_ALL.
+            let committed: bool = match (self.get_prevout(i)?.script_pub_key.determine_type(), &sighash) {
+                (_, SigHash::DEFAULT) | (_, SigHash::ALL) => true,
+                (ScriptType::P2TR, x) => !x.allows_new_inputs(),
+                _ => false
+            };
+            if committed {
+                return Err(BuilderErr::TxCommitted())
+            }
+        }
+        self.inputs[index].sequence = sequence;
+
+        Ok(())
+    }
+
+    /**
+        Set the address that the value left over after the outputs and fee is sent to
+    */
+    pub fn set_change_address(&mut self, address: &str) -> Result<(), BuilderErr> {
+        match Address::parse(address, &self.network) {
+            Ok(x) => self.change_address = Some(x),
+            Err(x) => return Err(BuilderErr::InvalidAddress(x))
+        }
+
+        Ok(())
+    }
+
+    /**
+        Add a new input from txid and output index.
+        The output being spent will be looked up when signing.
     */
     pub fn add_input(&mut self, txid: &str, vout: u32) -> Result<(), BuilderErr> {
+        self.push_input(txid, vout, None)
+    }
+
+    /**
+        Add a new input from txid and output index along with the output it spends.
+        Inputs added this way can be signed without a connection to an Electrum server.
+    */
+    pub fn add_input_with_prevout(&mut self, txid: &str, vout: u32, prevout: Output) -> Result<(), BuilderErr> {
+        self.push_input(txid, vout, Some(prevout))
+    }
+
+    /**
+        Add a new input spending an unspent output listed by an Electrum server
+    */
+    pub fn add_unspent(&mut self, unspent: &api::Unspent) -> Result<(), BuilderErr> {
+        self.push_input(&unspent.txid, unspent.vout, Some(unspent.output.clone()))
+    }
+
+    fn push_input(&mut self, txid: &str, vout: u32, prevout: Option<Output>) -> Result<(), BuilderErr> {
         //Check if an input has been signed that does not allow for new inputs
         for i in 0..self.sighashes.len() {
-            match self.sighashes[i] {
-                Some(SigHash::ALL) |
-                Some(SigHash::NONE) |
-                Some(SigHash::SINGLE) => return Err(BuilderErr::TxCommitted()),
+            match &self.sighashes[i] {
+                Some(x) if !x.allows_new_inputs() => return Err(BuilderErr::TxCommitted()),
                 _ => { /* New input can be added */}
             }
         }
@@ -139,25 +434,69 @@ impl TxBuilder {
         self.script_sigs.push(None);
         self.witness.push(None);
         self.sighashes.push(None);
+        self.prevouts.push(prevout);
 
         Ok(())
     }
 
+    /**
+        Select inputs from the given UTXOs to pay for the outputs at the selector's feerate
+        and add them to the builder. Inputs that are already in the builder are not counted.
+    */
+    pub fn select_inputs(&mut self, utxos: &Vec<Utxo>, selector: &CoinSelector) -> Result<Selection, BuilderErr> {
+        let selection: Selection = selector.select(utxos, &self.outputs)?;
+        for utxo in &selection.utxos {
+            self.add_input_with_prevout(&utxo.txid, utxo.vout, utxo.output.clone())?;
+        }
+
+        Ok(selection)
+    }
+
     /**
         Add a new output with recepeint address and value
     */
     pub fn add_output(&mut self, address: &str, value: u64) -> Result<(), BuilderErr> {
+        match Address::parse(address, &self.network) {
+            Ok(x) => self.push_output(Output::from_address(&x, value)),
+            Err(x) => Err(BuilderErr::InvalidAddress(x))
+        }
+    }
+
+    /**
+        Add a new output locked by any scriptPubKey
+    */
+    pub fn add_output_script(&mut self, script_pub_key: Script, value: u64) -> Result<(), BuilderErr> {
+        self.push_output(Output::from_script(script_pub_key, value))
+    }
+
+    /**
+        Add a zero value null data output carrying the data.
+        The data can be at most 80 bytes and only one null data output is relayed per transaction.
+    */
+    pub fn add_op_return(&mut self, data: &[u8]) -> Result<(), BuilderErr> {
+        let output: Output = match Output::op_return(data) {
+            Ok(x) => x,
+            Err(x) => return Err(BuilderErr::InvalidScript(x))
+        };
+
+        self.push_output(output)
+    }
+
+    fn push_output(&mut self, output: Output) -> Result<(), BuilderErr> {
         //Check if an input has been signed that does not allow for new outputs
         for i in 0..self.sighashes.len() {
-            match self.sighashes[i] {
-                Some(SigHash::ALL) |
-                Some(SigHash::ALL_ANYONECANPAY) => return Err(BuilderErr::TxCommitted()),
-                _ => { /* New input can be added */}
+            match &self.sighashes[i] {
+                Some(x) if !x.allows_new_outputs() => return Err(BuilderErr::TxCommitted()),
+                _ => { /* New output can be added */}
             }
         }
+
+        //Transactions with more than one null data output are not relayed
+        if output.script_pub_key.is_op_return() && self.outputs.iter().any(|x| x.script_pub_key.is_op_return()) {
+            return Err(BuilderErr::MultipleOpReturn())
+        }
         
-        let new_out: Output = Output::new(address, value);
-        self.outputs.push(new_out);
+        self.outputs.push(output);
 
         Ok(())
     }
@@ -167,20 +506,27 @@ impl TxBuilder {
     */  
     pub fn sign_input(&mut self, index: usize, signing_data: &SigningData, sighash: SigHash) -> Result<(), BuilderErr> {
         //Return an error if the given input index is larger than the total amount of inputs
-        if self.inputs.len() < index {
+        if self.inputs.len() <= index {
             return Err(BuilderErr::InvalidInputIndex(index))
         }
         
         //Create a copy of the transaction
-        let tx_copy: Tx = Tx::construct(self.inputs.clone(), self.outputs.clone(), 0, false);
+        let tx_copy: Tx = self.unsigned_tx();
 
         //Get the unlocking script type of the input
-        let script_pub_key: Script = Script::new(Self::get_input_script_pub_key(&self, index)?);
+        let script_pub_key: Script = self.get_prevout(index)?.script_pub_key;
         let input_script_type: ScriptType = script_pub_key.determine_type();
         match input_script_type {
-                ScriptType::P2WPKH | ScriptType::P2WSH => self.inputs[index].segwit = true,
+                ScriptType::P2WPKH | ScriptType::P2WSH | ScriptType::P2TR => self.inputs[index].segwit = true,
                 _ => { }
         }
+
+        //SigHash DEFAULT only exists for Taproot inputs
+        match (&input_script_type, &sighash) {
+            (ScriptType::P2TR, _) => { },
+            (_, SigHash::DEFAULT) => return Err(BuilderErr::InvalidSigHash()),
+            _ => { }
+        }
         
         //Sign the input and set the script_sig based on the locking script
         match input_script_type {
@@ -202,6 +548,19 @@ impl TxBuilder {
             },
             ScriptType::P2SH => pipes::p2sh(self, &tx_copy, index, &sighash, &signing_data)?,
             ScriptType::P2WSH => pipes::p2wsh(self, &tx_copy, index, &sighash, &signing_data)?,
+            ScriptType::P2TR => {
+                match signing_data.control_block {
+                    //Script path if a control block is provided
+                    Some(_) => pipes::p2tr_script_path(self, &tx_copy, index, &sighash, &signing_data)?,
+
+                    //Only sign p2tr key path if 1 key is provided
+                    None => if signing_data.keys.len() == 1 {
+                        pipes::p2tr(self, &tx_copy, index, &sighash, &signing_data)?;
+                    } else {
+                        return Err(BuilderErr::InvalidSigningData())
+                    }
+                }
+            },
             ScriptType::NonStandard => return Err(BuilderErr::UnknownScriptType())
         }
 
@@ -209,30 +568,191 @@ impl TxBuilder {
     }
 
     /**
-        Get the scriptPubKey for the input being signed
+        Get the output being spent by the input at the given index.
+        If it was not provided when adding the input, it is looked up through the chain source
+        (or Electrum if no source is set) and stored so later lookups do not need a connection.
     */
-    fn get_input_script_pub_key(&self, index: usize) -> Result<Vec<u8> ,BuilderErr> {
-        let rpc = match api::Electrum::new(&self.electrum_url, &self.network) {
+    pub(crate) fn get_prevout(&mut self, index: usize) -> Result<Output, BuilderErr> {
+        if let Some(x) = &self.prevouts[index] {
+            return Ok(x.clone())
+        }
+
+        let txid: String = bytes::encode_02x(&self.inputs[index].txid);
+        let vout: u32 = self.inputs[index].vout;
+        let prevout = match &self.source {
+            Some(source) => source.get_prevout(&txid, vout),
+            None => {
+                let rpc = match api::Electrum::new(&self.electrum_url, &self.network) {
+                    Ok(x) => x,
+                    Err(_) => return Err(BuilderErr::CannotGetElectrum)
+                };
+                ChainSource::get_prevout(&rpc, &txid, vout)
+            }
+        };
+        let prevout: Output = match prevout {
             Ok(x) => x,
-            Err(_) => return Err(BuilderErr::CannotGetElectrum)
+            Err(_) => return Err(BuilderErr::CannotGetScriptPubKey(txid, index))
         };
+        self.prevouts[index] = Some(prevout.clone());
 
-        let input_spkhex: Vec<u8> = match rpc.get_input_script_pubkey(&bytes::encode_02x(&self.inputs[index].txid), self.inputs[index].vout as usize) {
-            Ok(x) => x,
-            Err(_) => return Err(BuilderErr::CannotGetScriptPubKey(bytes::encode_02x(&self.inputs[index].txid), index))
+        Ok(prevout)
+    }
+
+    /**
+        Estimate the virtual size of the transaction once every input is signed.
+
+        Signed inputs are measured as they are. Unsigned inputs are estimated with the
+        largest possible signatures from the signing data that will be used to sign them.
+        Signing data is given for every input in the same order as the inputs.
+    */
+    pub fn estimate_vsize(&mut self, signing_data: &Vec<SigningData>) -> Result<u64, BuilderErr> {
+        if signing_data.len() != self.inputs.len() {
+            return Err(BuilderErr::InvalidSigningData())
+        }
+
+        let mut input_weight: u64 = 0;
+        let mut segwit_inputs: usize = 0;
+        for i in 0..self.inputs.len() {
+            let (script_sig, witness) = if self.script_sigs[i].is_some() || self.witness[i].is_some() {
+                (self.script_sigs[i].clone().unwrap_or(Script::new(vec![])), self.witness[i].clone())
+            } else {
+                let script_pub_key: Script = self.get_prevout(i)?.script_pub_key;
+                estimate::dummy_satisfaction(&script_pub_key, &signing_data[i])?
+            };
+            if witness.is_some() { segwit_inputs += 1 }
+            input_weight += estimate::satisfied_weight(&script_sig, &witness);
+        }
+
+        //Version, locktime, input and output counts and the outputs
+        let mut size: usize = 8;
+        size += VarInt::from_usize(self.inputs.len()).unwrap().len();
+        size += VarInt::from_usize(self.outputs.len()).unwrap().len();
+        for output in &self.outputs {
+            size += match output.serialize() {
+                Ok(x) => x.len(),
+                Err(_) => return Err(BuilderErr::FailedToSerialize())
+            };
+        }
+        let mut weight: u64 = size as u64 * 4 + input_weight;
+
+        //Segwit transactions have the marker and flag and an empty witness for legacy inputs
+        if segwit_inputs > 0 {
+            weight += 2 + (self.inputs.len() - segwit_inputs) as u64;
+        }
+
+        Ok((weight + 3) / 4)
+    }
+
+    /**
+        Set the fee from the estimated size of the signed transaction at the given feerate and
+        send the value left over to the change address. This is done before signing, using the
+        signing data that each input will be signed with.
+
+        The change output is left out if there is no change address or if it would be dust,
+        in which case the left over value goes to the fee. Calling this again replaces the
+        change output. Returns the fee paid by the transaction.
+    */
+    pub fn finalize_with_feerate(&mut self, feerate: FeeRate, signing_data: &Vec<SigningData>) -> Result<u64, BuilderErr> {
+        self.finalize_with_fee(|vsize| feerate.fee_for_vsize(vsize), signing_data)
+    }
+
+    /**
+        Same as finalize_with_feerate with the feerate estimated to confirm within the target
+        amount of blocks
+    */
+    pub fn finalize_with_target(&mut self, target: usize, signing_data: &Vec<SigningData>) -> Result<u64, BuilderErr> {
+        let feerate: FeeRate = self.estimate_feerate(target)?;
+        self.finalize_with_feerate(feerate, signing_data)
+    }
+
+    /**
+        Estimate the feerate needed to confirm within the target amount of blocks through
+        the chain source (or Electrum if no source is set)
+    */
+    pub fn estimate_feerate(&self, target: usize) -> Result<FeeRate, BuilderErr> {
+        let feerate = match &self.source {
+            Some(source) => source.estimate_fee(target),
+            None => {
+                let rpc = match api::Electrum::new(&self.electrum_url, &self.network) {
+                    Ok(x) => x,
+                    Err(_) => return Err(BuilderErr::CannotGetElectrum)
+                };
+                ChainSource::estimate_fee(&rpc, target)
+            }
         };
 
-        Ok(input_spkhex)
+        match feerate {
+            Ok(x) => Ok(x),
+            Err(_) => Err(BuilderErr::FeeUnavailable())
+        }
+    }
+
+    /**
+        Same as finalize_with_feerate but the fee needed for a virtual size is given by a function
+    */
+    pub(crate) fn finalize_with_fee<F: Fn(u64) -> u64>(&mut self, fee_for_vsize: F, signing_data: &Vec<SigningData>) -> Result<u64, BuilderErr> {
+        //Check if an input has been signed that does not allow for the outputs to change
+        for i in 0..self.sighashes.len() {
+            match &self.sighashes[i] {
+                Some(x) if !x.allows_new_outputs() => return Err(BuilderErr::TxCommitted()),
+                _ => { /* Change can be added */ }
+            }
+        }
+
+        //Remove the change output from an earlier call
+        if let Some(index) = self.change_index.take() {
+            self.outputs.remove(index);
+        }
+
+        let mut input_value: u64 = 0;
+        for i in 0..self.inputs.len() {
+            input_value += self.get_prevout(i)?.value;
+        }
+        let output_value: u64 = self.outputs.iter().map(|x| x.value).sum();
+
+        let fee: u64 = fee_for_vsize(self.estimate_vsize(signing_data)?);
+        if input_value < output_value + fee {
+            return Err(BuilderErr::InsufficientFunds(input_value, output_value + fee))
+        }
+
+        let change_output: Output = match &self.change_address {
+            Some(x) => Output::from_address(x, 0),
+            None => return Ok(input_value - output_value)
+        };
+
+        //The change output pays for its own size
+        self.outputs.push(change_output);
+        let index: usize = self.outputs.len() - 1;
+        let fee_with_change: u64 = fee_for_vsize(self.estimate_vsize(signing_data)?);
+        let change_value: u64 = (input_value - output_value).saturating_sub(fee_with_change);
+        if change_value < self.outputs[index].dust_limit() {
+            self.outputs.pop();
+            return Ok(input_value - output_value)
+        }
+        self.outputs[index].value = change_value;
+        self.change_index = Some(index);
+
+        Ok(fee_with_change)
+    }
+
+    /**
+        Copy of the transaction without any scriptSigs or witnesses that signatures are made over
+    */
+    fn unsigned_tx(&self) -> Tx {
+        let mut tx: Tx = Tx::construct(self.inputs.clone(), self.outputs.clone(), self.locktime, false);
+        tx.version = self.version;
+
+        tx
     }
 
     /**
         Take all the data in the Builder and make it into Tx that can be serialized
     */
     pub fn build(&self) -> Result<Tx, BuilderErr> {
-        let version: u32 = 1;
+        let version: u32 = self.version;
         let input_count: usize = self.inputs.len();
         let output_count: usize = self.outputs.len();
-        let locktime: u32 = 0x00000000;
+        let locktime: u32 = self.locktime;
 
         //Loop over each input and check if there is a scriptSig or witness for it
         let mut inputs: Vec<Input> = vec![];
@@ -316,6 +836,23 @@ impl TxBuilder {
             segwit
         })
     }
+
+    /**
+        Build the transaction and check that every input satisfies the output it spends
+        using the script interpreter with the given flags.
+    */
+    pub fn build_verified(&mut self, flags: VerifyFlags) -> Result<Tx, BuilderErr> {
+        let tx: Tx = self.build()?;
+        let mut prevouts: Vec<Output> = vec![];
+        for i in 0..self.inputs.len() {
+            prevouts.push(self.get_prevout(i)?);
+        }
+
+        match tx.verify(&prevouts, flags) {
+            Ok(_) => Ok(tx),
+            Err((index, x)) => Err(BuilderErr::ScriptVerifyFailed(index, x))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -323,10 +860,13 @@ mod tests {
     use super::*;
     use crate::prelude::Serialize;
     use crate::{
-        tx::Script
+        tx::Script,
+        tx::taproot,
+        test_utils::{ PREV_TXID, key, p2wpkh_address, p2wpkh_output }
     };
     use btc_keyaddress::prelude::*;
     use btc_keyaddress::key::PrivKey as PrivKey;
+    use bitcoin_hashes::Hash;
 
     #[test]
     fn single_legacy_p2pkh_input() {
@@ -346,6 +886,392 @@ mod tests {
         assert_eq!(tx.get_txid(), expected_txid);
     }
 
+    //P2PKH scriptPubKey locked to the given key
+    fn p2pkh_script_pub_key(key: &PrivKey) -> Script {
+        let pubkey_hash = bitcoin_hashes::hash160::Hash::hash(&PubKey::from_priv_key(key).as_bytes::<33>());
+        let mut script_pub_key: Vec<u8> = vec![0x76, 0xa9, 0x14];
+        script_pub_key.append(&mut pubkey_hash.to_vec());
+        script_pub_key.append(&mut vec![0x88, 0xac]);
+
+        Script::new(script_pub_key)
+    }
+
+    #[test]
+    fn single_legacy_p2pkh_input_offline() {
+        let expected_txid = "d37c3d75e7a70261bf191dfc296272cbb20e0466167d4f6f8fde6c2458f05004";
+        let key: PrivKey = PrivKey::from_slice(&[25, 185, 89, 6, 72, 28, 43, 234, 167, 160, 163, 78, 240, 86, 146, 133, 49, 98, 255, 253, 45, 121, 146, 10, 233, 252, 142, 232, 193, 73, 255, 150]).unwrap();
+        let prevout = Output { value: 90000, script_pub_key: p2pkh_script_pub_key(&key) };
+        
+        //Create and sign the transaction without an Electrum connection
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 1, prevout).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 80000).unwrap();
+
+        let signing_data = SigningData::new(vec![key], None);
+        txb.sign_input(0, &signing_data, SigHash::ALL).unwrap();
+        let tx: Tx = txb.build().unwrap();
+
+        //Compare the derived and expected TXID
+        assert_eq!(tx.get_txid(), expected_txid);
+    }
+
+    #[test]
+    fn single_legacy_p2pkh_input_memory_chain() {
+        let expected_txid = "d37c3d75e7a70261bf191dfc296272cbb20e0466167d4f6f8fde6c2458f05004";
+        let key: PrivKey = PrivKey::from_slice(&[25, 185, 89, 6, 72, 28, 43, 234, 167, 160, 163, 78, 240, 86, 146, 133, 49, 98, 255, 253, 45, 121, 146, 10, 233, 252, 142, 232, 193, 73, 255, 150]).unwrap();
+        let mut chain = api::MemoryChain::new();
+        chain.insert_prevout(
+            "a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 1,
+            Output { value: 90000, script_pub_key: p2pkh_script_pub_key(&key) }
+        );
+
+        //Create and sign the transaction with the prevout coming from the chain source
+        let mut txb = TxBuilder::with_source(Network::Testnet, chain);
+        txb.add_input("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", 1).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 80000).unwrap();
+
+        let signing_data = SigningData::new(vec![key], None);
+        txb.sign_input(0, &signing_data, SigHash::ALL).unwrap();
+        let tx: Tx = txb.build().unwrap();
+        assert_eq!(tx.get_txid(), expected_txid);
+
+        //Broadcasting through the memory chain records the tx
+        let source = txb.source.as_ref().unwrap();
+        assert_eq!(source.broadcast(&tx).unwrap(), expected_txid);
+        assert_eq!(source.broadcasted().len(), 1);
+    }
+
+    #[test]
+    fn finalize_with_estimated_feerate() {
+        let key: PrivKey = key(15);
+        let signing_data = vec![SigningData::new(vec![key], None)];
+        let mut chain = api::MemoryChain::new();
+        chain.insert_prevout(PREV_TXID, 0, p2wpkh_output(&key, 100000));
+
+        let mut txb = TxBuilder::with_source(Network::Testnet, chain);
+        txb.add_input(PREV_TXID, 0).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 50000).unwrap();
+        txb.set_change_address("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e").unwrap();
+        assert!(matches!(txb.finalize_with_target(6, &signing_data), Err(BuilderErr::FeeUnavailable())));
+
+        //The estimate is used like a feerate given directly
+        txb.source.as_mut().unwrap().set_feerate(FeeRate::from_sat_per_vb(12));
+        assert_eq!(txb.estimate_feerate(6).unwrap(), FeeRate::from_sat_per_vb(12));
+        let fee = txb.finalize_with_target(6, &signing_data).unwrap();
+        assert_eq!(fee, txb.finalize_with_feerate(FeeRate::from_sat_per_vb(12), &signing_data).unwrap());
+        txb.sign_input(0, &signing_data[0], SigHash::ALL).unwrap();
+        let tx: Tx = txb.build().unwrap();
+        let prevouts = vec![txb.get_prevout(0).unwrap()];
+        assert_eq!(tx.fee(&prevouts).unwrap(), fee);
+        assert!(tx.feerate(&prevouts).unwrap() >= FeeRate::from_sat_per_vb(12));
+    }
+
+    #[test]
+    fn estimate_vsize_before_signing() {
+        let keys: Vec<PrivKey> = (1..5).map(key).collect();
+        let multisig = Script::multisig_locking(2, &keys[..3].to_vec());
+        let p2sh = crate::tx::ScriptBuilder::new()
+            .push_opcode(crate::tx::ScriptCodes::OP_HASH160)
+            .push_slice(&crate::hash::hash160(&multisig.code))
+            .push_opcode(crate::tx::ScriptCodes::OP_EQUAL)
+            .into_script();
+        let (output_key, _) = taproot::output_key(&taproot::internal_key(&keys[3]), None).unwrap();
+        let prevouts = vec![
+            Output { value: 10000, script_pub_key: p2pkh_script_pub_key(&keys[0]) },
+            p2wpkh_output(&keys[1], 20000),
+            Output { value: 30000, script_pub_key: p2sh },
+            Output { value: 40000, script_pub_key: Script::p2tr_locking(&output_key) }
+        ];
+        let signing_data = vec![
+            SigningData::new(vec![keys[0]], None),
+            SigningData::new(vec![keys[1]], None),
+            SigningData::new(vec![keys[0], keys[2]], Some(multisig)),
+            SigningData::new(vec![keys[3]], None)
+        ];
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        for i in 0..prevouts.len() {
+            txb.add_input_with_prevout(PREV_TXID, i as u32, prevouts[i].clone()).unwrap();
+        }
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 90000).unwrap();
+        let estimate = txb.estimate_vsize(&signing_data).unwrap();
+
+        //The estimate is never below the signed size. It can be over by up to two bytes for each
+        //ECDSA signature and by two bytes of the multisig scriptSig length
+        for i in 0..signing_data.len() {
+            txb.sign_input(i, &signing_data[i], SigHash::ALL).unwrap();
+        }
+        let vsize = txb.build().unwrap().vsize();
+        assert!(estimate >= vsize && estimate - vsize <= 9, "estimate {} vsize {}", estimate, vsize);
+
+        //Once signed the inputs are measured exactly
+        assert_eq!(txb.estimate_vsize(&signing_data).unwrap(), vsize);
+        assert!(txb.estimate_vsize(&signing_data[..2].to_vec()).is_err());
+    }
+
+    #[test]
+    fn finalize_with_feerate_adds_change() {
+        let key: PrivKey = key(7);
+        let signing_data = vec![SigningData::new(vec![key], None)];
+        let feerate = FeeRate::from_sat_per_vb(2);
+        let new_builder = |value: u64| {
+            let mut txb = TxBuilder::new(Network::Testnet);
+            txb.add_input_with_prevout(PREV_TXID, 0, p2wpkh_output(&key, value)).unwrap();
+            txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 50000).unwrap();
+            txb.set_change_address("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e").unwrap();
+            txb
+        };
+
+        //The change output gets what is left after the fee. Finalizing again replaces it.
+        let mut txb = new_builder(100000);
+        txb.finalize_with_feerate(FeeRate::from_sat_per_vb(20), &signing_data).unwrap();
+        let fee = txb.finalize_with_feerate(feerate, &signing_data).unwrap();
+        assert_eq!(txb.outputs.len(), 2);
+        assert_eq!(txb.change_index, Some(1));
+        assert_eq!(txb.outputs[1].value, 100000 - 50000 - fee);
+
+        //The signed transaction pays at least the feerate and is over by less than a signature byte
+        txb.sign_input(0, &signing_data[0], SigHash::ALL).unwrap();
+        let tx: Tx = txb.build().unwrap();
+        let prevouts = vec![txb.get_prevout(0).unwrap()];
+        assert_eq!(tx.fee(&prevouts).unwrap(), fee);
+        assert!(tx.feerate(&prevouts).unwrap() >= feerate);
+        assert!(fee - feerate.fee_for_vsize(tx.vsize()) <= 2);
+
+        //Change that would be dust is dropped and goes to the fee
+        let mut txb = new_builder(50500);
+        assert_eq!(txb.finalize_with_feerate(feerate, &signing_data).unwrap(), 500);
+        assert_eq!(txb.outputs.len(), 1);
+        assert_eq!(txb.change_index, None);
+
+        //The inputs must pay for the outputs and the fee
+        let mut txb = new_builder(50100);
+        match txb.finalize_with_feerate(feerate, &signing_data) {
+            Err(BuilderErr::InsufficientFunds(50100, x)) => assert!(x > 50100),
+            x => panic!("{:?}", x)
+        }
+    }
+
+    #[test]
+    fn op_return_and_script_outputs() {
+        let key: PrivKey = key(13);
+        let document_hash = crate::hash::sha256(b"document".to_vec());
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, p2wpkh_output(&key, 50000)).unwrap();
+        txb.add_op_return(&document_hash).unwrap();
+        txb.add_output_script(p2wpkh_address(&key).script_pub_key(), 49000).unwrap();
+
+        //Data is limited to 80 bytes and only one null data output is allowed
+        assert!(Script::op_return(&[0xab; 80]).is_ok());
+        match txb.add_op_return(&[0xab; 81]) {
+            Err(BuilderErr::InvalidScript(ScriptErr::DataTooLarge(81))) => { },
+            x => panic!("{:?}", x)
+        }
+        assert!(matches!(txb.add_op_return(&[0xab; 80]), Err(BuilderErr::MultipleOpReturn())));
+        assert!(matches!(txb.add_output_script(Script::op_return(&[0xab]).unwrap(), 0), Err(BuilderErr::MultipleOpReturn())));
+        assert_eq!(txb.outputs.len(), 2);
+
+        txb.sign_input(0, &SigningData::new(vec![key], None), SigHash::ALL).unwrap();
+        let tx: Tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[0].value, 0);
+        assert_eq!(tx.outputs[0].script_pub_key.to_asm(), format!("OP_RETURN {}", bytes::encode_02x(&document_hash)));
+        assert!(tx.outputs[0].script_pub_key.is_op_return());
+        assert!(!tx.outputs[1].script_pub_key.is_op_return());
+
+        //Null data outputs are never dust
+        assert_eq!(tx.outputs[0].dust_limit(), 0);
+        assert_eq!(tx.outputs[1].dust_limit(), 294);
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        let mut txb = TxBuilder::new(Network::Testnet);
+        match txb.add_output("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", 1000) {
+            Err(BuilderErr::InvalidAddress(AddressErr::NetworkMismatch())) => { },
+            x => panic!("{:?}", x)
+        }
+        match txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa3", 1000) {
+            Err(BuilderErr::InvalidAddress(AddressErr::InvalidChecksum())) => { },
+            x => panic!("{:?}", x)
+        }
+        assert!(matches!(txb.set_change_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"), Err(BuilderErr::InvalidAddress(AddressErr::NetworkMismatch()))));
+        assert!(txb.outputs.is_empty());
+        assert!(txb.change_address.is_none());
+
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 1000).unwrap();
+        assert_eq!(txb.outputs[0].script_pub_key.code, Output::new("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 0, &Network::Testnet).unwrap().script_pub_key.code);
+    }
+
+    #[test]
+    fn version_locktime_and_sequence() {
+        let key: PrivKey = key(9);
+        let (output_key, _) = taproot::output_key(&taproot::internal_key(&key), None).unwrap();
+        let prevouts = vec![
+            Output { value: 10000, script_pub_key: p2pkh_script_pub_key(&key) },
+            p2wpkh_output(&key, 20000),
+            Output { value: 30000, script_pub_key: Script::p2tr_locking(&output_key) }
+        ];
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        for i in 0..prevouts.len() {
+            txb.add_input_with_prevout(PREV_TXID, i as u32, prevouts[i].clone()).unwrap();
+        }
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 55000).unwrap();
+        txb.set_version(2).unwrap();
+        txb.set_locktime(700000).unwrap();
+        txb.set_sequence(0, 0xFFFFFFFE).unwrap();
+        txb.set_sequence(1, 144).unwrap();
+        assert!(txb.set_sequence(3, 0).is_err());
+
+        //Signatures are made over the version, locktime and sequences so they still verify once built
+        let signing_data = SigningData::new(vec![key], None);
+        txb.sign_input(0, &signing_data, SigHash::ALL).unwrap();
+        txb.sign_input(1, &signing_data, SigHash::ALL).unwrap();
+        txb.sign_input(2, &signing_data, SigHash::DEFAULT).unwrap();
+        let tx: Tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.locktime, 700000);
+        assert_eq!(tx.inputs.iter().map(|x| x.sequence).collect::<Vec<u32>>(), vec![0xFFFFFFFE, 144, 0xFFFFFFFF]);
+
+        let parsed = Tx::from_hex(&bytes::encode_02x(&tx.serialize().unwrap())).unwrap();
+        assert_eq!(parsed.version, 2);
+        assert_eq!(parsed.locktime, 700000);
+
+        //Signed inputs commit to all three
+        assert!(matches!(txb.set_version(1), Err(BuilderErr::TxCommitted())));
+        assert!(matches!(txb.set_locktime(0), Err(BuilderErr::TxCommitted())));
+        assert!(matches!(txb.set_sequence(2, 0), Err(BuilderErr::TxCommitted())));
+    }
+
+    #[test]
+    fn taproot_sighash_none_commits_to_sequences() {
+        let key: PrivKey = key(10);
+        let (output_key, _) = taproot::output_key(&taproot::internal_key(&key), None).unwrap();
+        let prevouts = vec![
+            Output { value: 10000, script_pub_key: Script::p2tr_locking(&output_key) },
+            p2wpkh_output(&key, 20000),
+            p2wpkh_output(&key, 30000)
+        ];
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        for i in 0..prevouts.len() {
+            txb.add_input_with_prevout(PREV_TXID, i as u32, prevouts[i].clone()).unwrap();
+        }
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 55000).unwrap();
+
+        //A P2WPKH signature with NONE leaves the other sequences free
+        let signing_data = SigningData::new(vec![key], None);
+        txb.sign_input(1, &signing_data, SigHash::NONE).unwrap();
+        txb.set_sequence(2, 0xFFFFFFFD).unwrap();
+
+        //A Taproot signature with NONE still commits to every sequence
+        txb.sign_input(0, &signing_data, SigHash::NONE).unwrap();
+        assert!(matches!(txb.set_sequence(2, 0xFFFFFFFE), Err(BuilderErr::TxCommitted())));
+
+        txb.sign_input(2, &signing_data, SigHash::ALL).unwrap();
+        let tx: Tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+        assert_eq!(tx.inputs[2].sequence, 0xFFFFFFFD);
+    }
+
+    #[test]
+    fn p2tr_script_path_with_inputs() {
+        use crate::tx::{ ScriptBuilder, ScriptCodes, TapTreeBuilder };
+        let signers = vec![key(10), key(11)];
+        let preimage: Vec<u8> = b"preimage".to_vec();
+
+        //Hashlock leaf and a 1-of-2 OP_CHECKSIGADD leaf
+        let hashlock = ScriptBuilder::new()
+            .push_opcode(ScriptCodes::OP_SHA256)
+            .push_slice(&crate::hash::sha256(&preimage))
+            .push_opcode(ScriptCodes::OP_EQUALVERIFY)
+            .push_slice(&taproot::internal_key(&signers[0]).serialize())
+            .push_opcode(ScriptCodes::OP_CHECKSIG)
+            .into_script();
+        let threshold = ScriptBuilder::new()
+            .push_slice(&taproot::internal_key(&signers[0]).serialize())
+            .push_opcode(ScriptCodes::OP_CHECKSIG)
+            .push_slice(&taproot::internal_key(&signers[1]).serialize())
+            .push_opcode(ScriptCodes::OP_CHECKSIGADD)
+            .push_int(1)
+            .push_opcode(ScriptCodes::OP_NUMEQUAL)
+            .into_script();
+        let mut tree = TapTreeBuilder::new();
+        tree.add_leaf(1, hashlock.clone());
+        tree.add_leaf(1, threshold.clone());
+        let spend_info = tree.finalize(taproot::internal_key(&key(12))).unwrap();
+        let prevout = Output { value: 50000, script_pub_key: spend_info.script_pub_key() };
+
+        //The preimage sits above the signature and an empty vector stands in for the absent key
+        let signing_data = vec![
+            SigningData::taproot_script_path_with_inputs(
+                vec![ScriptInput::Item(preimage), ScriptInput::Signature(signers[0])],
+                hashlock.clone(),
+                spend_info.control_block(&hashlock).unwrap()
+            ),
+            SigningData::taproot_script_path_with_inputs(
+                vec![ScriptInput::Item(vec![]), ScriptInput::Signature(signers[1])],
+                threshold.clone(),
+                spend_info.control_block(&threshold).unwrap()
+            )
+        ];
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, prevout.clone()).unwrap();
+        txb.add_input_with_prevout(PREV_TXID, 1, prevout.clone()).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 90000).unwrap();
+        let estimate = txb.estimate_vsize(&signing_data).unwrap();
+        txb.sign_input(0, &signing_data[0], SigHash::DEFAULT).unwrap();
+        txb.sign_input(1, &signing_data[1], SigHash::DEFAULT).unwrap();
+        let tx: Tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+        assert!(estimate >= tx.vsize());
+
+        //Witness items are stored from the bottom of the stack
+        let witness = tx.witness.clone().unwrap();
+        assert_eq!(witness[0].stack()[1].code, b"preimage".to_vec());
+        assert_eq!(witness[1].len(), 4);
+        assert!(witness[1].stack()[1].code.is_empty());
+
+        //The wrong preimage fails the OP_EQUALVERIFY
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, prevout).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 40000).unwrap();
+        let wrong = SigningData::taproot_script_path_with_inputs(
+            vec![ScriptInput::Item(b"wrong".to_vec()), ScriptInput::Signature(signers[0])],
+            hashlock.clone(),
+            spend_info.control_block(&hashlock).unwrap()
+        );
+        txb.sign_input(0, &wrong, SigHash::DEFAULT).unwrap();
+        assert!(matches!(txb.build_verified(VerifyFlags::standard()), Err(BuilderErr::ScriptVerifyFailed(0, _))));
+    }
+
+    #[test]
+    fn single_p2tr_key_path_input_offline() {
+        let key: PrivKey = PrivKey::from_slice(&[131, 187, 80, 16, 233, 20, 231, 76, 171, 218, 189, 168, 220, 150, 47, 40, 73, 149, 85, 236, 159, 205, 198, 160, 182, 32, 149, 30, 95, 184, 54, 186]).unwrap();
+        let (output_key, _) = taproot::output_key(&taproot::internal_key(&key), None).unwrap();
+        let mut script_pub_key: Vec<u8> = vec![0x51, 0x20];
+        script_pub_key.append(&mut output_key.serialize().to_vec());
+        let prevout = Output { value: 100000, script_pub_key: Script::new(script_pub_key) };
+
+        //Create and sign the transaction
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout("36e336b364abaf48b46c415903b1d93c7a740d7a3bde1691e30fec3d7a180245", 0, prevout.clone()).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 90000).unwrap();
+        txb.sign_input(0, &SigningData::new(vec![key], None), SigHash::DEFAULT).unwrap();
+        let tx: Tx = txb.build().unwrap();
+
+        //The witness is a single 64 byte signature that verifies against the output key
+        let witness = &tx.witness.as_ref().unwrap()[0];
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness.stack()[0].code.len(), 64);
+
+        let tx_copy = Tx::construct(txb.inputs.clone(), txb.outputs.clone(), 0, false);
+        let preimage = super::super::hashpreimage::taproot(&tx_copy, &SigHash::DEFAULT, 0, &vec![prevout], &None, None).unwrap();
+        let msg = crate::signature::new_msg(&crate::hash::tagged_hash("TapSighash", preimage)).unwrap();
+        let sig = crate::schnorrsig::Signature::from_slice(&witness.stack()[0].code).unwrap();
+        crate::signature::verify_schnorr(&sig, &msg, &output_key).unwrap();
+    }
+
     #[test]
     fn single_segwit_p2wpkh_input() {
         let expected_txid = "10296b2590ce4397a617cf77071581fc0eb34dc531b1c243565d7508970e57b7";
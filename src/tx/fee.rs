@@ -0,0 +1,91 @@
+/*
+    Fee rates used to calculate transaction fees.
+
+    Fee rates are stored in satoshis per 1000 virtual bytes like in Bitcoin Core
+    so that rates below 1 sat/vB can be represented without floats.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeeErr {
+    PrevoutCount(usize, usize),     //Number of inputs and number of prevouts given
+    NegativeFee(u64, u64)           //Value of the inputs and value of the outputs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct FeeRate {
+    sat_per_kvb: u64
+}
+
+impl FeeRate {
+    /**
+        Default feerate a replacement has to pay on top of the fee of the transactions it replaces
+    */
+    pub const INCREMENTAL_RELAY: FeeRate = FeeRate { sat_per_kvb: 1000 };
+
+    pub fn from_sat_per_kvb(sat_per_kvb: u64) -> Self {
+        Self {
+            sat_per_kvb
+        }
+    }
+
+    pub fn from_sat_per_vb(sat_per_vb: u64) -> Self {
+        Self::from_sat_per_kvb(sat_per_vb * 1000)
+    }
+
+    /**
+        Create a fee rate from BTC/kvB, the unit used by bitcoind and Electrum servers
+    */
+    pub fn from_btc_per_kvb(btc_per_kvb: f64) -> Self {
+        Self::from_sat_per_kvb((btc_per_kvb * 100_000_000.0).round() as u64)
+    }
+
+    /**
+        Fee rate paid by a fee over a virtual size
+    */
+    pub fn from_fee_and_vsize(fee: u64, vsize: u64) -> Self {
+        Self::from_sat_per_kvb(fee * 1000 / vsize.max(1))
+    }
+
+    pub fn sat_per_kvb(&self) -> u64 {
+        self.sat_per_kvb
+    }
+
+    pub fn sat_per_vb(&self) -> f64 {
+        self.sat_per_kvb as f64 / 1000.0
+    }
+
+    /**
+        Fee needed to pay this rate for the given virtual size, rounded up
+    */
+    pub fn fee_for_vsize(&self, vsize: u64) -> u64 {
+        (self.sat_per_kvb * vsize + 999) / 1000
+    }
+
+    /**
+        Fee needed to pay this rate for the given weight, rounded up
+    */
+    pub fn fee_for_weight(&self, weight: u64) -> u64 {
+        self.fee_for_vsize((weight + 3) / 4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feerates() {
+        let rate = FeeRate::from_sat_per_vb(5);
+        assert_eq!(rate.sat_per_kvb(), 5000);
+        assert_eq!(rate.fee_for_vsize(141), 705);
+        assert_eq!(rate.fee_for_weight(561), 705);
+
+        //Fractional rates round the fee up
+        let rate = FeeRate::from_sat_per_kvb(1234);
+        assert_eq!(rate.sat_per_vb(), 1.234);
+        assert_eq!(rate.fee_for_vsize(110), 136);
+
+        assert_eq!(FeeRate::from_btc_per_kvb(0.00012345), FeeRate::from_sat_per_kvb(12345));
+        assert_eq!(FeeRate::from_fee_and_vsize(1000, 0), FeeRate::from_sat_per_kvb(1000000));
+        assert!(FeeRate::from_sat_per_vb(2) > FeeRate::from_sat_per_kvb(1999));
+    }
+}
@@ -11,6 +11,8 @@ use crate::{
     },
     util::serialize::{
         Serialize as SerializeTrait,
+        Deserialize,
+        Decoder,
         SerializationError
     },
     util::varint::VarInt as VarInt,
@@ -56,6 +58,27 @@ impl SerializeTrait for Input {
     }
 }
 
+impl Deserialize for Input {
+    fn deserialize(decoder: &mut Decoder) -> Result<Self, SerializationError> {
+        //TXIDs are stored in big endian, so reverse the serialized bytes
+        let mut txid: [u8; 32] = [0; 32];
+        txid.copy_from_slice(&decoder.read_bytes(32)?);
+        txid.reverse();
+        let vout = decoder.read_u32()?;
+        let script_sig = Script::new(decoder.read_var_bytes()?);
+        let sequence = decoder.read_u32()?;
+
+        Ok(Self {
+            txid,
+            vout,
+            scriptSig_size: script_sig.len(),
+            scriptSig: script_sig,
+            sequence,
+            segwit: false  //Set by the transaction once the witness is read
+        })
+    }
+}
+
 impl Input {
     pub fn unsigned_input(
         txid: [u8; 32],
@@ -0,0 +1,327 @@
+/*
+    Parse scripts into instructions and convert scripts to and from ASM.
+
+    ASM is a space separated list of opcode names and hex data pushes,
+    e.g. `OP_2 <pubkey> <pubkey> OP_2 OP_CHECKMULTISIG`. Data pushes are
+    written with the smallest PUSHDATA opcode that fits unless an explicit
+    OP_PUSHDATA1/2/4 comes before them. Tokens starting with 0x are copied
+    into the script as they are, which is also how unassigned opcodes and
+    truncated pushes are rendered.
+*/
+use crate::{
+    tx::{
+        Script,
+        ScriptErr,
+        ScriptCodes
+    },
+    util::bytes::{
+        encode_02x,
+        try_decode_02x
+    }
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Push(u8, Vec<u8>),      //Opcode used to push the data and the data. OP_0 pushes no data.
+    Op(ScriptCodes),        //Any other opcode
+    Unknown(u8)             //Unassigned opcode byte. These are OP_SUCCESSx in Tapscript
+}
+
+impl Instruction {
+    /**
+        Get the opcode byte of the instruction
+    */
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instruction::Push(op, _) => *op,
+            Instruction::Op(op) => *op as u8,
+            Instruction::Unknown(op) => *op
+        }
+    }
+
+    /**
+        Get the data pushed by the instruction if it is a push
+    */
+    pub fn push_bytes(&self) -> Option<&Vec<u8>> {
+        match self {
+            Instruction::Push(_, data) => Some(data),
+            _ => None
+        }
+    }
+}
+
+impl Script {
+    /**
+        Split the script into instructions.
+        Errors with the position of a push that runs past the end of the script.
+    */
+    pub fn instructions(&self) -> Result<Vec<Instruction>, ScriptErr> {
+        let mut instructions: Vec<Instruction> = vec![];
+        let mut pc: usize = 0;
+        while pc < self.code.len() {
+            let (opcode, data) = read_op(&self.code, &mut pc)?;
+            let instruction = if opcode <= ScriptCodes::OP_PUSHDATA4 as u8 {
+                Instruction::Push(opcode, data.to_vec())
+            } else {
+                match ScriptCodes::from_u8(opcode) {
+                    Some(x) => Instruction::Op(x),
+                    None => Instruction::Unknown(opcode)
+                }
+            };
+            instructions.push(instruction);
+        }
+
+        Ok(instructions)
+    }
+
+    /**
+        Render the script as ASM
+    */
+    pub fn to_asm(&self) -> String {
+        let mut tokens: Vec<String> = vec![];
+        let mut pc: usize = 0;
+        while pc < self.code.len() {
+            let start: usize = pc;
+            let (opcode, data) = match read_op(&self.code, &mut pc) {
+                Ok(x) => x,
+                Err(_) => {
+                    //Keep the bytes of a truncated push so the script can be rebuilt from the ASM
+                    tokens.push(format!("0x{}", encode_02x(&self.code[start..])));
+                    break
+                }
+            };
+
+            if opcode == ScriptCodes::OP_0 as u8 {
+                tokens.push(ScriptCodes::OP_0.name());
+            } else if opcode <= ScriptCodes::OP_PUSHDATA4 as u8 {
+                if push_data(data)[0] != opcode {
+                    //Empty pushes with a PUSHDATA opcode have no data to write after the opcode name
+                    if data.is_empty() {
+                        tokens.push(format!("0x{}", encode_02x(&self.code[start..pc])));
+                        continue
+                    }
+                    tokens.push(ScriptCodes::from_u8(opcode).unwrap().name());
+                }
+                tokens.push(encode_02x(data));
+            } else {
+                match ScriptCodes::from_u8(opcode) {
+                    Some(x) => tokens.push(x.name()),
+                    None => tokens.push(format!("0x{:02x}", opcode))
+                }
+            }
+        }
+
+        tokens.join(" ")
+    }
+
+    /**
+        Build a script from ASM.
+        Data pushes can be written as hex with or without angle brackets.
+    */
+    pub fn from_asm(asm: &str) -> Result<Self, ScriptErr> {
+        let mut code: Vec<u8> = vec![];
+        let mut tokens = asm.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if let Some(raw) = token.strip_prefix("0x") {
+                code.append(&mut asm_hex(raw, token)?);
+                continue
+            }
+
+            match ScriptCodes::from_name(token) {
+                //An explicit PUSHDATA opcode takes the next token as its data
+                Some(op @ ScriptCodes::OP_PUSHDATA1) |
+                Some(op @ ScriptCodes::OP_PUSHDATA2) |
+                Some(op @ ScriptCodes::OP_PUSHDATA4) => {
+                    let data: Vec<u8> = match tokens.next() {
+                        Some(x) => asm_hex(x.trim_start_matches('<').trim_end_matches('>'), x)?,
+                        None => return Err(ScriptErr::InvalidAsm(token.to_string()))
+                    };
+                    let width: usize = match op {
+                        ScriptCodes::OP_PUSHDATA1 => 1,
+                        ScriptCodes::OP_PUSHDATA2 => 2,
+                        _ => 4
+                    };
+                    if width < 8 && data.len() >> (8 * width) != 0 {
+                        return Err(ScriptErr::InvalidAsm(token.to_string()))
+                    }
+                    code.push(op as u8);
+                    code.extend_from_slice(&(data.len() as u32).to_le_bytes()[..width]);
+                    code.extend_from_slice(&data);
+                },
+                Some(op) => code.push(op as u8),
+                None => {
+                    let hex: &str = token.trim_start_matches('<').trim_end_matches('>');
+                    code.append(&mut push_data(&asm_hex(hex, token)?));
+                }
+            }
+        }
+
+        Ok(Self::new(code))
+    }
+}
+
+/**
+    Decode the hex of an ASM token
+*/
+fn asm_hex(hex: &str, token: &str) -> Result<Vec<u8>, ScriptErr> {
+    match try_decode_02x(hex) {
+        Ok(x) if !hex.is_empty() => Ok(x),
+        _ => Err(ScriptErr::InvalidAsm(token.to_string()))
+    }
+}
+
+/**
+    Read the opcode at the given position of a script and the data it pushes.
+    The position is moved past the opcode and data.
+*/
+pub(crate) fn read_op<'a>(script: &'a [u8], pc: &mut usize) -> Result<(u8, &'a [u8]), ScriptErr> {
+    let start: usize = *pc;
+    let opcode: u8 = script[*pc];
+    *pc += 1;
+
+    //Number of bytes used to store the length of the push
+    let length_bytes: usize = match opcode {
+        0x4c => 1,
+        0x4d => 2,
+        0x4e => 4,
+        _ => 0
+    };
+    if script.len() - *pc < length_bytes { return Err(ScriptErr::InvalidPush(start)) }
+
+    let size: usize = match opcode {
+        0x01..=0x4b => opcode as usize,
+        0x4c..=0x4e => {
+            let mut size: usize = 0;
+            for i in 0..length_bytes {
+                size |= (script[*pc + i] as usize) << (8 * i);
+            }
+            *pc += length_bytes;
+            size
+        },
+        _ => 0
+    };
+    if script.len() - *pc < size { return Err(ScriptErr::InvalidPush(start)) }
+
+    let data: &[u8] = &script[*pc..*pc + size];
+    *pc += size;
+
+    Ok((opcode, data))
+}
+
+/**
+    Script bytes that push the given data.
+    Matches how Bitcoin Core serializes data pushes, which never uses OP_1 to OP_16.
+*/
+pub(crate) fn push_data(data: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    if data.len() < ScriptCodes::OP_PUSHDATA1 as usize {
+        bytes.push(data.len() as u8);
+    } else if data.len() <= 0xff {
+        bytes.push(ScriptCodes::OP_PUSHDATA1 as u8);
+        bytes.push(data.len() as u8);
+    } else if data.len() <= 0xffff {
+        bytes.push(ScriptCodes::OP_PUSHDATA2 as u8);
+        bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    } else {
+        bytes.push(ScriptCodes::OP_PUSHDATA4 as u8);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    }
+    bytes.extend_from_slice(data);
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes::decode_02x;
+
+    #[test]
+    fn opcode_table() {
+        //Every assigned byte maps back to itself through its name
+        for byte in 0..=255u8 {
+            if let Some(op) = ScriptCodes::from_u8(byte) {
+                assert_eq!(op as u8, byte);
+                assert_eq!(ScriptCodes::from_name(&op.name()), Some(op));
+            }
+        }
+        assert_eq!((0..=255u8).filter_map(ScriptCodes::from_u8).count(), 113);
+        assert!(ScriptCodes::from_u8(0x14).is_none());
+        assert!(ScriptCodes::from_u8(0xbb).is_none());
+
+        assert_eq!(ScriptCodes::from_name("OP_NOP2"), Some(ScriptCodes::OP_CHECKLOCKTIMEVERIFY));
+        assert_eq!(ScriptCodes::from_name("OP_TRUE"), Some(ScriptCodes::OP_1));
+        assert_eq!(ScriptCodes::from_name("OP_SHA3"), None);
+
+        assert_eq!(ScriptCodes::from_small_int(16), Some(ScriptCodes::OP_16));
+        assert_eq!(ScriptCodes::from_small_int(17), None);
+        assert_eq!(ScriptCodes::OP_1NEGATE.small_int(), Some(-1));
+        assert_eq!(ScriptCodes::OP_7.small_int(), Some(7));
+        assert_eq!(ScriptCodes::OP_NOP.small_int(), None);
+    }
+
+    #[test]
+    fn parse_instructions() {
+        //P2PKH
+        let script = Script::new(decode_02x("76a914e3a8ceec9e0e7de74a5e3ba0c1a1ba6e9d0ea35088ac"));
+        assert_eq!(script.instructions().unwrap(), vec![
+            Instruction::Op(ScriptCodes::OP_DUP),
+            Instruction::Op(ScriptCodes::OP_HASH160),
+            Instruction::Push(0x14, decode_02x("e3a8ceec9e0e7de74a5e3ba0c1a1ba6e9d0ea350")),
+            Instruction::Op(ScriptCodes::OP_EQUALVERIFY),
+            Instruction::Op(ScriptCodes::OP_CHECKSIG)
+        ]);
+
+        //PUSHDATA1/2/4, including non minimal pushes, OP_0 and unassigned opcodes
+        let mut code: Vec<u8> = vec![0x00, 0x4c, 0x02, 0xaa, 0xbb, 0x4d, 0x01, 0x00, 0xcc, 0x4e, 0x00, 0x00, 0x00, 0x00, 0xbb];
+        code.append(&mut push_data(&[0x11; 300]));
+        let instructions = Script::new(code).instructions().unwrap();
+        assert_eq!(instructions, vec![
+            Instruction::Push(0x00, vec![]),
+            Instruction::Push(0x4c, vec![0xaa, 0xbb]),
+            Instruction::Push(0x4d, vec![0xcc]),
+            Instruction::Push(0x4e, vec![]),
+            Instruction::Unknown(0xbb),
+            Instruction::Push(0x4d, vec![0x11; 300])
+        ]);
+        assert_eq!(instructions[4].opcode(), 0xbb);
+        assert_eq!(instructions[1].push_bytes(), Some(&vec![0xaa, 0xbb]));
+
+        //Pushes that run past the end of the script
+        assert_eq!(Script::new(vec![0x51, 0x03, 0x01, 0x02]).instructions(), Err(ScriptErr::InvalidPush(1)));
+        assert_eq!(Script::new(vec![0x4d, 0x01]).instructions(), Err(ScriptErr::InvalidPush(0)));
+        assert_eq!(Script::new(vec![]).instructions(), Ok(vec![]));
+    }
+
+    #[test]
+    fn asm() {
+        let pk1 = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let pk2 = "03e6c9ab70b7d8b4e1d0bb8b0d8b9c1f1b1a8e7a9a7c0d9c8bba0c1c2d3e4f5a6b";
+        let multisig = Script::from_asm(&format!("OP_2 <{}> <{}> OP_2 OP_CHECKMULTISIG", pk1, pk2)).unwrap();
+        assert_eq!(multisig.code, decode_02x(&format!("5221{}21{}52ae", pk1, pk2)));
+        assert_eq!(multisig.to_asm(), format!("OP_2 {} {} OP_2 OP_CHECKMULTISIG", pk1, pk2));
+
+        //Aliases and bare hex
+        let cltv = Script::from_asm("f401 OP_NOP2 OP_DROP OP_TRUE").unwrap();
+        assert_eq!(cltv.code, vec![0x02, 0xf4, 0x01, 0xb1, 0x75, 0x51]);
+        assert_eq!(cltv.to_asm(), "f401 OP_CHECKLOCKTIMEVERIFY OP_DROP OP_1");
+
+        //Long pushes pick the PUSHDATA opcode from the length
+        let long = Script::from_asm(&"ab".repeat(80)).unwrap();
+        assert_eq!(&long.code[..2], &[0x4c, 80]);
+        assert_eq!(long.to_asm(), "ab".repeat(80));
+
+        //Non minimal pushes, unassigned opcodes and truncated pushes survive a round trip
+        let code: Vec<u8> = vec![0x00, 0x4c, 0x01, 0x07, 0x4c, 0x00, 0xbb, 0x4e, 0x05, 0x00, 0x00, 0x00, 0x01];
+        let asm = Script::new(code.clone()).to_asm();
+        assert_eq!(asm, "OP_0 OP_PUSHDATA1 07 0x4c00 0xbb 0x4e0500000001");
+        assert_eq!(Script::from_asm(&asm).unwrap().code, code);
+
+        assert_eq!(Script::from_asm("").unwrap().code, Vec::<u8>::new());
+        assert_eq!(Script::from_asm("OP_DUP OP_FOO").unwrap_err(), ScriptErr::InvalidAsm("OP_FOO".to_string()));
+        assert_eq!(Script::from_asm("OP_1 abc").unwrap_err(), ScriptErr::InvalidAsm("abc".to_string()));
+        assert_eq!(Script::from_asm("OP_1 +f").unwrap_err(), ScriptErr::InvalidAsm("+f".to_string()));
+        assert_eq!(Script::from_asm("OP_PUSHDATA1").unwrap_err(), ScriptErr::InvalidAsm("OP_PUSHDATA1".to_string()));
+        assert_eq!(Script::from_asm(&format!("OP_PUSHDATA1 {}", "00".repeat(256))).unwrap_err(), ScriptErr::InvalidAsm("OP_PUSHDATA1".to_string()));
+    }
+}
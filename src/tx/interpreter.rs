@@ -0,0 +1,1669 @@
+/*
+    Script interpreter for verifying that the inputs of a transaction
+    satisfy the outputs they spend.
+
+    Follows the rules of Bitcoin Core's interpreter for legacy, P2SH,
+    segwit version 0 and Taproot (BIP-341/342) spends. Rules that are only
+    enforced by policy can be turned on and off with VerifyFlags.
+*/
+use std::ops::BitOr;
+use crate::{
+    hash,
+    signature,
+    Signature, PublicKey,
+    schnorrsig,
+    tx::{
+        Tx,
+        Output,
+        Script,
+        SigHash,
+        ScriptCodes::{
+            self,
+            *
+        },
+        builder::hashpreimage,
+        instruction,
+        taproot::{
+            self,
+            ControlBlock,
+            TAPSCRIPT_LEAF_VERSION
+        }
+    },
+    util::varint::VarInt
+};
+
+//Consensus limits
+const MAX_SCRIPT_SIZE: usize = 10000;
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+const MAX_STACK_SIZE: usize = 1000;
+const LOCKTIME_THRESHOLD: i64 = 500000000;
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000FFFF;
+const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+const ANNEX_TAG: u8 = 0x50;
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+/**
+    Rules to enforce when verifying scripts.
+    Flags are combined with `|`.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyFlags(pub u32);
+
+impl VerifyFlags {
+    pub const NONE: Self = Self(0);
+    pub const P2SH: Self = Self(1 << 0);                                        //Evaluate P2SH redeem scripts (BIP-16)
+    pub const STRICTENC: Self = Self(1 << 1);                                   //Signatures and public keys must be strictly encoded
+    pub const DERSIG: Self = Self(1 << 2);                                      //Signatures must be strict DER (BIP-66)
+    pub const LOW_S: Self = Self(1 << 3);                                       //Signatures must have a low S value
+    pub const NULLDUMMY: Self = Self(1 << 4);                                   //The CHECKMULTISIG dummy element must be empty (BIP-147)
+    pub const SIGPUSHONLY: Self = Self(1 << 5);                                 //scriptSigs can only contain pushes
+    pub const MINIMALDATA: Self = Self(1 << 6);                                 //Pushes and numbers must use the smallest encoding
+    pub const DISCOURAGE_UPGRADABLE_NOPS: Self = Self(1 << 7);                  //Fail on NOP1 and NOP4-NOP10
+    pub const CLEANSTACK: Self = Self(1 << 8);                                  //Exactly one element must be left on the stack
+    pub const CHECKLOCKTIMEVERIFY: Self = Self(1 << 9);                         //BIP-65
+    pub const CHECKSEQUENCEVERIFY: Self = Self(1 << 10);                        //BIP-112
+    pub const WITNESS: Self = Self(1 << 11);                                    //Segwit (BIP-141)
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: Self = Self(1 << 12);      //Fail on unknown witness versions
+    pub const MINIMALIF: Self = Self(1 << 13);                                  //IF arguments must be empty or 0x01 in segwit v0
+    pub const NULLFAIL: Self = Self(1 << 14);                                   //Failed signatures must be empty
+    pub const WITNESS_PUBKEYTYPE: Self = Self(1 << 15);                         //Segwit v0 public keys must be compressed
+    pub const CONST_SCRIPTCODE: Self = Self(1 << 16);                           //Fail on OP_CODESEPARATOR and signatures in legacy scripts
+    pub const TAPROOT: Self = Self(1 << 17);                                    //Taproot and Tapscript (BIP-341/342)
+    pub const DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: Self = Self(1 << 18);      //Fail on unknown leaf versions
+    pub const DISCOURAGE_OP_SUCCESS: Self = Self(1 << 19);                      //Fail on OP_SUCCESSx in Tapscript
+    pub const DISCOURAGE_UPGRADABLE_PUBKEYTYPE: Self = Self(1 << 20);           //Fail on unknown public key types in Tapscript
+
+    /**
+        Flags enforced by consensus
+    */
+    pub fn mandatory() -> Self {
+        Self::P2SH | Self::DERSIG | Self::NULLDUMMY | Self::CHECKLOCKTIMEVERIFY |
+        Self::CHECKSEQUENCEVERIFY | Self::WITNESS | Self::TAPROOT
+    }
+
+    /**
+        Flags enforced by nodes when relaying transactions
+    */
+    pub fn standard() -> Self {
+        Self::mandatory() | Self::STRICTENC | Self::LOW_S | Self::MINIMALDATA |
+        Self::DISCOURAGE_UPGRADABLE_NOPS | Self::CLEANSTACK | Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM |
+        Self::MINIMALIF | Self::NULLFAIL | Self::WITNESS_PUBKEYTYPE | Self::CONST_SCRIPTCODE |
+        Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION | Self::DISCOURAGE_OP_SUCCESS | Self::DISCOURAGE_UPGRADABLE_PUBKEYTYPE
+    }
+
+    /**
+        Returns true if all the given flags are set
+    */
+    pub fn contains(&self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpreterErr {
+    InvalidIndex(usize),
+    MissingPrevouts(),
+    EvalFalse(),
+    OpReturn(),
+    ScriptSize(),
+    PushSize(),
+    OpCount(),
+    StackSize(),
+    SigCount(),
+    PubKeyCount(),
+    Verify(),
+    EqualVerify(),
+    CheckMultisigVerify(),
+    CheckSigVerify(),
+    NumEqualVerify(),
+    BadOpcode(u8),
+    DisabledOpcode(u8),
+    InvalidStackOperation(),
+    InvalidAltstackOperation(),
+    UnbalancedConditional(),
+    NumOverflow(),
+    NegativeLocktime(),
+    UnsatisfiedLocktime(),
+    SigHashType(),
+    SigDer(),
+    MinimalData(),
+    SigPushOnly(),
+    SigHighS(),
+    SigNullDummy(),
+    PubKeyType(),
+    CleanStack(),
+    MinimalIf(),
+    SigNullFail(),
+    DiscourageUpgradableNops(),
+    DiscourageUpgradableWitnessProgram(),
+    DiscourageUpgradableTaprootVersion(),
+    DiscourageOpSuccess(),
+    DiscourageUpgradablePubKeyType(),
+    WitnessProgramWrongLength(),
+    WitnessProgramWitnessEmpty(),
+    WitnessProgramMismatch(),
+    WitnessMalleated(),
+    WitnessMalleatedP2SH(),
+    WitnessUnexpected(),
+    WitnessPubKeyType(),
+    SchnorrSigSize(),
+    SchnorrSigHashType(),
+    SchnorrSig(),
+    TaprootWrongControlSize(),
+    TapscriptValidationWeight(),
+    TapscriptCheckMultisig(),
+    TapscriptMinimalIf(),
+    OpCodeSeparator(),
+    SigFindAndDelete()
+}
+
+type Stack = Vec<Vec<u8>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+    Tapscript
+}
+
+/**
+    Data about the input being executed that is used by Taproot signature checks
+*/
+struct ExecData {
+    leaf_hash: Option<[u8; 32]>,
+    annex: Option<Vec<u8>>,
+    codesep_pos: u32,
+    validation_weight_left: i64
+}
+
+impl ExecData {
+    fn new() -> Self {
+        Self {
+            leaf_hash: None,
+            annex: None,
+            codesep_pos: 0xFFFFFFFF,
+            validation_weight_left: 0
+        }
+    }
+}
+
+/**
+    Checks signatures and timelocks against the transaction being verified
+*/
+struct Checker<'a> {
+    tx: &'a Tx,
+    index: usize,
+    prevouts: &'a Vec<Output>
+}
+
+impl Tx {
+    /**
+        Verify that every input satisfies the output it spends.
+        The prevouts are the outputs being spent by each input, in the same order as the inputs.
+
+        Returns the index of the first input that fails along with the reason.
+    */
+    pub fn verify(&self, prevouts: &Vec<Output>, flags: VerifyFlags) -> Result<(), (usize, InterpreterErr)> {
+        for i in 0..self.inputs.len() {
+            match self.verify_input(i, prevouts, flags) {
+                Ok(_) => { },
+                Err(x) => return Err((i, x))
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Verify that the input at the given index satisfies the output it spends.
+        All prevouts are needed as Taproot signatures commit to every output being spent.
+    */
+    pub fn verify_input(&self, index: usize, prevouts: &Vec<Output>, flags: VerifyFlags) -> Result<(), InterpreterErr> {
+        if index >= self.inputs.len() { return Err(InterpreterErr::InvalidIndex(index)) }
+        if prevouts.len() != self.inputs.len() { return Err(InterpreterErr::MissingPrevouts()) }
+
+        let witness: Stack = match &self.witness {
+            Some(x) if x.len() > index => x[index].stack().iter().map(|item| item.code.clone()).collect(),
+            _ => vec![]
+        };
+        let checker = Checker {
+            tx: self,
+            index,
+            prevouts
+        };
+
+        verify_script(&self.inputs[index].scriptSig.code, &prevouts[index].script_pub_key.code, &witness, flags, &checker)
+    }
+}
+
+/**
+    Run the scriptSig and scriptPubKey of an input, followed by the P2SH redeem script
+    and witness program if there are any.
+*/
+fn verify_script(script_sig: &[u8], script_pub_key: &[u8], witness: &Stack, flags: VerifyFlags, checker: &Checker) -> Result<(), InterpreterErr> {
+    if flags.contains(VerifyFlags::SIGPUSHONLY) && !is_push_only(script_sig) {
+        return Err(InterpreterErr::SigPushOnly())
+    }
+
+    //The scriptSig leaves its pushes on the stack for the scriptPubKey
+    let mut stack: Stack = vec![];
+    let mut execdata = ExecData::new();
+    eval_script(&mut stack, script_sig, flags, checker, SigVersion::Base, &mut execdata)?;
+    let p2sh_stack: Stack = stack.clone();
+    eval_script(&mut stack, script_pub_key, flags, checker, SigVersion::Base, &mut execdata)?;
+    if stack.is_empty() || !cast_to_bool(&stack[stack.len() - 1]) {
+        return Err(InterpreterErr::EvalFalse())
+    }
+
+    //Native witness programs need an empty scriptSig
+    let mut had_witness: bool = false;
+    if flags.contains(VerifyFlags::WITNESS) {
+        if let Some((version, program)) = witness_program(script_pub_key) {
+            had_witness = true;
+            if !script_sig.is_empty() { return Err(InterpreterErr::WitnessMalleated()) }
+            verify_witness_program(witness, version, program, flags, checker, false)?;
+
+            //The witness program leaves a single element so the clean stack check passes
+            stack.truncate(1);
+        }
+    }
+
+    //Run the redeem script, which is the last push of the scriptSig
+    if flags.contains(VerifyFlags::P2SH) && is_p2sh(script_pub_key) {
+        if !is_push_only(script_sig) { return Err(InterpreterErr::SigPushOnly()) }
+
+        stack = p2sh_stack;
+        let redeem_script: Vec<u8> = pop(&mut stack)?;
+        eval_script(&mut stack, &redeem_script, flags, checker, SigVersion::Base, &mut execdata)?;
+        if stack.is_empty() || !cast_to_bool(&stack[stack.len() - 1]) {
+            return Err(InterpreterErr::EvalFalse())
+        }
+
+        //P2SH nested witness programs need the scriptSig to be exactly the push of the redeem script
+        if flags.contains(VerifyFlags::WITNESS) {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                if script_sig != instruction::push_data(&redeem_script).as_slice() { return Err(InterpreterErr::WitnessMalleatedP2SH()) }
+                verify_witness_program(witness, version, program, flags, checker, true)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    if flags.contains(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        return Err(InterpreterErr::CleanStack())
+    }
+
+    //Inputs that do not spend a witness program cannot have a witness
+    if flags.contains(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+        return Err(InterpreterErr::WitnessUnexpected())
+    }
+
+    Ok(())
+}
+
+/**
+    Verify a witness program with the input's witness stack
+*/
+fn verify_witness_program(witness: &Stack, version: u8, program: &[u8], flags: VerifyFlags, checker: &Checker, is_p2sh: bool) -> Result<(), InterpreterErr> {
+    let mut stack: Stack = witness.clone();
+    let mut execdata = ExecData::new();
+
+    if version == 0 {
+        match program.len() {
+            //P2WSH. The last witness item is the script
+            32 => {
+                let script: Vec<u8> = match stack.pop() {
+                    Some(x) => x,
+                    None => return Err(InterpreterErr::WitnessProgramWitnessEmpty())
+                };
+                if hash::sha256(&script) != program { return Err(InterpreterErr::WitnessProgramMismatch()) }
+
+                execute_witness_script(stack, &script, flags, checker, SigVersion::WitnessV0, &mut execdata)
+            },
+
+            //P2WPKH. The witness is a signature and public key run against a P2PKH script
+            20 => {
+                if stack.len() != 2 { return Err(InterpreterErr::WitnessProgramMismatch()) }
+                let mut script: Vec<u8> = vec![OP_DUP as u8, OP_HASH160 as u8, 0x14];
+                script.extend_from_slice(program);
+                script.append(&mut vec![OP_EQUALVERIFY as u8, OP_CHECKSIG as u8]);
+
+                execute_witness_script(stack, &script, flags, checker, SigVersion::WitnessV0, &mut execdata)
+            },
+            _ => Err(InterpreterErr::WitnessProgramWrongLength())
+        }
+    } else if version == 1 && program.len() == 32 && !is_p2sh {
+        if !flags.contains(VerifyFlags::TAPROOT) { return Ok(()) }
+        if stack.is_empty() { return Err(InterpreterErr::WitnessProgramWitnessEmpty()) }
+
+        //The last item is the annex if there are at least two items and it starts with 0x50
+        if stack.len() >= 2 && stack[stack.len() - 1].first() == Some(&ANNEX_TAG) {
+            execdata.annex = stack.pop();
+        }
+
+        //Key path spend. The only item left is a signature for the output key
+        if stack.len() == 1 {
+            return checker.check_schnorr(&stack[0], program, &execdata)
+        }
+
+        //Script path spend. The last two items are the script and the control block
+        let control: Vec<u8> = pop(&mut stack)?;
+        let script: Vec<u8> = pop(&mut stack)?;
+        if control.len() < TAPROOT_CONTROL_BASE_SIZE ||
+           control.len() > TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT ||
+           (control.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0 {
+            return Err(InterpreterErr::TaprootWrongControlSize())
+        }
+
+        let script = Script::new(script);
+        let committed: bool = match (ControlBlock::from_slice(&control), schnorrsig::PublicKey::from_slice(program)) {
+            (Ok(c), Ok(k)) => c.verify(&script, &k),
+            _ => false
+        };
+        if !committed { return Err(InterpreterErr::WitnessProgramMismatch()) }
+
+        let leaf_version: u8 = control[0] & 0xfe;
+        execdata.leaf_hash = Some(taproot::tap_leaf_hash(leaf_version, &script));
+        if leaf_version == TAPSCRIPT_LEAF_VERSION {
+            execdata.validation_weight_left = witness_size(witness) as i64 + VALIDATION_WEIGHT_OFFSET;
+            return execute_witness_script(stack, &script.code, flags, checker, SigVersion::Tapscript, &mut execdata)
+        }
+
+        //Unknown leaf versions are left for future soft forks
+        if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION) {
+            return Err(InterpreterErr::DiscourageUpgradableTaprootVersion())
+        }
+        Ok(())
+    } else {
+        //Unknown witness versions are left for future soft forks
+        if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+            return Err(InterpreterErr::DiscourageUpgradableWitnessProgram())
+        }
+        Ok(())
+    }
+}
+
+/**
+    Run a witness script with the remaining witness items as the stack.
+    The script must leave exactly one true element on the stack.
+*/
+fn execute_witness_script(mut stack: Stack, script: &[u8], flags: VerifyFlags, checker: &Checker, sigversion: SigVersion, execdata: &mut ExecData) -> Result<(), InterpreterErr> {
+    if sigversion == SigVersion::Tapscript {
+        //Any OP_SUCCESSx makes the script succeed without being run
+        let mut pc: usize = 0;
+        while pc < script.len() {
+            let (opcode, _) = read_op(script, &mut pc)?;
+            if is_op_success(opcode) {
+                if flags.contains(VerifyFlags::DISCOURAGE_OP_SUCCESS) {
+                    return Err(InterpreterErr::DiscourageOpSuccess())
+                }
+                return Ok(())
+            }
+        }
+
+        if stack.len() > MAX_STACK_SIZE { return Err(InterpreterErr::StackSize()) }
+    }
+
+    if stack.iter().any(|x| x.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(InterpreterErr::PushSize())
+    }
+
+    eval_script(&mut stack, script, flags, checker, sigversion, execdata)?;
+    if stack.len() != 1 { return Err(InterpreterErr::CleanStack()) }
+    if !cast_to_bool(&stack[0]) { return Err(InterpreterErr::EvalFalse()) }
+
+    Ok(())
+}
+
+/**
+    Run a script on the given stack
+*/
+fn eval_script(stack: &mut Stack, script: &[u8], flags: VerifyFlags, checker: &Checker, sigversion: SigVersion, execdata: &mut ExecData) -> Result<(), InterpreterErr> {
+    if sigversion != SigVersion::Tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(InterpreterErr::ScriptSize())
+    }
+
+    let require_minimal: bool = flags.contains(VerifyFlags::MINIMALDATA);
+    let mut exec_stack: Vec<bool> = vec![];     //Whether each branch of the open IFs is being executed
+    let mut altstack: Stack = vec![];
+    let mut codehash_start: usize = 0;          //Start of the script signatures commit to, moved by OP_CODESEPARATOR
+    let mut op_count: usize = 0;
+    let mut opcode_pos: u32 = 0;
+    let mut pc: usize = 0;
+    execdata.codesep_pos = 0xFFFFFFFF;
+
+    while pc < script.len() {
+        let executing: bool = exec_stack.iter().all(|x| *x);
+        let (opcode, data) = read_op(script, &mut pc)?;
+
+        if data.len() > MAX_SCRIPT_ELEMENT_SIZE { return Err(InterpreterErr::PushSize()) }
+        if sigversion != SigVersion::Tapscript && opcode > OP_16 as u8 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT { return Err(InterpreterErr::OpCount()) }
+        }
+
+        //Disabled opcodes fail the script even in branches that are not executed
+        if is_disabled(opcode) { return Err(InterpreterErr::DisabledOpcode(opcode)) }
+        if opcode == OP_CODESEPARATOR as u8 && sigversion == SigVersion::Base && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+            return Err(InterpreterErr::OpCodeSeparator())
+        }
+
+        if executing && opcode <= OP_PUSHDATA4 as u8 {
+            if require_minimal && !is_minimal_push(data, opcode) { return Err(InterpreterErr::MinimalData()) }
+            stack.push(data.to_vec());
+        } else if executing || (OP_IF as u8..=OP_ENDIF as u8).contains(&opcode) {
+            let op: ScriptCodes = match ScriptCodes::from_u8(opcode) {
+                Some(x) => x,
+                None => return Err(InterpreterErr::BadOpcode(opcode))
+            };
+            match op {
+                //Push the number of the opcode
+                OP_1NEGATE | OP_1 | OP_2 | OP_3 | OP_4 | OP_5 | OP_6 | OP_7 | OP_8 |
+                OP_9 | OP_10 | OP_11 | OP_12 | OP_13 | OP_14 | OP_15 | OP_16 => stack.push(encode_num(op.small_int().unwrap())),
+
+                //Control
+                OP_NOP => { },
+                OP_CHECKLOCKTIMEVERIFY => {
+                    if flags.contains(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+                        //Locktimes can be 5 bytes long to reach past 2038
+                        let locktime: i64 = decode_num(top(stack, 1)?, require_minimal, 5)?;
+                        if locktime < 0 { return Err(InterpreterErr::NegativeLocktime()) }
+                        if !checker.check_locktime(locktime) { return Err(InterpreterErr::UnsatisfiedLocktime()) }
+                    } else if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        return Err(InterpreterErr::DiscourageUpgradableNops())
+                    }
+                },
+                OP_CHECKSEQUENCEVERIFY => {
+                    if flags.contains(VerifyFlags::CHECKSEQUENCEVERIFY) {
+                        let sequence: i64 = decode_num(top(stack, 1)?, require_minimal, 5)?;
+                        if sequence < 0 { return Err(InterpreterErr::NegativeLocktime()) }
+
+                        //The script does not check the sequence if the disable flag is set
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && !checker.check_sequence(sequence) {
+                            return Err(InterpreterErr::UnsatisfiedLocktime())
+                        }
+                    } else if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        return Err(InterpreterErr::DiscourageUpgradableNops())
+                    }
+                },
+                OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 => {
+                    if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        return Err(InterpreterErr::DiscourageUpgradableNops())
+                    }
+                },
+                OP_IF | OP_NOTIF => {
+                    let mut value: bool = false;
+                    if executing {
+                        let condition: Vec<u8> = match stack.pop() {
+                            Some(x) => x,
+                            None => return Err(InterpreterErr::UnbalancedConditional())
+                        };
+
+                        //The condition must be empty or 0x01 in Tapscript and when MINIMALIF is set for segwit v0
+                        let minimal: bool = condition.is_empty() || condition == vec![0x01];
+                        if sigversion == SigVersion::Tapscript && !minimal {
+                            return Err(InterpreterErr::TapscriptMinimalIf())
+                        }
+                        if sigversion == SigVersion::WitnessV0 && flags.contains(VerifyFlags::MINIMALIF) && !minimal {
+                            return Err(InterpreterErr::MinimalIf())
+                        }
+
+                        value = cast_to_bool(&condition);
+                        if op == OP_NOTIF { value = !value }
+                    }
+                    exec_stack.push(value);
+                },
+                OP_ELSE => {
+                    match exec_stack.last_mut() {
+                        Some(x) => *x = !*x,
+                        None => return Err(InterpreterErr::UnbalancedConditional())
+                    }
+                },
+                OP_ENDIF => {
+                    if exec_stack.pop().is_none() { return Err(InterpreterErr::UnbalancedConditional()) }
+                },
+                OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 1)?) { return Err(InterpreterErr::Verify()) }
+                    stack.pop();
+                },
+                OP_RETURN => return Err(InterpreterErr::OpReturn()),
+
+                //Stack
+                OP_TOALTSTACK => {
+                    let x = pop(stack)?;
+                    altstack.push(x);
+                },
+                OP_FROMALTSTACK => {
+                    match altstack.pop() {
+                        Some(x) => stack.push(x),
+                        None => return Err(InterpreterErr::InvalidAltstackOperation())
+                    }
+                },
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                },
+                OP_2DUP => {
+                    require(stack, 2)?;
+                    let items: Stack = stack[stack.len() - 2..].to_vec();
+                    stack.extend(items);
+                },
+                OP_3DUP => {
+                    require(stack, 3)?;
+                    let items: Stack = stack[stack.len() - 3..].to_vec();
+                    stack.extend(items);
+                },
+                OP_2OVER => {
+                    require(stack, 4)?;
+                    let items: Stack = stack[stack.len() - 4..stack.len() - 2].to_vec();
+                    stack.extend(items);
+                },
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let len = stack.len();
+                    let items: Stack = stack.drain(len - 6..len - 4).collect();
+                    stack.extend(items);
+                },
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                },
+                OP_IFDUP => {
+                    let x = top(stack, 1)?.clone();
+                    if cast_to_bool(&x) { stack.push(x) }
+                },
+                OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+                OP_DROP => { pop(stack)?; },
+                OP_DUP => {
+                    let x = top(stack, 1)?.clone();
+                    stack.push(x);
+                },
+                OP_NIP => {
+                    require(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                },
+                OP_OVER => {
+                    let x = top(stack, 2)?.clone();
+                    stack.push(x);
+                },
+                OP_PICK | OP_ROLL => {
+                    require(stack, 2)?;
+                    let n: i64 = decode_num(&pop(stack)?, require_minimal, 4)?;
+                    if n < 0 || n as usize >= stack.len() { return Err(InterpreterErr::InvalidStackOperation()) }
+                    let position: usize = stack.len() - 1 - n as usize;
+                    let x = if op == OP_ROLL { stack.remove(position) } else { stack[position].clone() };
+                    stack.push(x);
+                },
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    stack.swap(len - 3, len - 2);
+                    stack.swap(len - 2, len - 1);
+                },
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                },
+                OP_TUCK => {
+                    let x = top(stack, 1)?.clone();
+                    require(stack, 2)?;
+                    stack.insert(stack.len() - 2, x);
+                },
+                OP_SIZE => {
+                    let size = top(stack, 1)?.len();
+                    stack.push(encode_num(size as i64));
+                },
+
+                //Equality
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require(stack, 2)?;
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
+                    if op == OP_EQUALVERIFY {
+                        if a != b { return Err(InterpreterErr::EqualVerify()) }
+                    } else {
+                        stack.push(encode_bool(a == b));
+                    }
+                },
+
+                //Arithmetic on one number
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let n: i64 = decode_num(top(stack, 1)?, require_minimal, 4)?;
+                    stack.pop();
+                    let result: i64 = match op {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64
+                    };
+                    stack.push(encode_num(result));
+                },
+
+                //Arithmetic on two numbers
+                OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY | OP_NUMNOTEQUAL |
+                OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHANOREQUAL | OP_MIN | OP_MAX => {
+                    let a: i64 = decode_num(top(stack, 2)?, require_minimal, 4)?;
+                    let b: i64 = decode_num(top(stack, 1)?, require_minimal, 4)?;
+                    stack.truncate(stack.len() - 2);
+                    let result: i64 = match op {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b)
+                    };
+                    if op == OP_NUMEQUALVERIFY {
+                        if result == 0 { return Err(InterpreterErr::NumEqualVerify()) }
+                    } else {
+                        stack.push(encode_num(result));
+                    }
+                },
+                OP_WITHIN => {
+                    let x: i64 = decode_num(top(stack, 3)?, require_minimal, 4)?;
+                    let min: i64 = decode_num(top(stack, 2)?, require_minimal, 4)?;
+                    let max: i64 = decode_num(top(stack, 1)?, require_minimal, 4)?;
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_bool(min <= x && x < max));
+                },
+
+                //Crypto
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let x = pop(stack)?;
+                    stack.push(match op {
+                        OP_RIPEMD160 => hash::ripemd160(&x).to_vec(),
+                        OP_SHA1 => hash::sha1(&x).to_vec(),
+                        OP_SHA256 => hash::sha256(&x).to_vec(),
+                        OP_HASH160 => hash::hash160(&x).to_vec(),
+                        _ => hash::sha256d(&x).to_vec()
+                    });
+                },
+                OP_CODESEPARATOR => {
+                    //Signatures only commit to the script after the last executed OP_CODESEPARATOR
+                    codehash_start = pc;
+                    execdata.codesep_pos = opcode_pos;
+                },
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    require(stack, 2)?;
+                    let sig: Vec<u8> = top(stack, 2)?.clone();
+                    let pubkey: Vec<u8> = top(stack, 1)?.clone();
+                    let success: bool = eval_checksig(&sig, &pubkey, &script[codehash_start..], flags, checker, sigversion, execdata)?;
+                    stack.truncate(stack.len() - 2);
+                    if op == OP_CHECKSIGVERIFY {
+                        if !success { return Err(InterpreterErr::CheckSigVerify()) }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                },
+                OP_CHECKSIGADD => {
+                    //Only available in Tapscript
+                    if sigversion != SigVersion::Tapscript { return Err(InterpreterErr::BadOpcode(opcode)) }
+
+                    require(stack, 3)?;
+                    let sig: Vec<u8> = top(stack, 3)?.clone();
+                    let n: i64 = decode_num(top(stack, 2)?, require_minimal, 4)?;
+                    let pubkey: Vec<u8> = top(stack, 1)?.clone();
+                    let success: bool = eval_checksig(&sig, &pubkey, &script[codehash_start..], flags, checker, sigversion, execdata)?;
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_num(n + success as i64));
+                },
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    //Replaced by OP_CHECKSIGADD in Tapscript
+                    if sigversion == SigVersion::Tapscript { return Err(InterpreterErr::TapscriptCheckMultisig()) }
+
+                    let success: bool = eval_checkmultisig(stack, &script[codehash_start..], flags, checker, sigversion, &mut op_count)?;
+                    if op == OP_CHECKMULTISIGVERIFY {
+                        if !success { return Err(InterpreterErr::CheckMultisigVerify()) }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                },
+                _ => return Err(InterpreterErr::BadOpcode(opcode))
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE { return Err(InterpreterErr::StackSize()) }
+        opcode_pos += 1;
+    }
+
+    if !exec_stack.is_empty() { return Err(InterpreterErr::UnbalancedConditional()) }
+
+    Ok(())
+}
+
+/**
+    Check a signature for OP_CHECKSIG, OP_CHECKSIGVERIFY and OP_CHECKSIGADD.
+    Returns false if the signature is invalid but can be treated as a failed check instead of an error.
+*/
+fn eval_checksig(sig: &[u8], pubkey: &[u8], script_code: &[u8], flags: VerifyFlags, checker: &Checker, sigversion: SigVersion, execdata: &mut ExecData) -> Result<bool, InterpreterErr> {
+    if sigversion == SigVersion::Tapscript {
+        //Every non empty signature uses up some of the validation weight budget
+        let success: bool = !sig.is_empty();
+        if success {
+            execdata.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+            if execdata.validation_weight_left < 0 { return Err(InterpreterErr::TapscriptValidationWeight()) }
+        }
+
+        //32 byte keys are BIP-340 keys. Other non empty keys are left for future soft forks
+        if pubkey.is_empty() {
+            return Err(InterpreterErr::PubKeyType())
+        } else if pubkey.len() == 32 {
+            if success { checker.check_schnorr(sig, pubkey, execdata)? }
+        } else if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE) {
+            return Err(InterpreterErr::DiscourageUpgradablePubKeyType())
+        }
+
+        return Ok(success)
+    }
+
+    //Legacy signatures cannot sign themselves so they are removed from the script code
+    let mut script_code: Vec<u8> = script_code.to_vec();
+    if sigversion == SigVersion::Base {
+        let found: usize = find_and_delete(&mut script_code, &instruction::push_data(sig));
+        if found > 0 && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+            return Err(InterpreterErr::SigFindAndDelete())
+        }
+    }
+
+    check_signature_encoding(sig, flags)?;
+    check_pubkey_encoding(pubkey, flags, sigversion)?;
+    let success: bool = checker.check_ecdsa(sig, pubkey, &script_code, sigversion);
+    if !success && flags.contains(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+        return Err(InterpreterErr::SigNullFail())
+    }
+
+    Ok(success)
+}
+
+/**
+    Check the signatures for OP_CHECKMULTISIG and OP_CHECKMULTISIGVERIFY.
+    The stack holds a dummy element, the signatures and their count, then the public keys and their count.
+    Every item used is removed from the stack.
+*/
+fn eval_checkmultisig(stack: &mut Stack, script_code: &[u8], flags: VerifyFlags, checker: &Checker, sigversion: SigVersion, op_count: &mut usize) -> Result<bool, InterpreterErr> {
+    let require_minimal: bool = flags.contains(VerifyFlags::MINIMALDATA);
+
+    let mut i: usize = 1;
+    let key_count: i64 = decode_num(top(stack, i)?, require_minimal, 4)?;
+    if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) { return Err(InterpreterErr::PubKeyCount()) }
+    let mut key_count: usize = key_count as usize;
+    *op_count += key_count;
+    if *op_count > MAX_OPS_PER_SCRIPT { return Err(InterpreterErr::OpCount()) }
+    i += 1;
+    let mut key_index: usize = i;
+    let mut keys_to_clear: usize = key_count + 2;  //Keys and counts that must be empty with NULLFAIL if the check fails
+    i += key_count;
+
+    let sig_count: i64 = decode_num(top(stack, i)?, require_minimal, 4)?;
+    if sig_count < 0 || sig_count as usize > key_count { return Err(InterpreterErr::SigCount()) }
+    let mut sig_count: usize = sig_count as usize;
+    i += 1;
+    let mut sig_index: usize = i;
+    i += sig_count;
+    require(stack, i)?;
+
+    //Legacy signatures cannot sign themselves so they are removed from the script code
+    let mut script_code: Vec<u8> = script_code.to_vec();
+    if sigversion == SigVersion::Base {
+        for k in 0..sig_count {
+            let sig = top(stack, sig_index + k)?.clone();
+            let found: usize = find_and_delete(&mut script_code, &instruction::push_data(&sig));
+            if found > 0 && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+                return Err(InterpreterErr::SigFindAndDelete())
+            }
+        }
+    }
+
+    //Signatures must be in the same order as the keys they were made with
+    let mut success: bool = true;
+    while success && sig_count > 0 {
+        let sig = top(stack, sig_index)?.clone();
+        let pubkey = top(stack, key_index)?.clone();
+        check_signature_encoding(&sig, flags)?;
+        check_pubkey_encoding(&pubkey, flags, sigversion)?;
+
+        if checker.check_ecdsa(&sig, &pubkey, &script_code, sigversion) {
+            sig_index += 1;
+            sig_count -= 1;
+        }
+        key_index += 1;
+        key_count -= 1;
+
+        //There are more signatures left than keys to check them with
+        if sig_count > key_count { success = false }
+    }
+
+    //Remove the items from the stack
+    while i > 1 {
+        i -= 1;
+        if !success && flags.contains(VerifyFlags::NULLFAIL) && keys_to_clear == 0 && !top(stack, 1)?.is_empty() {
+            return Err(InterpreterErr::SigNullFail())
+        }
+        keys_to_clear = keys_to_clear.saturating_sub(1);
+        stack.pop();
+    }
+
+    //An extra item is removed because of a bug in the original implementation
+    let dummy: Vec<u8> = pop(stack)?;
+    if flags.contains(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+        return Err(InterpreterErr::SigNullDummy())
+    }
+
+    Ok(success)
+}
+
+impl Checker<'_> {
+    /**
+        Check an ECDSA signature with the sighash byte appended against a public key
+    */
+    fn check_ecdsa(&self, sig: &[u8], pubkey: &[u8], script_code: &[u8], sigversion: SigVersion) -> bool {
+        let pubkey = match PublicKey::from_slice(pubkey) {
+            Ok(x) => x,
+            Err(_) => return false
+        };
+        if sig.is_empty() { return false }
+
+        //Signatures are checked with a low S value as libsecp256k1 only accepts those
+        let hash_type: u32 = sig[sig.len() - 1] as u32;
+        let mut signature = match Signature::from_der_lax(&sig[..sig.len() - 1]) {
+            Ok(x) => x,
+            Err(_) => return false
+        };
+        signature.normalize_s();
+
+        let hash: [u8; 32] = match sigversion {
+            SigVersion::Base => self.legacy_sighash(script_code, hash_type),
+            _ => self.segwit_sighash(script_code, hash_type)
+        };
+        let msg = match signature::new_msg(&hash) {
+            Ok(x) => x,
+            Err(_) => return false
+        };
+
+        signature::verify(&signature, &msg, &pubkey).is_ok()
+    }
+
+    /**
+        Check a BIP-340 signature with an optional sighash byte against an x-only public key
+    */
+    fn check_schnorr(&self, sig: &[u8], pubkey: &[u8], execdata: &ExecData) -> Result<(), InterpreterErr> {
+        let hash_type: u8 = match sig.len() {
+            64 => 0x00,
+            //SigHash DEFAULT is only allowed by leaving out the sighash byte
+            65 if sig[64] == 0x00 => return Err(InterpreterErr::SchnorrSigHashType()),
+            65 => sig[64],
+            _ => return Err(InterpreterErr::SchnorrSigSize())
+        };
+        let hash: [u8; 32] = match self.taproot_sighash(hash_type, execdata) {
+            Some(x) => x,
+            None => return Err(InterpreterErr::SchnorrSigHashType())
+        };
+
+        let verified: bool = match (
+            schnorrsig::Signature::from_slice(&sig[..64]),
+            schnorrsig::PublicKey::from_slice(pubkey),
+            signature::new_msg(&hash)
+        ) {
+            (Ok(s), Ok(k), Ok(m)) => signature::verify_schnorr(&s, &m, &k).is_ok(),
+            _ => false
+        };
+        if !verified { return Err(InterpreterErr::SchnorrSig()) }
+
+        Ok(())
+    }
+
+    /**
+        Legacy sighash of the input for the given script code and sighash type
+    */
+    fn legacy_sighash(&self, script_code: &[u8], hash_type: u32) -> [u8; 32] {
+        //Signing SINGLE without a matching output signs the number 1
+        if hash_type & 0x1f == SigHash::SINGLE as u32 && self.index >= self.tx.outputs.len() {
+            let mut one: [u8; 32] = [0; 32];
+            one[0] = 0x01;
+            return one
+        }
+
+        //OP_CODESEPARATORs are not part of the signed script
+        let mut tx_copy: Tx = self.tx.clone();
+        tx_copy.segwit = false;
+        let script_code = Script::new(remove_codeseparators(script_code));
+        match hashpreimage::legacy(&mut tx_copy, &base_sighash(hash_type), self.index, &script_code) {
+            Ok(x) => hash::sha256d(with_hash_type(x, hash_type)),
+            Err(_) => [0; 32]
+        }
+    }
+
+    /**
+        BIP-143 sighash of the input for the given script code and sighash type
+    */
+    fn segwit_sighash(&self, script_code: &[u8], hash_type: u32) -> [u8; 32] {
+        let mut sighash: SigHash = base_sighash(hash_type);
+
+        //Signing SINGLE without a matching output does not commit to any outputs
+        if hash_type & 0x1f == SigHash::SINGLE as u32 && self.index >= self.tx.outputs.len() {
+            sighash = match sighash {
+                SigHash::SINGLE_ANYONECANPAY => SigHash::NONE_ANYONECANPAY,
+                _ => SigHash::NONE
+            };
+        }
+
+        let mut code: Vec<u8> = VarInt::from_usize(script_code.len()).unwrap();
+        code.extend_from_slice(script_code);
+        match hashpreimage::segwit(self.tx, &sighash, self.index, &Script::new(code), self.prevouts[self.index].value) {
+            Ok(x) => hash::sha256d(with_hash_type(x, hash_type)),
+            Err(_) => [0; 32]
+        }
+    }
+
+    /**
+        BIP-341 sighash of the input. Returns None if the sighash type is not valid.
+    */
+    fn taproot_sighash(&self, hash_type: u8, execdata: &ExecData) -> Option<[u8; 32]> {
+        let sighash: SigHash = SigHash::from_u32(hash_type as u32)?;
+        if hash_type & 0x03 == SigHash::SINGLE as u8 && self.index >= self.tx.outputs.len() {
+            return None
+        }
+
+        let mut preimage: Vec<u8> = match hashpreimage::taproot(self.tx, &sighash, self.index, self.prevouts, &execdata.annex, execdata.leaf_hash) {
+            Ok(x) => x,
+            Err(_) => return None
+        };
+
+        //Script path spends end with the position of the last executed OP_CODESEPARATOR
+        if execdata.leaf_hash.is_some() {
+            preimage = with_hash_type(preimage, execdata.codesep_pos);
+        }
+
+        Some(hash::tagged_hash("TapSighash", preimage))
+    }
+
+    /**
+        Check the locktime required by OP_CHECKLOCKTIMEVERIFY against the transaction (BIP-65)
+    */
+    fn check_locktime(&self, locktime: i64) -> bool {
+        let tx_locktime: i64 = self.tx.locktime as i64;
+
+        //Both must be heights or both must be times
+        if (tx_locktime < LOCKTIME_THRESHOLD) != (locktime < LOCKTIME_THRESHOLD) { return false }
+        if locktime > tx_locktime { return false }
+
+        //The locktime is ignored if the input is final
+        self.tx.inputs[self.index].sequence != 0xFFFFFFFF
+    }
+
+    /**
+        Check the relative locktime required by OP_CHECKSEQUENCEVERIFY against the input's sequence (BIP-112)
+    */
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence: i64 = self.tx.inputs[self.index].sequence as i64;
+
+        //Relative locktimes only apply to version 2 transactions with the disable flag not set
+        if self.tx.version < 2 { return false }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 { return false }
+
+        //Both must be block counts or both must be time
+        let mask: i64 = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence: i64 = tx_sequence & mask;
+        let sequence: i64 = sequence & mask;
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) { return false }
+
+        sequence <= tx_sequence
+    }
+}
+
+/**
+    Get the SigHash used to create the preimage of a legacy or segwit v0 sighash type.
+    Undefined types are treated as ALL.
+*/
+fn base_sighash(hash_type: u32) -> SigHash {
+    let anyonecanpay: bool = hash_type & 0x80 != 0;
+    match (hash_type & 0x1f, anyonecanpay) {
+        (0x02, false) => SigHash::NONE,
+        (0x03, false) => SigHash::SINGLE,
+        (0x02, true) => SigHash::NONE_ANYONECANPAY,
+        (0x03, true) => SigHash::SINGLE_ANYONECANPAY,
+        (_, false) => SigHash::ALL,
+        (_, true) => SigHash::ALL_ANYONECANPAY
+    }
+}
+
+/**
+    Replace the last 4 bytes of a preimage with the given value.
+    Lets signatures with undefined sighash types be checked using the preimage of their base type.
+*/
+fn with_hash_type(mut preimage: Vec<u8>, hash_type: u32) -> Vec<u8> {
+    let len = preimage.len();
+    preimage[len - 4..].copy_from_slice(&hash_type.to_le_bytes());
+
+    preimage
+}
+
+/**
+    Check the encoding of an ECDSA signature against the verification flags
+*/
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), InterpreterErr> {
+    //Empty signatures are allowed so that a failed CHECKSIG can be created
+    if sig.is_empty() { return Ok(()) }
+
+    if (flags.contains(VerifyFlags::DERSIG) || flags.contains(VerifyFlags::LOW_S) || flags.contains(VerifyFlags::STRICTENC))
+        && !is_valid_signature_encoding(sig) {
+        return Err(InterpreterErr::SigDer())
+    }
+    if flags.contains(VerifyFlags::LOW_S) && !is_low_s(sig) {
+        return Err(InterpreterErr::SigHighS())
+    }
+    if flags.contains(VerifyFlags::STRICTENC) {
+        let hash_type: u8 = sig[sig.len() - 1] & !0x80;
+        if hash_type < SigHash::ALL as u8 || hash_type > SigHash::SINGLE as u8 {
+            return Err(InterpreterErr::SigHashType())
+        }
+    }
+
+    Ok(())
+}
+
+/**
+    Check the encoding of an ECDSA public key against the verification flags
+*/
+fn check_pubkey_encoding(pubkey: &[u8], flags: VerifyFlags, sigversion: SigVersion) -> Result<(), InterpreterErr> {
+    let compressed: bool = pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03);
+    let uncompressed: bool = pubkey.len() == 65 && pubkey[0] == 0x04;
+
+    if flags.contains(VerifyFlags::STRICTENC) && !compressed && !uncompressed {
+        return Err(InterpreterErr::PubKeyType())
+    }
+    if flags.contains(VerifyFlags::WITNESS_PUBKEYTYPE) && sigversion == SigVersion::WitnessV0 && !compressed {
+        return Err(InterpreterErr::WitnessPubKeyType())
+    }
+
+    Ok(())
+}
+
+/**
+    Check that a signature with the sighash byte appended is strict DER (BIP-66)
+*/
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    //0x30 [total length] 0x02 [R length] [R] 0x02 [S length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 { return false }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 { return false }
+
+    let len_r: usize = sig[3] as usize;
+    if 5 + len_r >= sig.len() { return false }
+    let len_s: usize = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() { return false }
+
+    //R and S must be positive integers without unnecessary leading zeros
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 { return false }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 { return false }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 { return false }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 { return false }
+
+    true
+}
+
+/**
+    Check that the S value of a signature with the sighash byte appended is in the lower half of the curve order
+*/
+fn is_low_s(sig: &[u8]) -> bool {
+    match Signature::from_der_lax(&sig[..sig.len() - 1]) {
+        Ok(x) => {
+            let mut normalized = x;
+            normalized.normalize_s();
+            normalized == x
+        },
+        Err(_) => false
+    }
+}
+
+/**
+    Read the opcode at the given position of a script and the data it pushes.
+    Pushes that run past the end of the script are bad opcodes.
+*/
+fn read_op<'a>(script: &'a [u8], pc: &mut usize) -> Result<(u8, &'a [u8]), InterpreterErr> {
+    let opcode: u8 = script[*pc];
+    match instruction::read_op(script, pc) {
+        Ok(x) => Ok(x),
+        Err(_) => Err(InterpreterErr::BadOpcode(opcode))
+    }
+}
+
+/**
+    Check that data is pushed with the smallest possible opcode
+*/
+fn is_minimal_push(data: &[u8], opcode: u8) -> bool {
+    match data.len() {
+        0 => opcode == OP_0 as u8,
+        1 if data[0] >= 1 && data[0] <= 16 => Some(opcode) == ScriptCodes::from_small_int(data[0]).map(|x| x as u8),
+        1 if data[0] == 0x81 => opcode == OP_1NEGATE as u8,
+        1..=75 => opcode as usize == data.len(),
+        76..=255 => opcode == OP_PUSHDATA1 as u8,
+        256..=65535 => opcode == OP_PUSHDATA2 as u8,
+        _ => true
+    }
+}
+
+/**
+    Remove every occurrence of a push from a script, only matching at opcode boundaries.
+    Returns the number of pushes removed.
+*/
+fn find_and_delete(script: &mut Vec<u8>, pattern: &[u8]) -> usize {
+    if pattern.is_empty() { return 0 }
+
+    let mut result: Vec<u8> = vec![];
+    let mut found: usize = 0;
+    let mut pc: usize = 0;
+    let mut last: usize = 0;
+    loop {
+        result.extend_from_slice(&script[last..pc]);
+        while script.len() - pc >= pattern.len() && &script[pc..pc + pattern.len()] == pattern {
+            pc += pattern.len();
+            found += 1;
+        }
+        last = pc;
+        if pc >= script.len() || read_op(script, &mut pc).is_err() { break }
+    }
+
+    if found > 0 {
+        result.extend_from_slice(&script[last..]);
+        *script = result;
+    }
+
+    found
+}
+
+/**
+    Remove every OP_CODESEPARATOR from a script
+*/
+fn remove_codeseparators(script: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = vec![];
+    let mut pc: usize = 0;
+    while pc < script.len() {
+        let start: usize = pc;
+        if read_op(script, &mut pc).is_err() {
+            result.extend_from_slice(&script[start..]);
+            break
+        }
+        if script[start] != OP_CODESEPARATOR as u8 {
+            result.extend_from_slice(&script[start..pc]);
+        }
+    }
+
+    result
+}
+
+/**
+    Returns true if the script only pushes data
+*/
+fn is_push_only(script: &[u8]) -> bool {
+    let mut pc: usize = 0;
+    while pc < script.len() {
+        match read_op(script, &mut pc) {
+            Ok((opcode, _)) if opcode <= OP_16 as u8 => { },
+            _ => return false
+        }
+    }
+
+    true
+}
+
+/**
+    Returns true if the script is a P2SH scriptPubKey
+*/
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == OP_HASH160 as u8 && script[1] == 0x14 && script[22] == OP_EQUAL as u8
+}
+
+/**
+    Get the version and program of a witness program scriptPubKey
+*/
+fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 { return None }
+    let version: u8 = match ScriptCodes::from_u8(script[0]).and_then(|x| x.small_int()) {
+        Some(x) if x >= 0 => x as u8,
+        _ => return None
+    };
+    if script[1] as usize + 2 != script.len() { return None }
+
+    Some((version, &script[2..]))
+}
+
+/**
+    Opcodes that fail the script wherever they appear in legacy and segwit v0 scripts
+*/
+fn is_disabled(opcode: u8) -> bool {
+    match ScriptCodes::from_u8(opcode) {
+        Some(OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR |
+             OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT) => true,
+        _ => false
+    }
+}
+
+/**
+    Opcodes that make a Tapscript succeed (BIP-342)
+*/
+fn is_op_success(opcode: u8) -> bool {
+    match opcode {
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254 => true,
+        _ => false
+    }
+}
+
+/**
+    Serialized size of a witness stack
+*/
+fn witness_size(witness: &Stack) -> usize {
+    let mut size: usize = VarInt::from_usize(witness.len()).unwrap().len();
+    for item in witness {
+        size += VarInt::from_usize(item.len()).unwrap().len() + item.len();
+    }
+
+    size
+}
+
+/**
+    Decode a number from the stack.
+    Numbers are little endian with the sign stored in the highest bit of the last byte.
+*/
+pub(crate) fn decode_num(bytes: &[u8], require_minimal: bool, max_size: usize) -> Result<i64, InterpreterErr> {
+    if bytes.len() > max_size { return Err(InterpreterErr::NumOverflow()) }
+    if bytes.is_empty() { return Ok(0) }
+
+    //The last byte can only be 0x00 or 0x80 if the byte before it needs its highest bit
+    let last: u8 = bytes[bytes.len() - 1];
+    if require_minimal && last & 0x7f == 0 && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0) {
+        return Err(InterpreterErr::MinimalData())
+    }
+
+    let mut value: i64 = 0;
+    for i in 0..bytes.len() {
+        value |= (bytes[i] as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        return Ok(-(value & !(0x80 << (8 * (bytes.len() - 1)))))
+    }
+
+    Ok(value)
+}
+
+/**
+    Encode a number to be pushed to the stack
+*/
+pub(crate) fn encode_num(value: i64) -> Vec<u8> {
+    if value == 0 { return vec![] }
+
+    let mut bytes: Vec<u8> = vec![];
+    let mut abs: u64 = value.unsigned_abs();
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+
+    //Add a byte for the sign if the highest bit is already used
+    let last: usize = bytes.len() - 1;
+    if bytes[last] & 0x80 != 0 {
+        bytes.push(if value < 0 { 0x80 } else { 0x00 });
+    } else if value < 0 {
+        bytes[last] |= 0x80;
+    }
+
+    bytes
+}
+
+/**
+    Stack elements are false if they are zero or negative zero
+*/
+fn cast_to_bool(bytes: &[u8]) -> bool {
+    for i in 0..bytes.len() {
+        if bytes[i] != 0 {
+            return !(i == bytes.len() - 1 && bytes[i] == 0x80)
+        }
+    }
+
+    false
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value { vec![0x01] } else { vec![] }
+}
+
+/**
+    Get the element at the given depth from the top of the stack, starting at 1
+*/
+fn top(stack: &Stack, depth: usize) -> Result<&Vec<u8>, InterpreterErr> {
+    require(stack, depth)?;
+    Ok(&stack[stack.len() - depth])
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, InterpreterErr> {
+    match stack.pop() {
+        Some(x) => Ok(x),
+        None => Err(InterpreterErr::InvalidStackOperation())
+    }
+}
+
+fn require(stack: &Stack, size: usize) -> Result<(), InterpreterErr> {
+    if stack.len() < size { return Err(InterpreterErr::InvalidStackOperation()) }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PrivKey, PubKey, Key,
+        tx::{
+            Input,
+            TxBuilder,
+            SigningData,
+            BuilderErr,
+            TapTreeBuilder
+        },
+        util::{
+            Network,
+            bytes::decode_02x
+        },
+        test_utils::{ PREV_TXID, key }
+    };
+
+    fn pubkey(key: &PrivKey) -> Vec<u8> {
+        PubKey::from_priv_key(key).as_bytes::<33>().to_vec()
+    }
+
+    fn p2pkh(key: &PrivKey) -> Script {
+        let mut script: Vec<u8> = vec![OP_DUP as u8, OP_HASH160 as u8, 0x14];
+        script.append(&mut hash::hash160(pubkey(key)).to_vec());
+        script.append(&mut vec![OP_EQUALVERIFY as u8, OP_CHECKSIG as u8]);
+        Script::new(script)
+    }
+
+    fn p2wpkh(key: &PrivKey) -> Script {
+        let mut script: Vec<u8> = vec![OP_0 as u8, 0x14];
+        script.append(&mut hash::hash160(pubkey(key)).to_vec());
+        Script::new(script)
+    }
+
+    fn p2sh(redeem_script: &Script) -> Script {
+        let mut script: Vec<u8> = vec![OP_HASH160 as u8, 0x14];
+        script.append(&mut hash::hash160(&redeem_script.code).to_vec());
+        script.push(OP_EQUAL as u8);
+        Script::new(script)
+    }
+
+    fn p2wsh(witness_script: &Script) -> Script {
+        let mut script: Vec<u8> = vec![OP_0 as u8, 0x20];
+        script.append(&mut hash::sha256(&witness_script.code).to_vec());
+        Script::new(script)
+    }
+
+    //Run a scriptSig against a scriptPubKey in a transaction with one input
+    fn run(script_sig: Vec<u8>, script_pub_key: Vec<u8>, version: u32, locktime: u32, sequence: u32, flags: VerifyFlags) -> Result<(), InterpreterErr> {
+        let mut input = Input::unsigned_input([0x01; 32], 0, sequence);
+        input.scriptSig = Script::new(script_sig);
+        let mut tx = Tx::construct(vec![input], vec![Output { value: 1000, script_pub_key: Script::new(vec![OP_1 as u8]) }], locktime, false);
+        tx.version = version;
+
+        tx.verify_input(0, &vec![Output { value: 2000, script_pub_key: Script::new(script_pub_key) }], flags)
+    }
+
+    #[test]
+    fn bip143_p2pk_and_p2wpkh() {
+        //BIP-143 native P2WPKH example spending a P2PK and a P2WPKH output
+        let raw = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+        let mut tx = Tx::from_hex(raw).unwrap();
+        let prevouts = vec![
+            Output { value: 625000000, script_pub_key: Script::new(decode_02x("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac")) },
+            Output { value: 600000000, script_pub_key: Script::new(decode_02x("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1")) }
+        ];
+        tx.verify(&prevouts, VerifyFlags::standard()).unwrap();
+
+        //Changing an output invalidates both signatures
+        tx.outputs[0].value -= 1;
+        assert_eq!(tx.verify_input(0, &prevouts, VerifyFlags::mandatory()), Err(InterpreterErr::EvalFalse()));
+        assert_eq!(tx.verify_input(1, &prevouts, VerifyFlags::mandatory()), Err(InterpreterErr::EvalFalse()));
+        assert_eq!(tx.verify_input(1, &prevouts, VerifyFlags::standard()), Err(InterpreterErr::SigNullFail()));
+
+        //The segwit input is verified against the amount it spends
+        tx.outputs[0].value += 1;
+        let mut wrong_amount = prevouts.clone();
+        wrong_amount[1].value += 1;
+        assert!(tx.verify_input(0, &wrong_amount, VerifyFlags::standard()).is_ok());
+        assert!(tx.verify_input(1, &wrong_amount, VerifyFlags::standard()).is_err());
+    }
+
+    #[test]
+    fn builder_single_key_inputs() {
+        let keys: Vec<PrivKey> = (1..5).map(|x| key(x)).collect();
+        let nested = p2wpkh(&keys[3]);
+        let prevouts = vec![
+            Output { value: 10000, script_pub_key: p2pkh(&keys[0]) },
+            Output { value: 20000, script_pub_key: p2pkh(&keys[1]) },
+            Output { value: 30000, script_pub_key: p2wpkh(&keys[2]) },
+            Output { value: 40000, script_pub_key: p2sh(&nested) }
+        ];
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        for i in 0..prevouts.len() {
+            txb.add_input_with_prevout("a8064a6143c6027dddafb356236a475dab3f56fa3dad1dc0c873e54e6527f167", i as u32, prevouts[i].clone()).unwrap();
+        }
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 50000).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 40000).unwrap();
+
+        //Sign with a mix of sighashes so that legacy and segwit sighashes are all checked
+        txb.sign_input(0, &SigningData::new(vec![keys[0]], None), SigHash::ALL).unwrap();
+        txb.sign_input(1, &SigningData::new(vec![keys[1]], None), SigHash::SINGLE).unwrap();
+        txb.sign_input(2, &SigningData::new(vec![keys[2]], None), SigHash::NONE_ANYONECANPAY).unwrap();
+        txb.sign_input(3, &SigningData::new(vec![keys[3]], Some(nested)), SigHash::ALL_ANYONECANPAY).unwrap();
+        let tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+
+        //The input signed with SINGLE only commits to its own output
+        let mut tx = tx;
+        tx.outputs[0].value -= 100;
+        assert!(tx.verify_input(1, &prevouts, VerifyFlags::standard()).is_ok());
+        assert!(tx.verify_input(0, &prevouts, VerifyFlags::standard()).is_err());
+    }
+
+    #[test]
+    fn builder_multisig_inputs() {
+        let keys: Vec<PrivKey> = (5..8).map(|x| key(x)).collect();
+        let multisig = Script::multisig_locking(2, &keys);
+        let prevouts = vec![
+            Output { value: 10000, script_pub_key: p2sh(&multisig) },
+            Output { value: 20000, script_pub_key: p2wsh(&multisig) },
+            Output { value: 30000, script_pub_key: p2sh(&p2wsh(&multisig)) }
+        ];
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        for i in 0..prevouts.len() {
+            txb.add_input_with_prevout(PREV_TXID, i as u32, prevouts[i].clone()).unwrap();
+        }
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 55000).unwrap();
+
+        //Signatures must be in the same order as the keys in the script
+        let signers = vec![keys[0], keys[2]];
+        txb.sign_input(0, &SigningData::new(signers.clone(), Some(multisig.clone())), SigHash::ALL).unwrap();
+        txb.sign_input(1, &SigningData::new(signers.clone(), Some(multisig.clone())), SigHash::ALL).unwrap();
+        txb.sign_input(2, &SigningData::nested_p2wsh(signers.clone(), multisig.clone()), SigHash::ALL).unwrap();
+        txb.build_verified(VerifyFlags::standard()).unwrap();
+
+        //Redeem scripts longer than 255 bytes are pushed with OP_PUSHDATA2
+        let keys: Vec<PrivKey> = (20..35).map(|x| key(x)).collect();
+        let large = Script::multisig_locking(3, &keys);
+        assert_eq!(large.code.len(), 513);
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 3, Output { value: 10000, script_pub_key: p2sh(&large) }).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 5000).unwrap();
+        txb.sign_input(0, &SigningData::new(vec![keys[1], keys[7], keys[14]], Some(large.clone())), SigHash::ALL).unwrap();
+        let tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+        let script_sig: &Vec<u8> = &tx.inputs[0].scriptSig.code;
+        assert_eq!(&script_sig[script_sig.len() - 516..script_sig.len() - 513], &[0x4d, 0x01, 0x02]);
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 1, prevouts[1].clone()).unwrap();
+        txb.add_output("msSJzRfQb2T3hvws3vRhqtK2Ao39cabEa2", 15000).unwrap();
+        txb.sign_input(0, &SigningData::new(vec![keys[2], keys[0]], Some(multisig.clone())), SigHash::ALL).unwrap();
+        match txb.build_verified(VerifyFlags::standard()) {
+            Err(BuilderErr::ScriptVerifyFailed(0, InterpreterErr::SigNullFail())) => { },
+            x => panic!("{:?}", x.map(|x| x.get_txid()))
+        }
+    }
+
+    #[test]
+    fn builder_taproot_inputs() {
+        let internal = key(9);
+        let signers = vec![key(10), key(11)];
+
+        //2-of-2 leaf using OP_CHECKSIGADD next to a single key leaf
+        let mut leaf: Vec<u8> = vec![0x20];
+        leaf.append(&mut taproot::internal_key(&signers[0]).serialize().to_vec());
+        leaf.push(OP_CHECKSIG as u8);
+        leaf.push(0x20);
+        leaf.append(&mut taproot::internal_key(&signers[1]).serialize().to_vec());
+        leaf.append(&mut vec![OP_CHECKSIGADD as u8, 0x52, OP_NUMEQUAL as u8]);
+        let leaf = Script::new(leaf);
+        let mut other: Vec<u8> = vec![0x20];
+        other.append(&mut taproot::internal_key(&key(12)).serialize().to_vec());
+        other.push(OP_CHECKSIG as u8);
+
+        let mut tree = TapTreeBuilder::new();
+        tree.add_leaf(1, leaf.clone());
+        tree.add_leaf(1, Script::new(other));
+        let spend_info = tree.finalize(taproot::internal_key(&internal)).unwrap();
+        let prevout = Output { value: 50000, script_pub_key: spend_info.script_pub_key() };
+
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 0, prevout.clone()).unwrap();
+        txb.add_input_with_prevout(PREV_TXID, 1, prevout.clone()).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 90000).unwrap();
+        let control_block = spend_info.control_block(&leaf).unwrap();
+        txb.sign_input(0, &SigningData::taproot_key_path(internal, spend_info.merkle_root), SigHash::DEFAULT).unwrap();
+        txb.sign_input(1, &SigningData::taproot_script_path(signers.clone(), leaf.clone(), control_block.clone()).with_annex(vec![0x50, 0x01]), SigHash::ALL).unwrap();
+        let tx = txb.build_verified(VerifyFlags::standard()).unwrap();
+
+        //Key path spends are a single signature, script path spends end with the annex
+        let witness = tx.witness.clone().unwrap();
+        assert_eq!(witness[0].len(), 1);
+        assert_eq!(witness[1].len(), 5);
+
+        //Only one valid signature fails the NUMEQUAL
+        let mut txb = TxBuilder::new(Network::Testnet);
+        txb.add_input_with_prevout(PREV_TXID, 1, prevout.clone()).unwrap();
+        txb.add_output("tb1qj8rvxxnzkdapv3rueazzyn434duv5q5ep3ze5e", 40000).unwrap();
+        txb.sign_input(0, &SigningData::taproot_script_path(vec![signers[0], key(12)], leaf, control_block), SigHash::DEFAULT).unwrap();
+        match txb.build_verified(VerifyFlags::standard()) {
+            Err(BuilderErr::ScriptVerifyFailed(0, InterpreterErr::SchnorrSig())) => { },
+            x => panic!("{:?}", x.map(|x| x.get_txid()))
+        }
+    }
+
+    #[test]
+    fn script_evaluation() {
+        let flags = VerifyFlags::standard();
+
+        //2 + 3 == 5
+        assert!(run(vec![0x52, 0x53], vec![OP_ADD as u8, 0x55, OP_EQUAL as u8], 1, 0, 0xFFFFFFFF, flags).is_ok());
+        assert_eq!(run(vec![0x52, 0x52], vec![OP_ADD as u8, 0x55, OP_EQUAL as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::EvalFalse()));
+
+        //Branches
+        let branches = vec![OP_IF as u8, OP_0 as u8, OP_ELSE as u8, OP_1 as u8, OP_ENDIF as u8];
+        assert!(run(vec![OP_0 as u8], branches.clone(), 1, 0, 0xFFFFFFFF, flags).is_ok());
+        assert_eq!(run(vec![OP_1 as u8], branches.clone(), 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::EvalFalse()));
+        assert_eq!(run(vec![OP_1 as u8], vec![OP_IF as u8, OP_1 as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::UnbalancedConditional()));
+
+        //Disabled opcodes fail even if they are not executed
+        assert_eq!(run(vec![OP_0 as u8], vec![OP_IF as u8, OP_CAT as u8, OP_ENDIF as u8, OP_1 as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::DisabledOpcode(OP_CAT as u8)));
+        assert_eq!(run(vec![], vec![OP_RETURN as u8, OP_1 as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::OpReturn()));
+
+        //Reserved and unassigned opcodes only fail when they are executed
+        assert!(run(vec![OP_0 as u8], vec![OP_IF as u8, OP_RESERVED as u8, 0xbb, OP_ENDIF as u8, OP_1 as u8], 1, 0, 0xFFFFFFFF, flags).is_ok());
+        assert_eq!(run(vec![], vec![OP_1 as u8, OP_RESERVED as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::BadOpcode(OP_RESERVED as u8)));
+        assert_eq!(run(vec![], vec![OP_1 as u8, 0xbb], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::BadOpcode(0xbb)));
+        assert_eq!(run(vec![], vec![OP_0 as u8, OP_IF as u8, OP_VERIF as u8, OP_ENDIF as u8, OP_1 as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::BadOpcode(OP_VERIF as u8)));
+
+        //Stack manipulation and hashing
+        assert!(run(vec![0x51, 0x52, 0x53], vec![OP_ROT as u8, 0x51, OP_EQUALVERIFY as u8, OP_2DROP as u8, OP_DEPTH as u8, OP_0 as u8, OP_EQUAL as u8], 1, 0, 0xFFFFFFFF, VerifyFlags::NONE).is_ok());
+        let mut script: Vec<u8> = vec![OP_SHA256 as u8, 0x20];
+        script.append(&mut hash::sha256(b"abc").to_vec());
+        script.push(OP_EQUAL as u8);
+        assert!(run(vec![0x03, b'a', b'b', b'c'], script, 1, 0, 0xFFFFFFFF, flags).is_ok());
+
+        //Pushes must be minimal with MINIMALDATA
+        assert_eq!(run(vec![0x01, 0x05], vec![0x55, OP_EQUAL as u8], 1, 0, 0xFFFFFFFF, flags), Err(InterpreterErr::MinimalData()));
+        assert!(run(vec![0x01, 0x05], vec![0x55, OP_EQUAL as u8], 1, 0, 0xFFFFFFFF, VerifyFlags::NONE).is_ok());
+
+        //P2SH scriptSigs can only push data
+        let redeem_script = Script::new(vec![OP_1 as u8]);
+        assert!(run(vec![0x01, OP_1 as u8], p2sh(&redeem_script).code, 1, 0, 0xFFFFFFFF, flags).is_ok());
+        assert_eq!(run(vec![OP_NOP as u8, 0x01, OP_1 as u8], p2sh(&redeem_script).code, 1, 0, 0xFFFFFFFF, VerifyFlags::P2SH), Err(InterpreterErr::SigPushOnly()));
+    }
+
+    #[test]
+    fn timelocks() {
+        let flags = VerifyFlags::standard();
+
+        //Absolute locktime of block 500
+        let cltv: Vec<u8> = vec![0x02, 0xf4, 0x01, OP_CHECKLOCKTIMEVERIFY as u8, OP_DROP as u8, OP_1 as u8];
+        assert!(run(vec![], cltv.clone(), 1, 600, 0xFFFFFFFE, flags).is_ok());
+        assert_eq!(run(vec![], cltv.clone(), 1, 400, 0xFFFFFFFE, flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+        assert_eq!(run(vec![], cltv.clone(), 1, 600, 0xFFFFFFFF, flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+        assert_eq!(run(vec![], cltv.clone(), 1, 1600000000, 0xFFFFFFFE, flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+        assert_eq!(run(vec![], vec![OP_1NEGATE as u8, OP_CHECKLOCKTIMEVERIFY as u8], 1, 600, 0, flags), Err(InterpreterErr::NegativeLocktime()));
+
+        //Relative locktime of 10 blocks
+        let csv: Vec<u8> = vec![0x5a, OP_CHECKSEQUENCEVERIFY as u8, OP_DROP as u8, OP_1 as u8];
+        assert!(run(vec![], csv.clone(), 2, 0, 10, flags).is_ok());
+        assert_eq!(run(vec![], csv.clone(), 2, 0, 9, flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+        assert_eq!(run(vec![], csv.clone(), 1, 0, 10, flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+        assert_eq!(run(vec![], csv.clone(), 2, 0, 10 | (1 << 22), flags), Err(InterpreterErr::UnsatisfiedLocktime()));
+
+        //Without the flags the opcodes are NOPs
+        assert!(run(vec![], cltv, 1, 400, 0xFFFFFFFF, VerifyFlags::NONE).is_ok());
+        assert_eq!(run(vec![], csv, 1, 0, 0, VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS), Err(InterpreterErr::DiscourageUpgradableNops()));
+    }
+
+    #[test]
+    fn script_numbers() {
+        for n in [0, 1, -1, 127, -127, 128, -128, 255, 256, -256, 0x7fffffff, -0x7fffffff].iter() {
+            assert_eq!(decode_num(&encode_num(*n), true, 4).unwrap(), *n);
+        }
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-128), vec![0x80, 0x80]);
+
+        //Numbers with unneeded trailing zeros are only allowed without MINIMALDATA
+        assert_eq!(decode_num(&[0x05, 0x00], true, 4), Err(InterpreterErr::MinimalData()));
+        assert_eq!(decode_num(&[0x05, 0x00], false, 4).unwrap(), 5);
+        assert_eq!(decode_num(&[0x80], false, 4).unwrap(), 0);
+        assert_eq!(decode_num(&[0x01, 0x02, 0x03, 0x04, 0x05], false, 4), Err(InterpreterErr::NumOverflow()));
+
+        //Negative zero is false
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+    }
+}
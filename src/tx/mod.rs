@@ -1,9 +1,16 @@
 mod transaction;
 mod input;
 mod output;
-mod builder;
+pub(crate) mod builder;
 mod witness;
 mod script;
+mod opcodes;
+pub(crate) mod instruction;
+mod scriptbuilder;
+mod fee;
+mod address;
+mod interpreter;
+pub mod taproot;
 
 pub use transaction::Tx as Tx;
 pub use input::Input as Input;
@@ -11,8 +18,35 @@ pub use output::Output as Output;
 pub use builder::txbuilder::TxBuilder as TxBuilder;
 pub use builder::txbuilder::SigHash as SigHash;
 pub use builder::txbuilder::SigningData as SigningData;
+pub use builder::txbuilder::ScriptInput as ScriptInput;
+pub use builder::txbuilder::BuilderErr as BuilderErr;
+pub use builder::coinselect::{
+    CoinSelector,
+    Selection,
+    SelectionAlgorithm,
+    Utxo
+};
+pub use builder::rbf::SEQUENCE_RBF;
 pub use witness::Witness as Witness;
+pub use taproot::{
+    TapTreeBuilder, TaprootSpendInfo,
+    TapLeaf, ControlBlock
+};
 pub use script::{
-    ScriptCodes, Script,
-    ScriptType, ScriptErr
+    Script,
+    ScriptType, ScriptErr,
+    MAX_OP_RETURN_RELAY
+};
+pub use opcodes::ScriptCodes;
+pub use instruction::Instruction;
+pub use scriptbuilder::ScriptBuilder;
+pub use address::{
+    Address, AddressErr,
+    Payload
+};
+pub use fee::{
+    FeeRate, FeeErr
+};
+pub use interpreter::{
+    VerifyFlags, InterpreterErr
 };
\ No newline at end of file
@@ -8,11 +8,19 @@
 use crate::{
     util::serialize::{
         Serialize as SerializeTrait,
+        Deserialize,
+        Decoder,
         SerializationError
     },
     util::varint::VarInt as VarInt,
+    util::Network,
     tx::{
-        Script
+        Script,
+        ScriptType,
+        ScriptErr,
+        FeeRate,
+        Address,
+        AddressErr
     }
 };
 
@@ -40,30 +48,71 @@ impl SerializeTrait for Output {
     }
 }
 
-impl Output {
-    pub fn new(address: &str, value: u64) -> Self {
-        //Create the scriptPubKey based on the address prefix
-        let script_pub_key: Script = match address.chars().nth(0) {
-            //P2PKH Addresses
-            Some('1') | Some('m') | Some('n') => {
-                Script::p2pkh_locking(address)
-            },
-            //P2SH Addresses
-            Some('3') | Some('2') => {
-                Script::p2sh_locking(address)
-            },
-            //SegWit Addresses
-            Some('b') | Some('t') => {
-                Script::segwit_locking(address)
-            },
+impl Deserialize for Output {
+    fn deserialize(decoder: &mut Decoder) -> Result<Self, SerializationError> {
+        let value = decoder.read_u64()?;
+        let script_pub_key = Script::new(decoder.read_var_bytes()?);
 
-            _ => panic!("Invalid address detected")
-        };
+        Ok(Self {
+            value,
+            script_pub_key
+        })
+    }
+}
 
+impl Output {
+    /**
+        Create an output paying an address that must be for the network
+    */
+    pub fn new(address: &str, value: u64, network: &Network) -> Result<Self, AddressErr> {
+        Ok(Self::from_address(&Address::parse(address, network)?, value))
+    }
+
+    /**
+        Create an output paying a parsed address
+    */
+    pub fn from_address(address: &Address, value: u64) -> Self {
+        Self {
+            value,
+            script_pub_key: address.script_pub_key()
+        }
+    }
 
+    /**
+        Create an output locked by any scriptPubKey
+    */
+    pub fn from_script(script_pub_key: Script, value: u64) -> Self {
         Self {
             value,
             script_pub_key
-        } 
+        }
+    }
+
+    /**
+        Create a zero value null data output carrying the data.
+        Errors if the data is larger than the 80 bytes relayed by default.
+    */
+    pub fn op_return(data: &[u8]) -> Result<Self, ScriptErr> {
+        Ok(Self::from_script(Script::op_return(data)?, 0))
+    }
+
+    /**
+        Smallest value this output can have without being dust.
+        An output is dust if spending it at 3 sat/vB costs more than a third of its value,
+        where spending a witness program takes 67 vbytes and anything else 148 vbytes.
+    */
+    pub fn dust_limit(&self) -> u64 {
+        //Null data outputs are never spent so any value is allowed
+        if self.script_pub_key.is_op_return() {
+            return 0
+        }
+
+        let spend_size: u64 = match self.script_pub_key.determine_type() {
+            ScriptType::P2WPKH | ScriptType::P2WSH | ScriptType::P2TR => 32 + 4 + 1 + 107 / 4 + 4,
+            _ => 32 + 4 + 1 + 107 + 4
+        };
+        let output_size: u64 = self.serialize().unwrap().len() as u64;
+
+        FeeRate::from_sat_per_vb(3).fee_for_vsize(output_size + spend_size)
     }
-}
\ No newline at end of file
+}
@@ -7,18 +7,22 @@
     a multisig script.
 */
 use btc_keyaddress::key::Key;
-use btc_keyaddress::script::RedeemScript;
-use btc_keyaddress::script::WitnessProgram;
 use crate::{
+    hash,
     util::{
-        //bech32,
-        serialize::serialize_sig,
-        varint::VarInt
+        Network,
+        serialize::serialize_sig
     },
     Signature,
+    schnorrsig,
     tx::{
         SigHash,
-        SigningData
+        SigningData,
+        ScriptCodes,
+        ScriptBuilder,
+        Address,
+        AddressErr,
+        Payload
     },
     PrivKey, PubKey
 };
@@ -29,30 +33,23 @@ pub enum ScriptType {
     P2SH,
     P2WPKH,
     P2WSH,
+    P2TR,
     NonStandard
 }
 
 
-#[allow(non_camel_case_types)]
-pub enum ScriptCodes {
-    //Null Data / False
-    OP_0 = 0x00,
-    
-    //P2PKH Codes
-    OP_DUP = 0x76,
-    OP_HASH160 = 0xA9,
-    OP_EQUALVERIFY = 0x88,
-    OP_CHECKSIG = 0xAC,
-
-    //P2SH Codes
-    OP_EQUAL = 0x87
-}
-
+#[derive(Debug, Clone, PartialEq)]
 pub enum ScriptErr {
     UnknownScript(),
-    MissingScript()
+    MissingScript(),
+    InvalidPush(usize),         //Push at this position runs past the end of the script
+    InvalidAsm(String),         //ASM token that is not an opcode or hex
+    DataTooLarge(usize)         //Size of data that does not fit in a null data script
 }
 
+//Largest null data scriptPubKey that is relayed by default
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
 #[derive(Debug, Clone)]
 pub struct Script {
     pub code: Vec<u8>
@@ -74,63 +71,86 @@ impl Script {
     }
 
     /**
-        Creates a P2PKH locking script from a pub key hash address
+        Creates a P2PKH locking script from a pub key hash address for the network
     */
-    pub fn p2pkh_locking(address: &str) -> Self {
-        let mut unlock_script: Vec<u8> = vec![];
-        unlock_script.push(ScriptCodes::OP_DUP as u8);
-        unlock_script.push(ScriptCodes::OP_HASH160 as u8);
-        unlock_script.push(0x14); //Length of the PubKey Hash to follow
-        let mut address_bytes: Vec<u8> = match bs58::decode(address).into_vec() {
-            Ok(x) => {
-                x[1..x.len()-4].to_vec()
-            },
-            Err(_) => panic!("cannot decode recepient address")
-        };
-        unlock_script.append(&mut &mut address_bytes);
-        unlock_script.push(ScriptCodes::OP_EQUALVERIFY as u8);
-        unlock_script.push(ScriptCodes::OP_CHECKSIG as u8);
-        
-        Self::new(unlock_script)
+    pub fn p2pkh_locking(address: &str, network: &Network) -> Result<Self, AddressErr> {
+        match Address::parse(address, network)? {
+            x if matches!(x.payload, Payload::PubkeyHash(_)) => Ok(x.script_pub_key()),
+            _ => Err(AddressErr::WrongType())
+        }
+    }
+
+    /**
+        Create a P2SH locking script from an script hash address for the network
+    */
+    pub fn p2sh_locking(address: &str, network: &Network) -> Result<Self, AddressErr> {
+        match Address::parse(address, network)? {
+            x if matches!(x.payload, Payload::ScriptHash(_)) => Ok(x.script_pub_key()),
+            _ => Err(AddressErr::WrongType())
+        }
+    }
+
+    /**
+        Create a SegWit locking script from a Bech32 or Bech32m address for the network
+    */
+    pub fn segwit_locking(address: &str, network: &Network) -> Result<Self, AddressErr> {
+        match Address::parse(address, network)? {
+            x if matches!(x.payload, Payload::WitnessProgram(_, _)) => Ok(x.script_pub_key()),
+            _ => Err(AddressErr::WrongType())
+        }
+    }
+
+    /**
+        Get the address this scriptPubKey pays to on the network.
+        Inverse of the P2PKH, P2SH and SegWit locking scripts.
+    */
+    pub fn to_address(&self, network: &Network) -> Result<String, AddressErr> {
+        Ok(Address::from_script(self, network)?.to_string())
+    }
+
+    /**
+        Create a P2TR locking script from a tweaked output key
+    */
+    pub fn p2tr_locking(output_key: &schnorrsig::PublicKey) -> Self {
+        ScriptBuilder::new()
+            .push_opcode(ScriptCodes::OP_1)
+            .push_slice(&output_key.serialize())
+            .into_script()
     }
 
     /**
-        Create a P2SH locking script from an script hash address
+        Create a null data locking script that pushes the data after OP_RETURN.
+        Outputs locked by it can never be spent. Errors if the script is larger than the
+        83 bytes relayed by default, which leaves room for 80 bytes of data.
     */
-    pub fn p2sh_locking(address: &str) -> Self {
-        let mut locking_script: Vec<u8> = vec![];
-                locking_script.push(ScriptCodes::OP_HASH160 as u8);
-                let mut script_hash_bytes: Vec<u8> = match bs58::decode(address).into_vec() {
-                    Ok(x) => {
-                        x[1..x.len()-4].to_vec()
-                    },
-                    Err(_) => panic!("cannot decode redeeming script")
-                };
-                locking_script.append(&mut VarInt::from_usize(script_hash_bytes.len()).unwrap());
-                locking_script.append(&mut script_hash_bytes);
-                locking_script.push(ScriptCodes::OP_EQUAL as u8);
-                
-                Self::new(locking_script)
+    pub fn op_return(data: &[u8]) -> Result<Self, ScriptErr> {
+        let script: Script = ScriptBuilder::new()
+            .push_opcode(ScriptCodes::OP_RETURN)
+            .push_slice(data)
+            .into_script();
+        if script.code.len() > MAX_OP_RETURN_RELAY {
+            return Err(ScriptErr::DataTooLarge(data.len()))
+        }
+
+        Ok(script)
     }
 
     /**
-        Create a SegWit locking script from a Bech32 address
+        Returns true if the script starts with OP_RETURN and can never be spent
     */
-    pub fn segwit_locking(address: &str) -> Self {
-        let script_pub_key = WitnessProgram::from_address(address).unwrap().to_scriptpubkey().code;
-        Self::new(script_pub_key)
+    pub fn is_op_return(&self) -> bool {
+        self.code.first() == Some(&(ScriptCodes::OP_RETURN as u8))
     }
 
     /**
         Create a PubKeyHash unlocking script for P2PKH and P2WPKH inputs
     */
     pub fn pkh_unlocking(signature: &Signature, signing_key: &PrivKey, sighash: &SigHash) -> Self {
-        let mut unlocking_script: Vec<u8> = vec![];
-
         //Set values
         let ss = serialize_sig(&signature);
         let pk: PubKey = PubKey::from_priv_key(&signing_key);
         let shb = match sighash {
+            SigHash::DEFAULT => 0x00,
             SigHash::ALL => 0x01,
             SigHash::NONE => 0x02,
             SigHash::SINGLE => 0x03,
@@ -139,55 +159,53 @@ impl Script {
             SigHash::SINGLE_ANYONECANPAY => 0x83
         };
 
+        let mut sig: Vec<u8> = ss.to_vec();
+        sig.push(shb);
+
         //Push values
-        unlocking_script.push((ss.len()+1) as u8);                   //Length of Sig
-        unlocking_script.append(&mut ss.to_vec());                   //Serialized Sig
-        unlocking_script.push(shb);                                  //Sighash Byte
-        unlocking_script.push(pk.as_bytes::<33>().len() as u8);      //Length of PK
-        unlocking_script.append(&mut pk.as_bytes::<33>().to_vec());  //PK bytes
-        
-        Self::new(unlocking_script)
+        ScriptBuilder::new()
+            .push_slice(&sig)
+            .push_slice(&pk.as_bytes::<33>())
+            .into_script()
     }
 
+    /**
+        Determine the type of a scriptPubKey from its template.
+        Scripts that are too short for their template are NonStandard.
+    */
     pub fn determine_type(&self) -> ScriptType {
-        println!("{:?}", crate::util::bytes::encode_02x(&self.code));
-        match self.code[0] {
-            0x76 => ScriptType::P2PKH,
-            0xA9 => ScriptType::P2SH,
+        let len: usize = self.code.len();
+        match (self.code.first(), self.code.get(1)) {
+            (Some(0x76), _) if len == 25 => ScriptType::P2PKH,
+            (Some(0xA9), _) if len == 23 => ScriptType::P2SH,
             //Segwit Version 0
-            0x00 => {
-                //Check if P2PKH or P2WSH or non standard
-                match self.code[1] {
-                    0x14 => ScriptType::P2WPKH,
-                    0x20 => ScriptType::P2WSH,
-                    _ => ScriptType::NonStandard
-                } 
-            },
-            0x51 => { 
-                //Mistaking a multisig script with quorum '1' for taproot so differentiate by checking the second byte
-                match self.code[1] {
-                    0x21 => ScriptType::P2SH,
-                    0x20 => unimplemented!("Taproot"), //Script starting with [0x51, 0x20] is a Taproot output script
-                    _ => ScriptType::P2SH
-                }
-            }, 
+            (Some(0x00), Some(0x14)) if len == 22 => ScriptType::P2WPKH,
+            (Some(0x00), Some(0x20)) if len == 34 => ScriptType::P2WSH,
+            //Mistaking a multisig script with quorum '1' for taproot so differentiate by checking the second byte
+            (Some(0x51), Some(0x20)) if len == 34 => ScriptType::P2TR, //Script starting with [0x51, 0x20] is a Taproot output script
+            (Some(0x51), Some(_)) => ScriptType::P2SH,
             _ => ScriptType::NonStandard
         }
     }
 
     /**
-        Wrapper around btc-keyaddress lib to create M-of-N multisig locking scripts
-        to present when signing P2SH inputs.
+        Create M-of-N multisig locking scripts to present when signing P2SH inputs.
+        Panics if M is zero or greater than the number of keys, or if there are more than 16 keys.
     */
     pub fn multisig_locking(m: u8, keys: &Vec<PrivKey>) -> Self {
-        let keys = keys.iter().map(|x| PubKey::from_priv_key(x)).collect::<Vec<PubKey>>();
-        
-        let script = match btc_keyaddress::prelude::RedeemScript::multisig(m, &keys) {
-            Ok(x) => x,
-            Err(x) => panic!("{:?}", x)
-        };
+        if m == 0 || m as usize > keys.len() || keys.len() > 16 {
+            panic!("invalid {}-of-{} multisig", m, keys.len())
+        }
 
-        Self::new(script.code)
+        let mut builder = ScriptBuilder::new().push_int(m as i64);
+        for key in keys {
+            builder = builder.push_slice(&PubKey::from_priv_key(key).as_bytes::<33>());
+        }
+
+        builder
+            .push_int(keys.len() as i64)
+            .push_opcode(ScriptCodes::OP_CHECKMULTISIG)
+            .into_script()
     }
 
     /**
@@ -195,34 +213,23 @@ impl Script {
         The scriptSig is created assuming that the redeem script is a multisig script.
     */
     pub fn p2sh_multisig_unlocking(signatures: &Vec<Signature>, signing_data: &SigningData, sighash: &SigHash) -> Result<Self, ScriptErr> {
-        let mut redeem_script: Script = match signing_data.script.clone() {
+        let redeem_script: Script = match signing_data.script.clone() {
             Some(x) => x,
             None => return Err(ScriptErr::MissingScript())
         };
         
-        let mut script: Vec<u8> = vec![];
-        script.push(0x00); //Push OP_0 first with multisig redeem script due to a bug in Bitcoin core
+        //Push OP_0 first with multisig redeem script due to a bug in Bitcoin core
+        let mut script = ScriptBuilder::new().push_opcode(ScriptCodes::OP_0);
         for i in 0..signatures.len() {
             //Append each signature to the script.
             //If none are present this loop will not be entered
             let mut serialized_signature = serialize_sig(&signatures[i]).to_vec();
-            script.append(&mut VarInt::from_usize(serialized_signature.len() + 1).unwrap());
-            script.append(&mut serialized_signature);
-            script.push(sighash.clone() as u8);
+            serialized_signature.push(sighash.clone() as u8);
+            script = script.push_slice(&serialized_signature);
         }
         
         //Append the redeem script
-        //If the redeem script is too long to use push_bytes op code,
-        //use the push data opcode.
-        if redeem_script.code.len() > 0x4b {
-            script.push(0x4c); //push data 1 bytes
-        }
-        script.push(redeem_script.code.len() as u8);
-
-
-        script.append(&mut redeem_script.code);
-
-        Ok(Script::new(script))
+        Ok(script.push_slice(&redeem_script.code).into_script())
     }
 
 
@@ -230,18 +237,46 @@ impl Script {
         Create the redeem script for a P2SH nested P2WPKH address. 
     */
     pub fn p2sh_p2wpkh_redeem_script(key: &PrivKey) -> Self {
-        let code = RedeemScript::p2wpkh(&PubKey::from_priv_key(key)).code;
+        let pubkey_hash = hash::hash160(&PubKey::from_priv_key(key).as_bytes::<33>());
 
-        Self::new(code)
+        ScriptBuilder::new()
+            .push_opcode(ScriptCodes::OP_0)
+            .push_slice(&pubkey_hash)
+            .into_script()
     }
 
     /** 
         Create the redeem script for a P2SH nested P2WSH address.
     */
     pub fn p2sh_p2wsh_redeem_script(script: &Script) -> Self {
-        let code = RedeemScript::new(script.code.clone());
-        let redeem_script = RedeemScript::p2wsh(&code).code;
+        ScriptBuilder::new()
+            .push_opcode(ScriptCodes::OP_0)
+            .push_slice(&hash::sha256(&script.code))
+            .into_script()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tx::Output,
+        test_utils::key
+    };
+
+    #[test]
+    fn determine_type_of_short_scripts() {
+        let p2wpkh = Script::p2sh_p2wpkh_redeem_script(&key(1));
+        assert!(matches!(p2wpkh.determine_type(), ScriptType::P2WPKH));
 
-        Self::new(redeem_script)
+        //Truncated templates are not mistaken for standard scripts
+        let mut truncated = p2wpkh.code.clone();
+        truncated.pop();
+        assert!(matches!(Script::new(truncated).determine_type(), ScriptType::NonStandard));
+        assert!(matches!(Script::new(vec![0x76]).determine_type(), ScriptType::NonStandard));
+        assert!(matches!(Script::new(vec![0x00]).determine_type(), ScriptType::NonStandard));
+        assert!(matches!(Script::new(vec![]).determine_type(), ScriptType::NonStandard));
+
+        //Empty scripts are priced as a 9 byte output spent by a 148 byte input
+        assert_eq!(Output::from_script(Script::new(vec![]), 1000).dust_limit(), 471);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,100 @@
+/*
+    Builder for scripts.
+
+    Every push uses the smallest encoding allowed by the MINIMALDATA rule:
+    empty data is OP_0, the single bytes 0x01 to 0x10 are OP_1 to OP_16,
+    0x81 is OP_1NEGATE and anything else uses the shortest push opcode.
+*/
+use crate::tx::{
+    Script,
+    ScriptCodes,
+    interpreter::encode_num,
+    instruction::push_data
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ScriptBuilder {
+    code: Vec<u8>
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self {
+            code: vec![]
+        }
+    }
+
+    /**
+        Push an opcode
+    */
+    pub fn push_opcode(mut self, opcode: ScriptCodes) -> Self {
+        self.code.push(opcode as u8);
+        self
+    }
+
+    /**
+        Push data onto the stack with the smallest push
+    */
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        match data {
+            [] => self.code.push(ScriptCodes::OP_0 as u8),
+            [x @ 0x01..=0x10] => self.code.push(ScriptCodes::OP_1 as u8 + x - 1),
+            [0x81] => self.code.push(ScriptCodes::OP_1NEGATE as u8),
+            _ => self.code.append(&mut push_data(data))
+        }
+        self
+    }
+
+    /**
+        Push a number encoded as a script number.
+        -1 to 16 are pushed with their own opcodes.
+    */
+    pub fn push_int(self, n: i64) -> Self {
+        self.push_slice(&encode_num(n))
+    }
+
+    pub fn into_script(self) -> Script {
+        Script::new(self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_pushes() {
+        let script = ScriptBuilder::new()
+            .push_slice(&[])
+            .push_slice(&[0x05])
+            .push_slice(&[0x81])
+            .push_slice(&[0x11])
+            .push_slice(&[0x00])
+            .into_script();
+        assert_eq!(script.code, vec![0x00, 0x55, 0x4f, 0x01, 0x11, 0x01, 0x00]);
+
+        //The push opcode is picked from the length of the data
+        for (len, prefix) in [(75, vec![0x4b]), (76, vec![0x4c, 0x4c]), (255, vec![0x4c, 0xff]), (256, vec![0x4d, 0x00, 0x01]), (520, vec![0x4d, 0x08, 0x02])].iter() {
+            let script = ScriptBuilder::new().push_slice(&vec![0xab; *len]).into_script();
+            assert_eq!(&script.code[..prefix.len()], &prefix[..]);
+            assert_eq!(script.code.len(), prefix.len() + len);
+        }
+        let script = ScriptBuilder::new().push_slice(&vec![0xab; 0x10000]).into_script();
+        assert_eq!(&script.code[..5], &[0x4e, 0x00, 0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn script_numbers() {
+        let script = ScriptBuilder::new()
+            .push_int(0)
+            .push_int(-1)
+            .push_int(16)
+            .push_int(17)
+            .push_int(-2)
+            .push_int(128)
+            .push_int(500000000)
+            .push_opcode(ScriptCodes::OP_CHECKLOCKTIMEVERIFY)
+            .into_script();
+        assert_eq!(script.to_asm(), "OP_0 OP_1NEGATE OP_16 11 82 8000 0065cd1d OP_CHECKLOCKTIMEVERIFY");
+    }
+}
@@ -0,0 +1,404 @@
+/*
+    Module implementing the key tweaking, leaf hashing, script trees and control blocks
+    needed for Taproot (BIP-341)
+*/
+use crate::{
+    Secp256k1,
+    schnorrsig,
+    hash,
+    PrivKey,
+    util::{
+        varint::VarInt,
+        bech32,
+        Network
+    },
+    tx::Script
+};
+
+//Leaf version for BIP-342 tapscript
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+
+#[derive(Debug)]
+pub enum TaprootErr {
+    InvalidKey(),
+    InvalidTweak(),
+    InvalidControlBlock(),
+    TreeTooDeep()
+}
+
+/**
+    A leaf of a script tree along with the sibling hashes needed to prove
+    it is part of the tree.
+*/
+#[derive(Debug, Clone)]
+pub struct TapLeaf {
+    pub leaf_version: u8,
+    pub script: Script,
+    pub merkle_path: Vec<[u8; 32]>
+}
+
+/**
+    Builds a script tree from weighted leaf scripts.
+
+    Leaves are combined Huffman style so that leaves with higher weights
+    (more likely to be spent) are closer to the root and cheaper to spend.
+*/
+#[derive(Debug, Clone)]
+pub struct TapTreeBuilder {
+    leaves: Vec<(u32, Script)>
+}
+
+impl TapTreeBuilder {
+    pub fn new() -> Self {
+        Self {
+            leaves: vec![]
+        }
+    }
+
+    /**
+        Add a tapscript leaf with the given weight
+    */
+    pub fn add_leaf(&mut self, weight: u32, script: Script) {
+        self.leaves.push((weight, script));
+    }
+
+    /**
+        Compute the script tree and tweak the internal key with its merkle root.
+        If no leaves were added, the output can only be spent with the key path.
+    */
+    pub fn finalize(&self, internal_key: schnorrsig::PublicKey) -> Result<TaprootSpendInfo, TaprootErr> {
+        //Each node is its weight, hash and the indexes of the leaves under it
+        let mut leaves: Vec<TapLeaf> = vec![];
+        let mut nodes: Vec<(u64, [u8; 32], Vec<usize>)> = vec![];
+        for i in 0..self.leaves.len() {
+            let (weight, script) = &self.leaves[i];
+            leaves.push(TapLeaf {
+                leaf_version: TAPSCRIPT_LEAF_VERSION,
+                script: script.clone(),
+                merkle_path: vec![]
+            });
+            nodes.push((*weight as u64, tap_leaf_hash(TAPSCRIPT_LEAF_VERSION, script), vec![i]));
+        }
+
+        //Repeatedly join the two lightest nodes until only the root is left
+        while nodes.len() > 1 {
+            nodes.sort_by(|a, b| b.0.cmp(&a.0));
+            let (a_weight, a_hash, a_leaves) = nodes.pop().unwrap();
+            let (b_weight, b_hash, b_leaves) = nodes.pop().unwrap();
+
+            //Each leaf under one node needs the other node's hash to reach the root
+            for i in &a_leaves {
+                leaves[*i].merkle_path.push(b_hash);
+            }
+            for i in &b_leaves {
+                leaves[*i].merkle_path.push(a_hash);
+            }
+
+            let mut joined = a_leaves;
+            joined.extend(b_leaves);
+            nodes.push((a_weight + b_weight, tap_branch_hash(&a_hash, &b_hash), joined));
+        }
+
+        if leaves.iter().any(|x| x.merkle_path.len() > 128) {
+            return Err(TaprootErr::TreeTooDeep())
+        }
+
+        let merkle_root: Option<[u8; 32]> = nodes.pop().map(|x| x.1);
+        let (output_key, output_key_parity) = output_key(&internal_key, merkle_root)?;
+
+        Ok(TaprootSpendInfo {
+            internal_key,
+            merkle_root,
+            output_key,
+            output_key_parity,
+            leaves
+        })
+    }
+}
+
+/**
+    Everything needed to create and spend a P2TR output
+*/
+#[derive(Debug, Clone)]
+pub struct TaprootSpendInfo {
+    pub internal_key: schnorrsig::PublicKey,
+    pub merkle_root: Option<[u8; 32]>,
+    pub output_key: schnorrsig::PublicKey,
+    pub output_key_parity: bool,
+    pub leaves: Vec<TapLeaf>
+}
+
+impl TaprootSpendInfo {
+    /**
+        The P2TR locking script for the output key
+    */
+    pub fn script_pub_key(&self) -> Script {
+        Script::p2tr_locking(&self.output_key)
+    }
+
+    /**
+        The Bech32m address for the output key
+    */
+    pub fn address(&self, network: &Network) -> String {
+        let hrp = match network {
+            Network::Bitcoin => "bc",
+            Network::Testnet => "tb"
+        };
+
+        bech32::encode_segwit(hrp, 1, &self.output_key.serialize()).unwrap()
+    }
+
+    /**
+        The control block for spending the given leaf script.
+        Returns None if the script is not in the tree.
+    */
+    pub fn control_block(&self, script: &Script) -> Option<ControlBlock> {
+        let leaf = self.leaves.iter().find(|x| x.script.code == script.code)?;
+
+        Some(ControlBlock {
+            leaf_version: leaf.leaf_version,
+            output_key_parity: self.output_key_parity,
+            internal_key: self.internal_key.clone(),
+            merkle_path: leaf.merkle_path.clone()
+        })
+    }
+}
+
+/**
+    Control block revealing the internal key and merkle path of a script
+    being spent through the script path.
+*/
+#[derive(Debug, Clone)]
+pub struct ControlBlock {
+    pub leaf_version: u8,
+    pub output_key_parity: bool,
+    pub internal_key: schnorrsig::PublicKey,
+    pub merkle_path: Vec<[u8; 32]>
+}
+
+impl ControlBlock {
+    /**
+        Create the control block for spending the given script.
+        The merkle path is the list of sibling hashes from the leaf up to the root.
+    */
+    pub fn new(
+        leaf_version: u8,
+        internal_key: schnorrsig::PublicKey,
+        merkle_path: Vec<[u8; 32]>,
+        script: &Script
+    ) -> Result<Self, TaprootErr> {
+        let mut control_block = Self {
+            leaf_version,
+            output_key_parity: false,
+            internal_key,
+            merkle_path
+        };
+        let merkle_root = control_block.merkle_root(script);
+        let (_, parity) = output_key(&control_block.internal_key, Some(merkle_root))?;
+        control_block.output_key_parity = parity;
+
+        Ok(control_block)
+    }
+
+    /**
+        Parse a serialized control block
+    */
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, TaprootErr> {
+        if bytes.len() < 33 || (bytes.len() - 33) % 32 != 0 || (bytes.len() - 33) / 32 > 128 {
+            return Err(TaprootErr::InvalidControlBlock())
+        }
+        let internal_key = match schnorrsig::PublicKey::from_slice(&bytes[1..33]) {
+            Ok(x) => x,
+            Err(_) => return Err(TaprootErr::InvalidKey())
+        };
+        let merkle_path: Vec<[u8; 32]> = bytes[33..].chunks(32).map(|x| {
+            let mut node = [0; 32];
+            node.copy_from_slice(x);
+            node
+        }).collect();
+
+        Ok(Self {
+            leaf_version: bytes[0] & 0xfe,
+            output_key_parity: bytes[0] & 0x01 == 1,
+            internal_key,
+            merkle_path
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![self.leaf_version | self.output_key_parity as u8];
+        bytes.append(&mut self.internal_key.serialize().to_vec());
+        for node in &self.merkle_path {
+            bytes.append(&mut node.to_vec());
+        }
+
+        bytes
+    }
+
+    /**
+        Compute the merkle root of the script tree from the given leaf script and the merkle path
+    */
+    pub fn merkle_root(&self, script: &Script) -> [u8; 32] {
+        let mut node = tap_leaf_hash(self.leaf_version, script);
+        for sibling in &self.merkle_path {
+            node = tap_branch_hash(&node, sibling);
+        }
+
+        node
+    }
+
+    /**
+        Check that this control block commits the script to the given output key
+    */
+    pub fn verify(&self, script: &Script, output_key: &schnorrsig::PublicKey) -> bool {
+        let tweak = tap_tweak_hash(&self.internal_key, Some(self.merkle_root(script)));
+        self.internal_key.tweak_add_check(&Secp256k1::verification_only(), output_key, self.output_key_parity, tweak)
+    }
+}
+
+/**
+    Hash a leaf script of a script tree
+*/
+pub fn tap_leaf_hash(leaf_version: u8, script: &Script) -> [u8; 32] {
+    let mut data: Vec<u8> = vec![leaf_version];
+    data.append(&mut VarInt::from_usize(script.code.len()).unwrap());
+    data.append(&mut script.code.clone());
+
+    hash::tagged_hash("TapLeaf", data)
+}
+
+/**
+    Hash two nodes of a script tree together.
+    The nodes are sorted before hashing so the order they are given in does not matter.
+*/
+pub fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut data: Vec<u8> = vec![];
+    if a < b {
+        data.append(&mut a.to_vec());
+        data.append(&mut b.to_vec());
+    } else {
+        data.append(&mut b.to_vec());
+        data.append(&mut a.to_vec());
+    }
+
+    hash::tagged_hash("TapBranch", data)
+}
+
+/**
+    Create the tweak used to get the output key from the internal key.
+    The merkle root is None for outputs without a script tree (BIP-86)
+*/
+pub fn tap_tweak_hash(internal_key: &schnorrsig::PublicKey, merkle_root: Option<[u8; 32]>) -> [u8; 32] {
+    let mut data: Vec<u8> = internal_key.serialize().to_vec();
+    if let Some(x) = merkle_root {
+        data.append(&mut x.to_vec());
+    }
+
+    hash::tagged_hash("TapTweak", data)
+}
+
+/**
+    Get the x-only internal public key of a private key
+*/
+pub fn internal_key(key: &PrivKey) -> schnorrsig::PublicKey {
+    let secp = Secp256k1::signing_only();
+    let keypair = schnorrsig::KeyPair::from_secret_key(&secp, key.raw());
+    schnorrsig::PublicKey::from_keypair(&secp, &keypair)
+}
+
+/**
+    Tweak an internal key with the merkle root to get the output key.
+    Returns the output key and its parity.
+*/
+pub fn output_key(internal_key: &schnorrsig::PublicKey, merkle_root: Option<[u8; 32]>) -> Result<(schnorrsig::PublicKey, bool), TaprootErr> {
+    let tweak = tap_tweak_hash(internal_key, merkle_root);
+    let mut output_key = internal_key.clone();
+    let parity = match output_key.tweak_add_assign(&Secp256k1::verification_only(), &tweak) {
+        Ok(x) => x,
+        Err(_) => return Err(TaprootErr::InvalidTweak())
+    };
+
+    Ok((output_key, parity))
+}
+
+/**
+    Create the untweaked key pair used to sign for script path spends
+*/
+pub fn keypair(key: &PrivKey) -> schnorrsig::KeyPair {
+    schnorrsig::KeyPair::from_secret_key(&Secp256k1::signing_only(), key.raw())
+}
+
+/**
+    Tweak a private key with the merkle root so it can sign for the output key.
+*/
+pub fn tweaked_keypair(key: &PrivKey, merkle_root: Option<[u8; 32]>) -> Result<schnorrsig::KeyPair, TaprootErr> {
+    let secp = Secp256k1::new();
+    let mut keypair = schnorrsig::KeyPair::from_secret_key(&secp, key.raw());
+    let tweak = tap_tweak_hash(&schnorrsig::PublicKey::from_keypair(&secp, &keypair), merkle_root);
+
+    //The key pair is negated when needed so that it matches the even y output key
+    match keypair.tweak_add_assign(&secp, &tweak) {
+        Ok(_) => Ok(keypair),
+        Err(_) => Err(TaprootErr::InvalidTweak())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes::{
+        decode_02x,
+        encode_02x
+    };
+
+    #[test]
+    fn bip86_output_key() {
+        //BIP-86 test vector for m/86'/0'/0'/0/0
+        let internal_key = schnorrsig::PublicKey::from_slice(&decode_02x("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")).unwrap();
+        let (output_key, _) = output_key(&internal_key, None).unwrap();
+
+        assert_eq!(encode_02x(&output_key.serialize()), "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c");
+    }
+
+    #[test]
+    fn control_block_round_trip() {
+        let internal_key = schnorrsig::PublicKey::from_slice(&decode_02x("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")).unwrap();
+        let script = Script::new(decode_02x("20cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115ac"));
+        let control_block = ControlBlock::new(TAPSCRIPT_LEAF_VERSION, internal_key, vec![[1; 32], [2; 32]], &script).unwrap();
+        let (output_key, _) = output_key(&internal_key, Some(control_block.merkle_root(&script))).unwrap();
+
+        let serialized = control_block.serialize();
+        assert_eq!(serialized.len(), 33 + 64);
+        let parsed = ControlBlock::from_slice(&serialized).unwrap();
+        assert_eq!(parsed.serialize(), serialized);
+        assert!(parsed.verify(&script, &output_key));
+        assert!(!parsed.verify(&Script::new(vec![0x51]), &output_key));
+    }
+
+    #[test]
+    fn tap_tree_spend_info() {
+        let internal_key = schnorrsig::PublicKey::from_slice(&decode_02x("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")).unwrap();
+
+        //No leaves gives the BIP-86 output
+        let info = TapTreeBuilder::new().finalize(internal_key).unwrap();
+        assert!(info.merkle_root.is_none());
+        assert_eq!(info.address(&Network::Bitcoin), "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+
+        //Heavier leaves are closer to the root and every leaf can be spent
+        let scripts = vec![Script::new(vec![0x51]), Script::new(vec![0x52]), Script::new(vec![0x53])];
+        let mut builder = TapTreeBuilder::new();
+        builder.add_leaf(10, scripts[0].clone());
+        builder.add_leaf(1, scripts[1].clone());
+        builder.add_leaf(1, scripts[2].clone());
+        let info = builder.finalize(internal_key).unwrap();
+
+        assert_eq!(info.leaves[0].merkle_path.len(), 1);
+        assert_eq!(info.leaves[1].merkle_path.len(), 2);
+        for script in &scripts {
+            let control_block = info.control_block(script).unwrap();
+            assert!(control_block.verify(script, &info.output_key));
+        }
+        assert!(info.control_block(&Script::new(vec![0x54])).is_none());
+    }
+}
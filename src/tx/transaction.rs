@@ -2,6 +2,8 @@ use crate::{
     util::bytes,
     util::serialize::{
         Serialize as SerializeTrait,
+        Deserialize,
+        Decoder,
         SerializationError
     },
     util::varint::VarInt as VarInt,
@@ -12,7 +14,9 @@ use crate::{
 use super::{
     input::Input,
     output::Output,
-    Witness
+    Witness,
+    FeeRate,
+    FeeErr
 };
 
 
@@ -87,7 +91,77 @@ impl SerializeTrait for Tx {
     }
 }
 
+impl Deserialize for Tx {
+    fn deserialize(decoder: &mut Decoder) -> Result<Self, SerializationError> {
+        let version = decoder.read_u32()?;
+
+        //A zero byte where the input count should be is the BIP144 segwit marker
+        let segwit: bool = decoder.peek()? == 0x00;
+        if segwit {
+            decoder.read_u8()?;
+            let flag = decoder.read_u8()?;
+            if flag != 0x01 { return Err(SerializationError::InvalidSegwitFlag(flag)) }
+        }
+
+        let input_count = decoder.read_varint()?;
+        let mut inputs: Vec<Input> = vec![];
+        for _ in 0..input_count {
+            inputs.push(Input::deserialize(decoder)?);
+        }
+
+        let output_count = decoder.read_varint()?;
+        let mut outputs: Vec<Output> = vec![];
+        for _ in 0..output_count {
+            outputs.push(Output::deserialize(decoder)?);
+        }
+
+        //Each input has a witness stack if the tx is segwit
+        let mut witness: Option<Vec<Witness>> = None;
+        if segwit {
+            let mut witnesses: Vec<Witness> = vec![];
+            for i in 0..inputs.len() {
+                let w = Witness::deserialize(decoder)?;
+                inputs[i].segwit = w.len() > 0;
+                witnesses.push(w);
+            }
+            if !inputs.iter().any(|x| x.segwit) {
+                return Err(SerializationError::SuperfluousWitness())
+            }
+            witness = Some(witnesses);
+        }
+
+        let locktime = decoder.read_u32()?;
+
+        //Marker and flag are stored the same way as in TxBuilder::build so that
+        //re-serializing gives back the same bytes.
+        Ok(Self {
+            version,
+            flag: if segwit { Some(0x00) } else { None },
+            marker: if segwit { Some(0x01) } else { None },
+            input_count,
+            inputs,
+            output_count,
+            outputs,
+            witness,
+            locktime,
+            segwit
+        })
+    }
+}
+
 impl Tx {
+    /**
+        Parse a raw transaction from a hex string
+    */
+    pub fn from_hex(hex: &str) -> Result<Self, SerializationError> {
+        let raw = match bytes::try_decode_02x(hex.trim()) {
+            Ok(x) => x,
+            Err(_) => return Err(SerializationError::InvalidHex())
+        };
+
+        Self::from_bytes(&raw)
+    }
+
     /**
         Constructs a basic non segwit transactions with given inputs and outputs.
         Used for signing transactions
@@ -140,6 +214,56 @@ impl Tx {
         encode_02x(&bytes::reverse(&sha256d(tx_serialized).to_vec()))
     }
 
+    /**
+        Weight of the transaction in weight units.
+        Bytes outside the witness count four times and witness bytes count once.
+    */
+    pub fn weight(&self) -> u64 {
+        let base_size = self.force_remove_segwit().serialize().unwrap().len() as u64;
+        let total_size = self.serialize().unwrap().len() as u64;
+
+        base_size * 3 + total_size
+    }
+
+    /**
+        Virtual size of the transaction, the weight divided by four and rounded up
+    */
+    pub fn vsize(&self) -> u64 {
+        (self.weight() + 3) / 4
+    }
+
+    /**
+        Fee paid by the transaction given the outputs spent by each input in order
+    */
+    pub fn fee(&self, prevouts: &Vec<Output>) -> Result<u64, FeeErr> {
+        if prevouts.len() != self.inputs.len() {
+            return Err(FeeErr::PrevoutCount(self.inputs.len(), prevouts.len()))
+        }
+
+        let input_value: u64 = prevouts.iter().map(|x| x.value).sum();
+        let output_value: u64 = self.outputs.iter().map(|x| x.value).sum();
+        if output_value > input_value {
+            return Err(FeeErr::NegativeFee(input_value, output_value))
+        }
+
+        Ok(input_value - output_value)
+    }
+
+    /**
+        Fee rate paid by the transaction given the outputs spent by each input in order
+    */
+    pub fn feerate(&self, prevouts: &Vec<Output>) -> Result<FeeRate, FeeErr> {
+        Ok(FeeRate::from_fee_and_vsize(self.fee(prevouts)?, self.vsize()))
+    }
+
+    /**
+        Returns true if the transaction opts in to replace-by-fee (BIP125) by having
+        an input with a sequence below 0xFFFFFFFE
+    */
+    pub fn signals_rbf(&self) -> bool {
+        self.inputs.iter().any(|x| x.sequence < 0xFFFFFFFE)
+    }
+
     /**
         Removes the segwit marker from self.
 
@@ -152,4 +276,102 @@ impl Tx {
 
         tx
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::Script;
+    use crate::util::bytes::decode_02x;
+
+    #[test]
+    fn deserialize_legacy_tx() {
+        //Genesis block coinbase
+        let raw = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+        let tx = Tx::from_hex(raw).unwrap();
+
+        assert!(!tx.segwit);
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].vout, 0xFFFFFFFF);
+        assert_eq!(tx.outputs[0].value, 5000000000);
+        assert_eq!(encode_02x(&tx.serialize().unwrap()), raw);
+        assert_eq!(tx.get_txid(), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+    }
+
+    #[test]
+    fn deserialize_segwit_tx() {
+        //BIP143 native P2WPKH example
+        let raw = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+        let tx = Tx::from_hex(raw).unwrap();
+
+        assert!(tx.segwit);
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.outputs.len(), 2);
+        assert!(!tx.inputs[0].segwit);
+        assert!(tx.inputs[1].segwit);
+        assert_eq!(tx.witness.as_ref().unwrap()[1].len(), 2);
+        assert_eq!(tx.locktime, 0x11);
+        assert_eq!(encode_02x(&tx.serialize().unwrap()), raw);
+    }
+
+    #[test]
+    fn witness_round_trip() {
+        //Empty items and single 0x00 items are distinct and both keep their length
+        let witness = Witness::new(vec![
+            crate::tx::Script::new(vec![]),
+            crate::tx::Script::new(vec![0x00]),
+            crate::tx::Script::new(vec![0x01, 0x02])
+        ]);
+        let bytes = witness.serialize().unwrap();
+        assert_eq!(encode_02x(&bytes), "03000100020102");
+
+        let decoded = Witness::deserialize(&mut Decoder::new(&bytes)).unwrap();
+        assert_eq!(decoded.stack()[0].code, Vec::<u8>::new());
+        assert_eq!(decoded.stack()[1].code, vec![0x00]);
+        assert_eq!(encode_02x(&decoded.serialize().unwrap()), "03000100020102");
+    }
+
+    #[test]
+    fn weight_and_vsize() {
+        //Genesis block coinbase. Legacy transactions weigh four times their size
+        let raw = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+        let tx = Tx::from_hex(raw).unwrap();
+        assert_eq!(tx.weight(), 816);
+        assert_eq!(tx.vsize(), 204);
+
+        //BIP143 native P2WPKH example. 233 bytes without the witness and 343 bytes with it
+        let raw = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+        let tx = Tx::from_hex(raw).unwrap();
+        assert_eq!(tx.weight(), 1042);
+        assert_eq!(tx.vsize(), 261);
+
+        //Fee of the example is the difference between the spent amounts and the outputs
+        let prevouts = vec![
+            Output { value: 625000000, script_pub_key: Script::new(decode_02x("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac")) },
+            Output { value: 600000000, script_pub_key: Script::new(decode_02x("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1")) }
+        ];
+        assert_eq!(tx.fee(&prevouts), Ok(889210000));
+        assert_eq!(tx.feerate(&prevouts).unwrap(), FeeRate::from_sat_per_kvb(889210000 * 1000 / 261));
+
+        assert_eq!(tx.fee(&prevouts[..1].to_vec()), Err(FeeErr::PrevoutCount(2, 1)));
+        let low = vec![Output { value: 1000, ..prevouts[0].clone() }, Output { value: 1000, ..prevouts[1].clone() }];
+        assert_eq!(tx.fee(&low), Err(FeeErr::NegativeFee(2000, 335790000)));
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        //Truncated
+        assert!(Tx::from_hex("0100000001").is_err());
+        //Bad segwit flag
+        assert!(Tx::from_hex("010000000002").is_err());
+        //Bad hex
+        assert!(Tx::from_hex("01000000zz").is_err());
+        //Signs are not hex digits even where they would parse as a number
+        let raw = format!("0100000001{}00000000000000000001{}0000000000", "00".repeat(32), "00".repeat(8));
+        assert!(Tx::from_hex(&raw).is_ok());
+        assert!(matches!(Tx::from_hex(&raw.replacen("01", "+1", 1)), Err(SerializationError::InvalidHex())));
+        //Segwit marker and flag with only empty witnesses
+        let raw = format!("01000000000101{}00000000000000000001{}000000000000", "00".repeat(32), "00".repeat(8));
+        assert!(matches!(Tx::from_hex(&raw), Err(SerializationError::SuperfluousWitness())));
+    }
+}
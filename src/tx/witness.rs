@@ -2,6 +2,8 @@ use crate::{
     util::{
         serialize::{
             Serialize,
+            Deserialize,
+            Decoder,
             serialize_sig,
             SerializationError
         },
@@ -9,9 +11,11 @@ use crate::{
     },
     tx::{
         Script,
-        SigHash
+        SigHash,
+        taproot::ControlBlock
     },
     Signature,
+    schnorrsig,
     Key, PrivKey, PubKey
 };
 
@@ -38,6 +42,13 @@ impl Witness {
         self.stack.len()
     }
 
+    /**
+        Returns the items on the witness stack
+    */
+    pub fn stack(&self) -> &Vec<Script> {
+        &self.stack
+    }
+
     /**
         Create witness for P2WPKH
     */
@@ -64,7 +75,7 @@ impl Witness {
     */
     pub fn p2wsh(signatures: &Vec<Signature>, witness_script: &Script, sighash: &SigHash) -> Self {
         let mut stack_items: Vec<Script> = vec![];
-        stack_items.push(Script::new(vec![0x00]));
+        stack_items.push(Script::new(vec![])); //Empty dummy item consumed by OP_CHECKMULTISIG
         
         for i in 0..signatures.len() {
             //Append each signature to the script.
@@ -85,6 +96,61 @@ impl Witness {
             stack: stack_items
         }
     }
+
+    /**
+        Create witness for P2TR key path spends.
+        The sighash byte is left out if the sighash is DEFAULT.
+    */
+    pub fn p2tr_key_path(signature: &schnorrsig::Signature, sighash: &SigHash, annex: &Option<Vec<u8>>) -> Self {
+        let mut stack_items: Vec<Script> = vec![Script::new(Self::schnorr_sig_bytes(signature, sighash))];
+        if let Some(x) = annex {
+            stack_items.push(Script::new(x.clone()));
+        }
+
+        Self {
+            stack: stack_items
+        }
+    }
+
+    /**
+        Create witness for P2TR script path spends.
+        The script inputs (signatures and any other items) are pushed in reverse so that the first
+        is at the top of the stack when the script is run, followed by the script, control block
+        and annex if present.
+    */
+    pub fn p2tr_script_path(
+        inputs: &Vec<Vec<u8>>,
+        script: &Script,
+        control_block: &ControlBlock,
+        annex: &Option<Vec<u8>>
+    ) -> Self {
+        let mut stack_items: Vec<Script> = vec![];
+        for i in (0..inputs.len()).rev() {
+            stack_items.push(Script::new(inputs[i].clone()));
+        }
+        stack_items.push(Script::new(script.code.clone()));
+        stack_items.push(Script::new(control_block.serialize()));
+        if let Some(x) = annex {
+            stack_items.push(Script::new(x.clone()));
+        }
+
+        Self {
+            stack: stack_items
+        }
+    }
+
+    /**
+        Serialize a Schnorr signature with the sighash byte appended if it is not DEFAULT
+    */
+    pub(crate) fn schnorr_sig_bytes(signature: &schnorrsig::Signature, sighash: &SigHash) -> Vec<u8> {
+        let mut sig: Vec<u8> = signature.as_ref().to_vec();
+        match sighash {
+            SigHash::DEFAULT => { },
+            _ => sig.push(sighash.clone() as u8)
+        }
+
+        sig
+    }
 }
 
 impl Serialize for Witness {
@@ -100,12 +166,9 @@ impl Serialize for Witness {
         witness_bytes.append(&mut stack_size_varint);
         for i in 0..self.len() {
             //Length of the stack item
-            if self.stack[i].code != vec![0x00] { //Only push the length if it is not 1 and the item is not 0x00 (To prevent DUMMY sig must be 0x00 error)
-                witness_bytes.append(
-                    &mut VarInt::from_usize(self.stack[i].code.len()).unwrap()
-                );
-            }
-            
+            witness_bytes.append(
+                &mut VarInt::from_usize(self.stack[i].code.len()).unwrap()
+            );
             
             //The stack item itself
             witness_bytes.append(
@@ -115,4 +178,16 @@ impl Serialize for Witness {
 
         Ok(witness_bytes)
     }
+}
+
+impl Deserialize for Witness {
+    fn deserialize(decoder: &mut Decoder) -> Result<Self, SerializationError> {
+        let item_count = decoder.read_varint()?;
+        let mut stack_items: Vec<Script> = vec![];
+        for _ in 0..item_count {
+            stack_items.push(Script::new(decoder.read_var_bytes()?));
+        }
+
+        Ok(Self::new(stack_items))
+    }
 }
\ No newline at end of file
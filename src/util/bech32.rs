@@ -1,13 +1,222 @@
 /*
     Bech32 module
 
-    Disabled in favour of btc-keyaddress's Bech32 encoder
+    Encodes and decodes Bech32 (BIP-173) and Bech32m (BIP-350) strings and
+    SegWit addresses. Witness version 0 uses Bech32, all later versions use Bech32m.
 */
-// use crate::{
-//     WitnessProgram
-// };
 
-// pub fn decode(address: &str) -> Vec<u8> {
-//     let decoded = WitnessProgram::from_address(&address).unwrap();
-//     decoded.to_scriptpubkey()
-// }
\ No newline at end of file
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variant {
+    Bech32,
+    Bech32m
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bech32Err {
+    MixedCase(),
+    InvalidChar(char),
+    InvalidLength(usize),
+    MissingSeparator(),
+    InvalidChecksum(),
+    InvalidPadding(),
+    InvalidWitnessVersion(u8),
+    InvalidProgramLength(usize),
+    WrongVariant()
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let generator: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for v in values {
+        let b = chk >> 25;
+        chk = (chk & 0x1ffffff) << 5 ^ (*v as u32);
+        for i in 0..5 {
+            if (b >> i) & 1 == 1 {
+                chk ^= generator[i];
+            }
+        }
+    }
+
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|x| x >> 5).collect();
+    expanded.push(0);
+    expanded.append(&mut hrp.bytes().map(|x| x & 31).collect());
+
+    expanded
+}
+
+fn checksum_const(variant: &Variant) -> u32 {
+    match variant {
+        Variant::Bech32 => BECH32_CONST,
+        Variant::Bech32m => BECH32M_CONST
+    }
+}
+
+/**
+    Regroup bits, eg. from 8 bit bytes to 5 bit words
+*/
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Err> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max: u32 = (1 << to) - 1;
+    let mut converted: Vec<u8> = vec![];
+    for value in data {
+        let v = *value as u32;
+        if v >> from != 0 { return Err(Bech32Err::InvalidPadding()) }
+        acc = (acc << from) | v;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return Err(Bech32Err::InvalidPadding())
+    }
+
+    Ok(converted)
+}
+
+/**
+    Encode 5 bit data with the human readable part
+*/
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.append(&mut vec![0; 6]);
+    let checksum = polymod(&values) ^ checksum_const(&variant);
+
+    let mut encoded = String::from(hrp);
+    encoded.push('1');
+    for d in data {
+        encoded.push(CHARSET[*d as usize] as char);
+    }
+    for i in 0..6 {
+        encoded.push(CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+
+    encoded
+}
+
+/**
+    Decode a Bech32 or Bech32m string into the human readable part, 5 bit data and variant
+*/
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant), Bech32Err> {
+    if s.len() > 90 || s.len() < 8 { return Err(Bech32Err::InvalidLength(s.len())) }
+    if s.to_lowercase() != s && s.to_uppercase() != s { return Err(Bech32Err::MixedCase()) }
+    let s = s.to_lowercase();
+
+    let pos = match s.rfind('1') {
+        Some(x) if x > 0 && x + 7 <= s.len() => x,
+        _ => return Err(Bech32Err::MissingSeparator())
+    };
+    let hrp = &s[..pos];
+    if let Some(c) = hrp.chars().find(|c| (*c as u32) < 33 || (*c as u32) > 126) {
+        return Err(Bech32Err::InvalidChar(c))
+    }
+
+    let mut data: Vec<u8> = vec![];
+    for c in s[pos+1..].chars() {
+        match CHARSET.iter().position(|x| *x as char == c) {
+            Some(x) => data.push(x as u8),
+            None => return Err(Bech32Err::InvalidChar(c))
+        }
+    }
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    let variant = match polymod(&values) {
+        BECH32_CONST => Variant::Bech32,
+        BECH32M_CONST => Variant::Bech32m,
+        _ => return Err(Bech32Err::InvalidChecksum())
+    };
+    data.truncate(data.len() - 6);
+
+    Ok((hrp.to_string(), data, variant))
+}
+
+/**
+    Encode a witness program as a SegWit address
+*/
+pub fn encode_segwit(hrp: &str, version: u8, program: &[u8]) -> Result<String, Bech32Err> {
+    if version > 16 { return Err(Bech32Err::InvalidWitnessVersion(version)) }
+    if program.len() < 2 || program.len() > 40 { return Err(Bech32Err::InvalidProgramLength(program.len())) }
+
+    let mut data: Vec<u8> = vec![version];
+    data.append(&mut convert_bits(program, 8, 5, true)?);
+    let variant = if version == 0 { Variant::Bech32 } else { Variant::Bech32m };
+
+    Ok(encode(hrp, &data, variant))
+}
+
+/**
+    Decode a SegWit address into the human readable part, witness version and witness program
+*/
+pub fn decode_segwit(address: &str) -> Result<(String, u8, Vec<u8>), Bech32Err> {
+    let (hrp, data, variant) = decode(address)?;
+    if data.is_empty() { return Err(Bech32Err::InvalidLength(0)) }
+
+    let version = data[0];
+    if version > 16 { return Err(Bech32Err::InvalidWitnessVersion(version)) }
+    let program = convert_bits(&data[1..], 5, 8, false)?;
+    if program.len() < 2 || program.len() > 40 { return Err(Bech32Err::InvalidProgramLength(program.len())) }
+    if version == 0 && program.len() != 20 && program.len() != 32 { return Err(Bech32Err::InvalidProgramLength(program.len())) }
+
+    //Version 0 must use Bech32 and all other versions must use Bech32m
+    match (version, variant) {
+        (0, Variant::Bech32) => { },
+        (0, Variant::Bech32m) => return Err(Bech32Err::WrongVariant()),
+        (_, Variant::Bech32) => return Err(Bech32Err::WrongVariant()),
+        (_, Variant::Bech32m) => { }
+    }
+
+    Ok((hrp, version, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes::encode_02x;
+
+    #[test]
+    fn segwit_v0_address() {
+        //BIP-173 test vector
+        let (hrp, version, program) = decode_segwit("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap();
+        assert_eq!(hrp, "bc");
+        assert_eq!(version, 0);
+        assert_eq!(encode_02x(&program), "751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(encode_segwit("bc", 0, &program).unwrap(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+    }
+
+    #[test]
+    fn segwit_v1_address() {
+        //BIP-86 test vector
+        let address = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+        let (_, version, program) = decode_segwit(address).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(encode_02x(&program), "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c");
+        assert_eq!(encode_segwit("bc", 1, &program).unwrap(), address);
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        //Bad checksum
+        assert!(decode_segwit("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5").is_err());
+        //Version 1 with Bech32 checksum (BIP-350)
+        assert_eq!(decode_segwit("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"), Err(Bech32Err::WrongVariant()));
+        //Mixed case
+        assert!(decode_segwit("bc1qW508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").is_err());
+    }
+}
@@ -21,6 +21,20 @@ pub fn decode_02x(hex: &str) -> Vec<u8> {
         .collect::<Vec<u8>>()
 }
 
+/*
+    Decodes hex strings into a byte vector, returning an error
+    instead of panicking on bad input. Only hex digits are accepted,
+    from_str_radix alone would also take a leading '+'.
+*/
+pub fn try_decode_02x(hex: &str) -> Result<Vec<u8>, ()> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) { return Err(()) }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| ()))
+        .collect::<Result<Vec<u8>, ()>>()
+}
+
 /*
     Encodes byte slices into hex string
 */
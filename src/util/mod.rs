@@ -3,6 +3,6 @@ use btc_keyaddress::prelude::*;
 pub mod bytes;
 pub mod serialize;
 pub mod varint;
-//pub mod bech32;
+pub mod bech32;
 
 pub use btc_keyaddress::prelude::Network as Network;
\ No newline at end of file
@@ -1,6 +1,9 @@
 /*
     Serialization module that implements the serialization of 
     various parts of a transaction.
+
+    Deserialization is done by reading from a Decoder, which keeps
+    track of how far into the byte array it has read.
 */
 use crate::{
     Signature, SerializedSignature
@@ -9,13 +12,132 @@ use crate::{
 #[derive(Debug)]
 pub enum SerializationError {
     DeserializationErr(),
-    VarIntErr(usize)
+    VarIntErr(usize),
+    UnexpectedEnd(usize),       //Ran out of bytes at the given position
+    TrailingBytes(usize),       //Bytes left over after decoding
+    NonMinimalVarInt(usize),    //VarInt at the given position is not canonically encoded
+    InvalidSegwitFlag(u8),
+    InvalidHex(),
+    SuperfluousWitness()        //Segwit marker and flag present but every witness is empty
 }
 
 pub trait Serialize {
     fn serialize(&self) -> Result<Vec<u8>, SerializationError>;
 }
 
+pub trait Deserialize: Sized {
+    fn deserialize(decoder: &mut Decoder) -> Result<Self, SerializationError>;
+
+    /**
+        Deserialize from a byte slice, erroring if any bytes are left over.
+    */
+    fn from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        let mut decoder = Decoder::new(bytes);
+        let decoded = Self::deserialize(&mut decoder)?;
+        if !decoder.is_empty() { return Err(SerializationError::TrailingBytes(decoder.position())) }
+
+        Ok(decoded)
+    }
+}
+
+/**
+    Cursor over a byte slice used when deserializing.
+*/
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            position: 0
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    /**
+        Look at the next byte without consuming it
+    */
+    pub fn peek(&self) -> Result<u8, SerializationError> {
+        match self.bytes.get(self.position) {
+            Some(x) => Ok(*x),
+            None => Err(SerializationError::UnexpectedEnd(self.position))
+        }
+    }
+
+    /**
+        Read the next n bytes
+    */
+    pub fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, SerializationError> {
+        if self.bytes.len() - self.position < n {
+            return Err(SerializationError::UnexpectedEnd(self.bytes.len()))
+        }
+        let read = self.bytes[self.position..self.position + n].to_vec();
+        self.position += n;
+
+        Ok(read)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SerializationError> {
+        let byte = self.peek()?;
+        self.position += 1;
+
+        Ok(byte)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SerializationError> {
+        let mut x = [0; 2];
+        x.copy_from_slice(&self.read_bytes(2)?);
+        Ok(u16::from_le_bytes(x))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SerializationError> {
+        let mut x = [0; 4];
+        x.copy_from_slice(&self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(x))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SerializationError> {
+        let mut x = [0; 8];
+        x.copy_from_slice(&self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(x))
+    }
+
+    /**
+        Read a VarInt.
+        Returns an error if the VarInt is not minimally encoded.
+    */
+    pub fn read_varint(&mut self) -> Result<u64, SerializationError> {
+        let start = self.position;
+        let (n, min) = match self.read_u8()? {
+            0xfd => (self.read_u16()? as u64, 0xfd),
+            0xfe => (self.read_u32()? as u64, 0x10000),
+            0xff => (self.read_u64()?, 0x100000000),
+            x => (x as u64, 0)
+        };
+        if n < min { return Err(SerializationError::NonMinimalVarInt(start)) }
+
+        Ok(n)
+    }
+
+    /**
+        Read a VarInt length prefix followed by that many bytes
+    */
+    pub fn read_var_bytes(&mut self) -> Result<Vec<u8>, SerializationError> {
+        let len = self.read_varint()? as usize;
+        self.read_bytes(len)
+    }
+}
+
 /**
     Serialize a signature.
     The serialization does not contain the sighash flag.
@@ -33,4 +155,4 @@ pub fn deserialize_sig(sig: &SerializedSignature) -> Result<Signature, Serializa
         Ok(x) => Ok(x),
         Err(_) => Err(SerializationError::DeserializationErr())
     }
-}
\ No newline at end of file
+}